@@ -69,7 +69,7 @@ impl<'a, T> NodeChain<'a, T> {
         self.nodes[0]
     }
 
-    fn pop(&mut self) {
+    pub fn pop(&mut self) {
         self.level_count -= 1;
     }
 
@@ -1,4 +1,4 @@
-use r53::{LabelSlice, Name, NameRelation};
+use r53::{LabelSequence, LabelSlice, Name, NameRelation};
 use std::{marker::PhantomData, mem};
 
 use crate::domaintree::flag::Color;
@@ -344,6 +344,82 @@ impl<T> RBTree<T> {
         result
     }
 
+    //node before the given one in dns order, chain holds the upper nodes
+    //of the given node, and is updated to the ones of the returned node.
+    //Node is after its left subtree and before its down tree
+    pub fn previous_node<'a>(
+        &'a self,
+        node: NodePtr<T>,
+        chain: &mut NodeChain<'a, T>,
+    ) -> NodePtr<T> {
+        if !node.left().is_null() {
+            return self.last_in_subtree(node.left(), chain);
+        }
+
+        let mut current = node;
+        while !current.is_subtree_root() {
+            let parent = current.parent();
+            if parent.right() == current {
+                return self.last_under_node(parent, chain);
+            }
+            current = parent;
+        }
+
+        //first node in the level is after the upper node
+        if chain.level_count == 0 {
+            return NodePtr::null();
+        }
+        let upper = chain.top();
+        chain.pop();
+        upper
+    }
+
+    //node before the name which doesn't exist in tree, chain should be
+    //the one used to find the name
+    pub fn previous_node_of_missing<'a>(&'a self, chain: &mut NodeChain<'a, T>) -> NodePtr<T> {
+        let node = chain.last_compared;
+        if node.is_null() {
+            return NodePtr::null();
+        }
+        if chain.last_compared_result.relation == NameRelation::SubDomain {
+            //name is under the node which has no down tree
+            chain.pop();
+            node
+        } else if chain.last_compared_result.order < 0 {
+            self.previous_node(node, chain)
+        } else {
+            self.last_under_node(node, chain)
+        }
+    }
+
+    //the last node in dns order
+    pub fn last_node<'a>(&'a self, chain: &mut NodeChain<'a, T>) -> NodePtr<T> {
+        if self.root.is_null() {
+            return NodePtr::null();
+        }
+        self.last_in_subtree(self.root, chain)
+    }
+
+    fn last_in_subtree<'a>(
+        &'a self,
+        mut node: NodePtr<T>,
+        chain: &mut NodeChain<'a, T>,
+    ) -> NodePtr<T> {
+        while !node.right().is_null() {
+            node = node.right();
+        }
+        self.last_under_node(node, chain)
+    }
+
+    fn last_under_node<'a>(&'a self, node: NodePtr<T>, chain: &mut NodeChain<'a, T>) -> NodePtr<T> {
+        if node.down().is_null() {
+            node
+        } else {
+            chain.push(node);
+            self.last_in_subtree(node.down(), chain)
+        }
+    }
+
     pub fn remove_node(&mut self, mut node: NodePtr<T>) -> Option<T> {
         let old_value = node.set_value(None);
 
@@ -489,6 +565,40 @@ impl<T> RBTree<T> {
         self.clear_recurse(root);
     }
 
+    //visit the nodes with value in dns order, which means node is
+    //visited after its left subtree and before its down tree
+    pub fn for_each<F: FnMut(Name, &T)>(&self, mut f: F) {
+        let mut uppers = Vec::new();
+        self.for_each_helper(self.root, &mut uppers, &mut f);
+    }
+
+    fn for_each_helper<F: FnMut(Name, &T)>(
+        &self,
+        node: NodePtr<T>,
+        uppers: &mut Vec<LabelSequence>,
+        f: &mut F,
+    ) {
+        if node.is_null() {
+            return;
+        }
+
+        self.for_each_helper(node.left(), uppers, f);
+        if let Some(value) = node.get_value() {
+            let names = uppers.iter().rev().collect::<Vec<&LabelSequence>>();
+            let name = node
+                .get_name()
+                .concat_all(&names)
+                .expect("get absolute name failed");
+            f(name, value);
+        }
+        if !node.down().is_null() {
+            uppers.push(node.get_name().clone());
+            self.for_each_helper(node.down(), uppers, f);
+            uppers.pop();
+        }
+        self.for_each_helper(node.right(), uppers, f);
+    }
+
     pub fn dump(&self, depth: usize) {
         indent(depth);
         println!("tree has {} node(s)", self.len);
@@ -589,6 +699,73 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_for_each() {
+        let data = sample_names();
+        let tree = build_tree(&data);
+        let mut names = Vec::new();
+        tree.for_each(|name, _| names.push(name));
+        let expected = vec![
+            "a",
+            "b",
+            "c",
+            "x.d.e.f",
+            "o.w.y.d.e.f",
+            "p.w.y.d.e.f",
+            "q.w.y.d.e.f",
+            "z.d.e.f",
+            "j.z.d.e.f",
+            "g.h",
+            "i.g.h",
+        ]
+        .iter()
+        .map(|n| Name::new(n).unwrap())
+        .collect::<Vec<Name>>();
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn test_previous_node() {
+        let data = sample_names();
+        let tree = build_tree(&data);
+        let mut expected = Vec::new();
+        tree.for_each(|name, _| expected.push(name));
+        expected.reverse();
+
+        let mut names = Vec::new();
+        let mut chain = NodeChain::new(&tree);
+        let mut node = tree.last_node(&mut chain);
+        while !node.is_null() {
+            if node.get_value().is_some() {
+                names.push(chain.get_absolute_name(node.get_name()));
+            }
+            node = tree.previous_node(node, &mut chain);
+        }
+        assert_eq!(names, expected);
+
+        for (missing, previous) in vec![
+            ("0.a", Some("a")),
+            ("aa", Some("a")),
+            ("y.d.e.f", Some("x.d.e.f")),
+            ("k.z.d.e.f", Some("j.z.d.e.f")),
+            ("zz", Some("i.g.h")),
+            ("0", None),
+        ] {
+            let mut chain = NodeChain::new(&tree);
+            let result = tree.find_node(&Name::new(missing).unwrap(), &mut chain);
+            assert_ne!(result.flag, FindResultFlag::ExacatMatch);
+            let node = tree.previous_node_of_missing(&mut chain);
+            assert_eq!(
+                previous.map(|n| Name::new(n).unwrap()),
+                if node.is_null() {
+                    None
+                } else {
+                    Some(chain.get_absolute_name(node.get_name()))
+                }
+            );
+        }
+    }
+
     #[test]
     fn test_delete() {
         let data = sample_names();
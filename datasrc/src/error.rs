@@ -31,4 +31,10 @@ pub enum DataSrcError {
 
     #[fail(display = "zone has no ns record")]
     ZoneShortOfNS,
+
+    #[fail(display = "rrsig {} has no covered type", _0)]
+    InvalidRRSIG(String),
+
+    #[fail(display = "{} line {} column {}: {}", _0, _1, _2, _3)]
+    InvalidZoneFile(String, usize, usize, String),
 }
@@ -1,8 +1,11 @@
 pub mod domaintree;
 
 mod error;
+pub mod master_file;
 pub mod memory_zone;
+mod nsec;
 mod rdataset;
+mod wire;
 pub mod zone;
 mod zone_loader;
 
@@ -14,6 +17,7 @@ pub use domaintree::{
     node_chain::NodeChain,
     tree::{FindResult, FindResultFlag, RBTree},
 };
+pub use master_file::MasterFileParser;
 pub use memory_zone::{MemoryZone, MemoryZoneFindResult};
 pub use zone::{FindOption, FindResultType, ZoneFinder, ZoneUpdater};
-pub use zone_loader::load_zone;
+pub use zone_loader::{load_zone, load_zone_file};
@@ -0,0 +1,980 @@
+use crate::error::DataSrcError;
+use failure::Result;
+use r53::{Name, RRset};
+use std::{
+    fs, mem,
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+//one $GENERATE could expand to at most this many records
+const MAX_GENERATED_RECORDS: u64 = 65536;
+
+type ParseResult<T> = std::result::Result<T, SyntaxError>;
+
+struct SyntaxError {
+    line: usize,
+    column: usize,
+    reason: String,
+}
+
+struct Source {
+    lexer: Lexer,
+    //None for zone content which isn't read from file
+    path: Option<PathBuf>,
+    //origin and owner of the including file, restored after the
+    //included file is finished
+    saved: Option<(String, Option<String>)>,
+}
+
+struct Generator {
+    //lhs [ttl] [class] type rhs
+    template: Vec<Token>,
+    //None after the iterator overflows
+    current: Option<u64>,
+    stop: u64,
+    step: u64,
+}
+
+//rfc1035 master file parser, every entry is resolved into an absolute
+//one line rrset text like "www.example.org. 300 IN A 1.1.1.1", which
+//is then parsed by r53
+pub struct MasterFileParser {
+    zone_name: String,
+    //the last one is the file which is being parsed
+    sources: Vec<Source>,
+    generator: Option<Generator>,
+    origin: String,
+    default_ttl: Option<u32>,
+    last_ttl: Option<u32>,
+    last_owner: Option<String>,
+    last_class: String,
+    //position of the entry which is parsed last
+    line: usize,
+    column: usize,
+}
+
+impl MasterFileParser {
+    pub fn new(origin: &Name, content: &str) -> Self {
+        let origin = absolute_name_str(&origin.to_string());
+        MasterFileParser {
+            zone_name: origin.clone(),
+            sources: vec![Source {
+                lexer: Lexer::new(content.to_string()),
+                path: None,
+                saved: None,
+            }],
+            generator: None,
+            origin,
+            default_ttl: None,
+            last_ttl: None,
+            last_owner: None,
+            last_class: "IN".to_string(),
+            line: 0,
+            column: 0,
+        }
+    }
+
+    //included files are resolved relative to the including file
+    pub fn from_file<P: AsRef<Path>>(origin: &Name, path: P) -> Result<Self> {
+        let path = fs::canonicalize(path)?;
+        let content = fs::read_to_string(&path)?;
+        let mut parser = MasterFileParser::new(origin, "");
+        parser.sources = vec![Source {
+            lexer: Lexer::new(content),
+            path: Some(path),
+            saved: None,
+        }];
+        Ok(parser)
+    }
+
+    //report error at the entry which is parsed last
+    pub fn entry_error(&self, reason: String) -> failure::Error {
+        self.to_error(SyntaxError {
+            line: self.line,
+            column: self.column,
+            reason,
+        })
+    }
+
+    fn to_error(&self, e: SyntaxError) -> failure::Error {
+        let source = match self.sources.last().unwrap().path {
+            Some(ref path) => path.display().to_string(),
+            None => self.zone_name.clone(),
+        };
+        DataSrcError::InvalidZoneFile(source, e.line, e.column, e.reason).into()
+    }
+
+    fn next_rrset(&mut self) -> ParseResult<Option<RRset>> {
+        loop {
+            if let Some(entry) = self.next_generated_entry()? {
+                self.line = entry.tokens[0].line;
+                self.column = entry.tokens[0].column;
+                return self.parse_rr(entry).map(Some);
+            }
+
+            let entry = match self.sources.last_mut().unwrap().lexer.next_entry()? {
+                Some(entry) => entry,
+                None => {
+                    if self.sources.len() == 1 {
+                        return Ok(None);
+                    }
+                    let source = self.sources.pop().unwrap();
+                    let (origin, last_owner) = source.saved.unwrap();
+                    self.origin = origin;
+                    self.last_owner = last_owner;
+                    continue;
+                }
+            };
+            let first = &entry.tokens[0];
+            self.line = first.line;
+            self.column = first.column;
+            if entry.has_owner && first.text.starts_with('$') {
+                self.handle_directive(entry)?;
+            } else {
+                return self.parse_rr(entry).map(Some);
+            }
+        }
+    }
+
+    fn handle_directive(&mut self, entry: Entry) -> ParseResult<()> {
+        let directive = &entry.tokens[0];
+        match directive.text.to_ascii_uppercase().as_ref() {
+            "$ORIGIN" => {
+                let name = entry.expect_args(directive, 1, 1, 0..0)?;
+                self.origin = self.make_name(&name[0])?;
+            }
+            "$TTL" => {
+                let ttl = entry.expect_args(directive, 1, 1, 0..0)?;
+                self.default_ttl = Some(parse_ttl(&ttl[0])?);
+            }
+            "$INCLUDE" => {
+                let args = entry.expect_args(directive, 1, 2, 0..1)?;
+                self.include(&args[0], args.get(1))?;
+            }
+            "$GENERATE" => {
+                let args = entry.expect_args(directive, 4, 6, 1..6)?;
+                let (start, stop, step) = parse_range(&args[0])?;
+                self.generator = Some(Generator {
+                    template: args[1..].to_vec(),
+                    current: Some(start),
+                    stop,
+                    step,
+                });
+            }
+            _ => {
+                return Err(directive.error(format!("unknown directive {}", directive.text)));
+            }
+        }
+        Ok(())
+    }
+
+    fn include(&mut self, file: &Token, origin: Option<&Token>) -> ParseResult<()> {
+        if self.sources.len() > MAX_INCLUDE_DEPTH {
+            return Err(file.error("too many nested includes".to_string()));
+        }
+
+        //zone content like the one from dynamic update isn't allowed
+        //to read local files
+        let file_name = file.text.trim_matches('"');
+        let path = match self.sources.last().unwrap().path {
+            Some(ref current) => current.parent().unwrap().join(file_name),
+            None => return Err(file.error("include without zone file".to_string())),
+        };
+        let include_failed =
+            |e: std::io::Error| file.error(format!("include {} failed: {}", file_name, e));
+        let path = fs::canonicalize(path).map_err(include_failed)?;
+        if self
+            .sources
+            .iter()
+            .any(|source| source.path.as_ref() == Some(&path))
+        {
+            return Err(file.error(format!("include {} get loop", file_name)));
+        }
+        let content = fs::read_to_string(&path).map_err(include_failed)?;
+
+        let origin = match origin {
+            Some(origin) => self.make_name(origin)?,
+            None => self.origin.clone(),
+        };
+        let saved = (
+            mem::replace(&mut self.origin, origin),
+            self.last_owner.take(),
+        );
+        self.sources.push(Source {
+            lexer: Lexer::new(content),
+            path: Some(path),
+            saved: Some(saved),
+        });
+        Ok(())
+    }
+
+    fn next_generated_entry(&mut self) -> ParseResult<Option<Entry>> {
+        let finished = match self.generator {
+            Some(ref generator) => generator.current.map_or(true, |v| v > generator.stop),
+            None => return Ok(None),
+        };
+        if finished {
+            self.generator = None;
+            return Ok(None);
+        }
+        let generator = self.generator.as_mut().unwrap();
+        let value = generator.current.unwrap();
+        generator.current = value.checked_add(generator.step);
+
+        let rhs = generator.template.len() - 1;
+        let tokens = generator
+            .template
+            .iter()
+            .enumerate()
+            .map(|(i, token)| {
+                if i == 0 || i == rhs {
+                    substitute(token, value)
+                } else {
+                    Ok(token.clone())
+                }
+            })
+            .collect::<ParseResult<Vec<Token>>>()?;
+        Ok(Some(Entry {
+            tokens,
+            has_owner: true,
+        }))
+    }
+
+    fn parse_rr(&mut self, entry: Entry) -> ParseResult<RRset> {
+        let mut tokens = entry.tokens.iter().peekable();
+        let owner = if entry.has_owner {
+            let owner = self.make_name(tokens.next().unwrap())?;
+            self.last_owner = Some(owner.clone());
+            owner
+        } else {
+            match self.last_owner {
+                Some(ref owner) => owner.clone(),
+                None => return Err(entry.tokens[0].error("no previous owner name".to_string())),
+            }
+        };
+
+        //ttl and class are optional and could be in any order
+        let mut ttl = None;
+        let mut class = None;
+        while let Some(token) = tokens.peek() {
+            if ttl.is_none() && token.text.starts_with(|c: char| c.is_ascii_digit()) {
+                ttl = Some(parse_ttl(token)?);
+            } else if class.is_none() && is_class(&token.text) {
+                class = Some(token.text.to_ascii_uppercase());
+            } else {
+                break;
+            }
+            tokens.next();
+        }
+        let typ = match tokens.next() {
+            Some(token) => token,
+            None => return Err(entry.last_token().error("missing rr type".to_string())),
+        };
+        let typ_str = typ.text.to_ascii_uppercase();
+
+        let mut rdata = tokens
+            .map(|token| token.text.clone())
+            .collect::<Vec<String>>();
+        if rdata.is_empty() {
+            return Err(typ.error("missing rdata".to_string()));
+        }
+        for &i in name_fields(&typ_str) {
+            if let Some(name) = rdata.get(i) {
+                let name = self.make_name(&typ.with_text(name.clone()))?;
+                rdata[i] = name;
+            }
+        }
+        if typ_str == "SOA" {
+            //refresh, retry, expire and minimum could use ttl units
+            for field in rdata.iter_mut().skip(3).take(4) {
+                *field = parse_ttl(&typ.with_text(field.clone()))?.to_string();
+            }
+        }
+
+        let ttl = match ttl.or(self.default_ttl).or(self.last_ttl) {
+            Some(ttl) => ttl,
+            //without $TTL, bind uses soa minimum as the default ttl
+            None if typ_str == "SOA" && rdata.len() == 7 => rdata[6]
+                .parse()
+                .map_err(|_| typ.error("invalid soa minimum".to_string()))?,
+            None => return Err(typ.error("no ttl specified".to_string())),
+        };
+        self.last_ttl = Some(ttl);
+        let class = class.unwrap_or_else(|| self.last_class.clone());
+        self.last_class = class.clone();
+
+        let rrset_str = format!(
+            "{} {} {} {} {}",
+            owner,
+            ttl,
+            class,
+            typ_str,
+            rdata.join(" ")
+        );
+        RRset::from_str(&rrset_str).map_err(|e| typ.error(e.to_string()))
+    }
+
+    fn make_name(&self, token: &Token) -> ParseResult<String> {
+        let name = if token.text == "@" {
+            self.origin.clone()
+        } else if token.text.ends_with('.') && !token.text.ends_with("\\.") {
+            token.text.clone()
+        } else if self.origin == "." {
+            format!("{}.", token.text)
+        } else {
+            format!("{}.{}", token.text, self.origin)
+        };
+        Name::new(name.as_ref()).map_err(|e| token.error(e.to_string()))?;
+        Ok(name)
+    }
+}
+
+impl Iterator for MasterFileParser {
+    type Item = Result<RRset>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_rrset() {
+            Ok(rrset) => rrset.map(Ok),
+            Err(e) => Some(Err(self.to_error(e))),
+        }
+    }
+}
+
+fn absolute_name_str(name: &str) -> String {
+    if name.ends_with('.') {
+        name.to_string()
+    } else {
+        format!("{}.", name)
+    }
+}
+
+fn is_class(s: &str) -> bool {
+    match s.to_ascii_uppercase().as_ref() {
+        "IN" | "CH" | "HS" | "CS" | "NONE" | "ANY" => true,
+        _ => false,
+    }
+}
+
+//index of the rdata fields which are domain names
+fn name_fields(typ: &str) -> &'static [usize] {
+    match typ {
+        "NS" | "CNAME" | "PTR" | "DNAME" => &[0],
+        "MX" => &[1],
+        "SRV" => &[3],
+        "SOA" => &[0, 1],
+        _ => &[],
+    }
+}
+
+//ttl is either seconds or with units like 1w2d3h4m5s
+fn parse_ttl(token: &Token) -> ParseResult<u32> {
+    let invalid = || token.error(format!("invalid ttl {}", token.text));
+    if let Ok(ttl) = token.text.parse::<u32>() {
+        return Ok(ttl);
+    }
+
+    let mut ttl: u32 = 0;
+    let mut value: Option<u32> = None;
+    for c in token.text.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            value = Some(
+                value
+                    .unwrap_or(0)
+                    .checked_mul(10)
+                    .and_then(|v| v.checked_add(digit))
+                    .ok_or_else(invalid)?,
+            );
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            'w' => 604_800,
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        let seconds = value.take().ok_or_else(invalid)?.checked_mul(unit);
+        ttl = seconds
+            .and_then(|seconds| ttl.checked_add(seconds))
+            .ok_or_else(invalid)?;
+    }
+    if value.is_some() {
+        return Err(invalid());
+    }
+    Ok(ttl)
+}
+
+//generate range is start-stop[/step]
+fn parse_range(token: &Token) -> ParseResult<(u64, u64, u64)> {
+    let invalid = || token.error(format!("invalid range {}", token.text));
+    let mut range_and_step = token.text.splitn(2, '/');
+    let range = range_and_step.next().unwrap();
+    let step = match range_and_step.next() {
+        Some(step) => step.parse::<u64>().map_err(|_| invalid())?,
+        None => 1,
+    };
+    let mut bounds = range.splitn(2, '-');
+    let start = bounds
+        .next()
+        .unwrap()
+        .parse::<u64>()
+        .map_err(|_| invalid())?;
+    let stop = match bounds.next() {
+        Some(stop) => stop.parse::<u64>().map_err(|_| invalid())?,
+        None => return Err(invalid()),
+    };
+    if start > stop || step == 0 {
+        return Err(invalid());
+    }
+    if (stop - start) / step >= MAX_GENERATED_RECORDS {
+        return Err(token.error(format!("range {} has too many records", token.text)));
+    }
+    Ok((start, stop, step))
+}
+
+//replace $ with the iterator, ${offset[,width[,base]]} changes the
+//format of it, and \$ is a literal $
+fn substitute(token: &Token, value: u64) -> ParseResult<Token> {
+    let mut text = String::new();
+    let mut chars = token.text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('$') => text.push('$'),
+                Some(c) => {
+                    text.push('\\');
+                    text.push(c);
+                }
+                None => text.push('\\'),
+            },
+            '$' => {
+                if chars.peek() != Some(&'{') {
+                    text.push_str(&value.to_string());
+                    continue;
+                }
+                chars.next();
+                let mut modifier = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => modifier.push(c),
+                        None => return Err(token.error("unterminated modifier".to_string())),
+                    }
+                }
+                text.push_str(&format_iterator(token, &modifier, value)?);
+            }
+            c => text.push(c),
+        }
+    }
+    Ok(token.with_text(text))
+}
+
+fn format_iterator(token: &Token, modifier: &str, value: u64) -> ParseResult<String> {
+    let invalid = || token.error(format!("invalid modifier {}", modifier));
+    let fields = modifier.split(',').collect::<Vec<&str>>();
+    if fields.len() > 3 {
+        return Err(invalid());
+    }
+    let offset = fields[0].parse::<i64>().map_err(|_| invalid())?;
+    let width = match fields.get(1) {
+        Some(width) => width.parse::<usize>().map_err(|_| invalid())?,
+        None => 0,
+    };
+    let value = if offset >= 0 {
+        value.checked_add(offset as u64)
+    } else {
+        value.checked_sub(offset.wrapping_neg() as u64)
+    }
+    .ok_or_else(invalid)?;
+    match fields.get(2).cloned().unwrap_or("d") {
+        "d" => Ok(format!("{:0width$}", value, width = width)),
+        "o" => Ok(format!("{:0width$o}", value, width = width)),
+        "x" => Ok(format!("{:0width$x}", value, width = width)),
+        "X" => Ok(format!("{:0width$X}", value, width = width)),
+        "n" => Ok(nibbles(value, width, false)),
+        "N" => Ok(nibbles(value, width, true)),
+        _ => Err(invalid()),
+    }
+}
+
+//reversed nibbles like 4.3.2.1 for 0x1234, used by ip6.arpa zone,
+//same as bind, width counts the dots
+fn nibbles(mut value: u64, mut width: usize, uppercase: bool) -> String {
+    let mut s = String::new();
+    loop {
+        let c = std::char::from_digit((value & 0xf) as u32, 16).unwrap();
+        s.push(if uppercase { c.to_ascii_uppercase() } else { c });
+        width = width.saturating_sub(1);
+        value >>= 4;
+        if value == 0 && width == 0 {
+            return s;
+        }
+        s.push('.');
+        width = width.saturating_sub(1);
+    }
+}
+
+#[derive(Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+    quoted: bool,
+}
+
+impl Token {
+    fn error(&self, reason: String) -> SyntaxError {
+        SyntaxError {
+            line: self.line,
+            column: self.column,
+            reason,
+        }
+    }
+
+    fn with_text(&self, text: String) -> Token {
+        Token {
+            text,
+            line: self.line,
+            column: self.column,
+            quoted: false,
+        }
+    }
+}
+
+//one logical line, which may span multiple lines with parentheses
+struct Entry {
+    tokens: Vec<Token>,
+    //entry which starts with blank uses the previous owner
+    has_owner: bool,
+}
+
+impl Entry {
+    fn last_token(&self) -> &Token {
+        self.tokens.last().unwrap()
+    }
+
+    //only the arguments in quotable, like file name of $INCLUDE and rr
+    //template of $GENERATE, could be quoted
+    fn expect_args(
+        &self,
+        directive: &Token,
+        min: usize,
+        max: usize,
+        quotable: Range<usize>,
+    ) -> ParseResult<&[Token]> {
+        let args = &self.tokens[1..];
+        if args.len() < min || args.len() > max {
+            return Err(
+                directive.error(format!("{} has wrong number of arguments", directive.text))
+            );
+        }
+        match args
+            .iter()
+            .enumerate()
+            .find(|(i, arg)| arg.quoted && !quotable.contains(i))
+        {
+            Some((_, arg)) => {
+                Err(arg.error(format!("{} argument can't be quoted", directive.text)))
+            }
+            None => Ok(args),
+        }
+    }
+}
+
+struct Lexer {
+    content: String,
+    pos: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Lexer {
+    fn new(content: String) -> Self {
+        Lexer {
+            content,
+            pos: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.content[self.pos..].chars().next()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, reason: &str) -> SyntaxError {
+        SyntaxError {
+            line: self.line,
+            column: self.column,
+            reason: reason.to_string(),
+        }
+    }
+
+    fn next_entry(&mut self) -> ParseResult<Option<Entry>> {
+        loop {
+            let has_owner = match self.peek() {
+                Some(c) => !c.is_whitespace() && c != ';',
+                None => return Ok(None),
+            };
+            let tokens = self.read_line()?;
+            if !tokens.is_empty() {
+                return Ok(Some(Entry { tokens, has_owner }));
+            }
+        }
+    }
+
+    fn read_line(&mut self) -> ParseResult<Vec<Token>> {
+        let mut tokens = Vec::new();
+        let mut paren_start: Option<(usize, usize)> = None;
+        while let Some(c) = self.peek() {
+            match c {
+                '\n' => {
+                    self.next_char();
+                    if paren_start.is_none() {
+                        return Ok(tokens);
+                    }
+                }
+                ';' => {
+                    while let Some(c) = self.peek() {
+                        if c == '\n' {
+                            break;
+                        }
+                        self.next_char();
+                    }
+                }
+                '(' => {
+                    if paren_start.is_some() {
+                        return Err(self.error("nested parentheses"));
+                    }
+                    paren_start = Some((self.line, self.column));
+                    self.next_char();
+                }
+                ')' => {
+                    if paren_start.take().is_none() {
+                        return Err(self.error("unbalanced parentheses"));
+                    }
+                    self.next_char();
+                }
+                '"' => tokens.push(self.read_quoted()?),
+                c if c.is_whitespace() => {
+                    self.next_char();
+                }
+                _ => tokens.push(self.read_word()?),
+            }
+        }
+
+        if let Some((line, column)) = paren_start {
+            return Err(SyntaxError {
+                line,
+                column,
+                reason: "unbalanced parentheses".to_string(),
+            });
+        }
+        Ok(tokens)
+    }
+
+    fn read_word(&mut self) -> ParseResult<Token> {
+        let (line, column) = (self.line, self.column);
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == ';' || c == '(' || c == ')' || c == '"' {
+                break;
+            }
+            self.next_char();
+            text.push(c);
+            if c == '\\' {
+                match self.next_char() {
+                    Some(c) => text.push(c),
+                    None => return Err(self.error("escape at end of file")),
+                }
+            }
+        }
+        Ok(Token {
+            text,
+            line,
+            column,
+            quoted: false,
+        })
+    }
+
+    //quotes are kept, since they are part of character string in rdata
+    fn read_quoted(&mut self) -> ParseResult<Token> {
+        let (line, column) = (self.line, self.column);
+        let mut text = String::new();
+        text.push(self.next_char().unwrap());
+        loop {
+            match self.next_char() {
+                Some('"') => {
+                    text.push('"');
+                    break;
+                }
+                Some('\\') => {
+                    text.push('\\');
+                    match self.next_char() {
+                        Some(c) => text.push(c),
+                        None => break,
+                    }
+                }
+                Some('\n') | None => {
+                    return Err(SyntaxError {
+                        line,
+                        column,
+                        reason: "unterminated quoted string".to_string(),
+                    });
+                }
+                Some(c) => text.push(c),
+            }
+        }
+        Ok(Token {
+            text,
+            line,
+            column,
+            quoted: true,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r53::RRType;
+    use std::{env, process};
+
+    fn parse(origin: &str, content: &str) -> Result<Vec<RRset>> {
+        MasterFileParser::new(&Name::new(origin).unwrap(), content).collect()
+    }
+
+    fn assert_error_at(content: &str, line: usize, column: usize) {
+        let err = parse("example.org", content).unwrap_err();
+        match err.downcast::<DataSrcError>() {
+            Ok(DataSrcError::InvalidZoneFile(_, l, c, _)) => assert_eq!((l, c), (line, column)),
+            Ok(e) => panic!("unexpected error {}", e),
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+
+    fn assert_rrsets_eq(rrsets: &[RRset], expected: &[&str]) {
+        assert_eq!(rrsets.len(), expected.len());
+        for (rrset, expected) in rrsets.iter().zip(expected.iter()) {
+            let expected = RRset::from_str(expected).unwrap();
+            assert!(rrset.name.eq(&expected.name));
+            assert_eq!(rrset.typ, expected.typ);
+            assert_eq!(rrset.ttl.0, expected.ttl.0);
+            assert_eq!(rrset.rdatas, expected.rdatas);
+        }
+    }
+
+    fn token(text: &str) -> Token {
+        Token {
+            text: text.to_string(),
+            line: 1,
+            column: 1,
+            quoted: false,
+        }
+    }
+
+    #[test]
+    fn test_parse_ttl() {
+        assert_eq!(parse_ttl(&token("3600")).ok(), Some(3600));
+        assert_eq!(parse_ttl(&token("1h")).ok(), Some(3600));
+        assert_eq!(parse_ttl(&token("1w2d3h4m5s")).ok(), Some(788_645));
+        assert_eq!(parse_ttl(&token("1D")).ok(), Some(86400));
+        assert!(parse_ttl(&token("1x")).is_err());
+        assert!(parse_ttl(&token("h")).is_err());
+        assert!(parse_ttl(&token("1h30")).is_err());
+        assert!(parse_ttl(&token("99999999999")).is_err());
+    }
+
+    #[test]
+    fn test_parse_bind_zone() {
+        let content = r#"
+$ORIGIN example.org.
+$TTL 1h ; default ttl
+@   IN  SOA ns1 hostmaster (
+            2019080101 ; serial
+            1d         ; refresh
+            2h         ; retry
+            4w         ; expire
+            1h )       ; minimum
+        IN  NS  ns1
+        IN  NS  ns2.example.net.
+        MX  10 mail
+ns1     A   192.0.2.1
+        AAAA 2001:db8::1
+www 300 IN  CNAME @
+txt     TXT "hello world" "a;b(c)"
+$ORIGIN sub.example.org.
+host    IN 60 A 192.0.2.2
+"#;
+        let rrsets = parse("example.org", content).unwrap();
+        assert_rrsets_eq(
+            &rrsets,
+            &[
+                "example.org. 3600 IN SOA ns1.example.org. hostmaster.example.org. 2019080101 86400 7200 2419200 3600",
+                "example.org. 3600 IN NS ns1.example.org.",
+                "example.org. 3600 IN NS ns2.example.net.",
+                "example.org. 3600 IN MX 10 mail.example.org.",
+                "ns1.example.org. 3600 IN A 192.0.2.1",
+                "ns1.example.org. 3600 IN AAAA 2001:db8::1",
+                "www.example.org. 300 IN CNAME example.org.",
+                "txt.example.org. 3600 IN TXT \"hello world\" \"a;b(c)\"",
+                "host.sub.example.org. 60 IN A 192.0.2.2",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_default_ttl() {
+        //without $TTL, soa minimum is used, then the last explicit ttl
+        let content =
+            "@ IN SOA ns1 hostmaster 1 2 3 4 5\n  NS ns1\nns1 20 A 1.1.1.1\nns2 A 1.1.1.2\n";
+        let rrsets = parse("example.org", content).unwrap();
+        let ttls = rrsets.iter().map(|rrset| rrset.ttl.0).collect::<Vec<u32>>();
+        assert_eq!(ttls, vec![5, 5, 20, 20]);
+        assert_eq!(rrsets[1].typ, RRType::NS);
+
+        assert_error_at("www A 1.1.1.1", 1, 5);
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        assert_error_at("$TTL 1h\n  A 1.1.1.1", 2, 3);
+        assert_error_at("$TTL 1h\nwww A 1.1.1.1 (\n", 2, 15);
+        assert_error_at("$TTL 1h\nwww A 1.1.1.1 )", 2, 15);
+        assert_error_at("$TTL 1h\nwww TXT \"abc\n", 2, 9);
+        assert_error_at("$TTL 1x", 1, 6);
+        assert_error_at("$TTL 1h\n$UNKNOWN 1", 2, 1);
+        assert_error_at("$TTL 1h\n\nwww A 1.1.1.1.1", 3, 5);
+        assert_error_at("$TTL 1h\nwww IN", 2, 5);
+        assert_error_at("$TTL 1h\n$INCLUDE /etc/hosts", 2, 10);
+        assert_error_at("$TTL \"1h\"", 1, 6);
+        assert_error_at("$ORIGIN \"example.org.\"", 1, 9);
+        assert_error_at("$GENERATE \"1-2\" host$ A 1.1.1.$", 1, 11);
+    }
+
+    #[test]
+    fn test_format_iterator() {
+        let t = token("");
+        assert_eq!(format_iterator(&t, "0", 5).ok(), Some("5".to_string()));
+        assert_eq!(format_iterator(&t, "-1,3", 5).ok(), Some("004".to_string()));
+        assert_eq!(
+            format_iterator(&t, "0,2,x", 255).ok(),
+            Some("ff".to_string())
+        );
+        assert_eq!(
+            format_iterator(&t, "0,0,X", 255).ok(),
+            Some("FF".to_string())
+        );
+        assert_eq!(format_iterator(&t, "0,0,o", 8).ok(), Some("10".to_string()));
+        assert_eq!(
+            format_iterator(&t, "0,0,n", 0x1234).ok(),
+            Some("4.3.2.1".to_string())
+        );
+        assert_eq!(
+            format_iterator(&t, "0,5,N", 0xa).ok(),
+            Some("A.0.0".to_string())
+        );
+        assert!(format_iterator(&t, "-6", 5).is_err());
+        assert!(format_iterator(&t, "0,0,z", 5).is_err());
+
+        assert_eq!(substitute(&token("host-$"), 3).ok().unwrap().text, "host-3");
+        assert_eq!(
+            substitute(&token("${10,3}.\\$"), 3).ok().unwrap().text,
+            "013.$"
+        );
+        assert!(substitute(&token("${1"), 3).is_err());
+    }
+
+    #[test]
+    fn test_generate() {
+        let content = "$TTL 300\n$GENERATE 1-5/2 host-$ A 192.0.2.$\n$GENERATE 1-2 $ 60 IN PTR host-${0,2}.example.net.\n";
+        let rrsets = parse("2.0.192.in-addr.arpa", content).unwrap();
+        assert_rrsets_eq(
+            &rrsets,
+            &[
+                "host-1.2.0.192.in-addr.arpa. 300 IN A 192.0.2.1",
+                "host-3.2.0.192.in-addr.arpa. 300 IN A 192.0.2.3",
+                "host-5.2.0.192.in-addr.arpa. 300 IN A 192.0.2.5",
+                "1.2.0.192.in-addr.arpa. 60 IN PTR host-01.example.net.",
+                "2.2.0.192.in-addr.arpa. 60 IN PTR host-02.example.net.",
+            ],
+        );
+
+        assert_error_at("$TTL 300\n$GENERATE 5-1 $ A 1.1.1.$", 2, 11);
+        assert_error_at("$TTL 300\n$GENERATE 1-5 $ A", 2, 1);
+        //generated records are validated one by one
+        assert_error_at("$TTL 300\n$GENERATE 1-300 $ A 1.1.1.$", 2, 19);
+        assert_error_at("$TTL 300\n$GENERATE 0-65536 $ A 1.1.1.1", 2, 11);
+
+        //iterator stops before it overflows
+        let content =
+            "$TTL 300\n$GENERATE 18446744073709551614-18446744073709551615/2 $ A 1.1.1.1\n";
+        let rrsets = parse("example.org", content).unwrap();
+        assert_rrsets_eq(
+            &rrsets,
+            &["18446744073709551614.example.org. 300 IN A 1.1.1.1"],
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = env::temp_dir().join(format!("vanguard2_master_file_test_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(
+            dir.join("zone"),
+            "$TTL 300\n@ NS ns\n$INCLUDE sub/hosts\n$INCLUDE sub/hosts child\nns A 1.1.1.1\n",
+        )
+        .unwrap();
+        fs::write(dir.join("sub/hosts"), "www A 1.1.1.2\n  AAAA ::1\n").unwrap();
+        let origin = Name::new("example.org").unwrap();
+        let rrsets = MasterFileParser::from_file(&origin, dir.join("zone"))
+            .unwrap()
+            .collect::<Result<Vec<RRset>>>()
+            .unwrap();
+        assert_rrsets_eq(
+            &rrsets,
+            &[
+                "example.org. 300 IN NS ns.example.org.",
+                "www.example.org. 300 IN A 1.1.1.2",
+                "www.example.org. 300 IN AAAA ::1",
+                "www.child.example.org. 300 IN A 1.1.1.2",
+                "www.child.example.org. 300 IN AAAA ::1",
+                "ns.example.org. 300 IN A 1.1.1.1",
+            ],
+        );
+
+        fs::write(dir.join("sub/hosts"), "$INCLUDE ../zone\n").unwrap();
+        let err = MasterFileParser::from_file(&origin, dir.join("zone"))
+            .unwrap()
+            .collect::<Result<Vec<RRset>>>()
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("line 1 column 10: include ../zone get loop"));
+
+        fs::write(dir.join("sub/hosts"), "www A 1.1.1.1.1\n").unwrap();
+        let err = MasterFileParser::from_file(&origin, dir.join("zone"))
+            .unwrap()
+            .collect::<Result<Vec<RRset>>>()
+            .unwrap_err();
+        assert!(err.to_string().contains("hosts line 1 column 5"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
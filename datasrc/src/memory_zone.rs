@@ -4,6 +4,7 @@ use crate::domaintree::{
     tree::{FindResultFlag, RBTree},
 };
 use crate::error::DataSrcError;
+use crate::nsec::Nsec3Param;
 use crate::rdataset::Rdataset;
 use crate::zone::{FindOption, FindResult, FindResultType, ZoneFinder, ZoneUpdater};
 use failure::Result;
@@ -33,6 +34,160 @@ impl MemoryZone {
             data,
         }
     }
+
+    //all the rrsets in dns order, the apex rrsets come first
+    pub fn get_all_rrsets(&self) -> Vec<RRset> {
+        let mut rrsets = Vec::new();
+        self.data.for_each(|name, rdataset| {
+            rrsets.append(&mut rdataset.get_all_rrsets(&name));
+        });
+        rrsets
+    }
+
+    //rrsets owned by the name, empty if name doesn't exist
+    pub fn get_rrsets(&self, name: &Name) -> Vec<RRset> {
+        let find_result = self.data.find(name);
+        if find_result.flag != FindResultFlag::ExacatMatch {
+            return Vec::new();
+        }
+        find_result
+            .get_value()
+            .map(|rdataset| rdataset.get_all_rrsets(name))
+            .unwrap_or_default()
+    }
+
+    //nsec owned by the nearest name before the given one in dns order,
+    //the last nsec in zone precedes the first name
+    pub fn get_previous_nsec(&self, name: &Name) -> Option<RRset> {
+        self.previous_rrset(name, RRType::NSEC, true)
+    }
+}
+
+//zone is copied by adding its rrsets into a new one, which is used to copy
+//on write when the zone is shared with zone transfer
+impl Clone for MemoryZone {
+    fn clone(&self) -> Self {
+        let mut zone = MemoryZone::new(self.origin.clone());
+        for rrset in self.get_all_rrsets() {
+            zone.add_rrset(rrset).expect("rrset of zone is valid");
+        }
+        zone
+    }
+}
+
+enum DenialType {
+    Nsec,
+    Nsec3(Nsec3Param),
+}
+
+impl MemoryZone {
+    //zone with nsec3param at apex uses nsec3, unsigned zone has neither,
+    //no proof is given if the hash algorithm or iterations isn't supported
+    fn get_denial_type(&self) -> Option<DenialType> {
+        let rdataset = self.root_node.get_value().as_ref()?;
+        if let Some(param) = rdataset.get_rrset(&self.origin, RRType::NSEC3PARAM) {
+            return param
+                .rdatas
+                .iter()
+                .find_map(Nsec3Param::from_rdata)
+                .map(DenialType::Nsec3);
+        }
+        rdataset
+            .get_rrset(&self.origin, RRType::NSEC)
+            .map(|_| DenialType::Nsec)
+    }
+
+    fn get_rrset(&self, name: &Name, typ: RRType) -> Option<RRset> {
+        let find_result = self.data.find(name);
+        if find_result.flag != FindResultFlag::ExacatMatch {
+            return None;
+        }
+        find_result.node.get_value().as_ref()?.get_rrset(name, typ)
+    }
+
+    //empty non-terminal exists in tree without value, or is part of the
+    //relative name of a node
+    fn name_exists(&self, name: &Name) -> bool {
+        let mut chain = NodeChain::new(&self.data);
+        let result = self.data.find_node(name, &mut chain);
+        result.flag == FindResultFlag::ExacatMatch
+            || chain.last_compared_result.relation == NameRelation::SuperDomain
+    }
+
+    fn closest_encloser(&self, name: &Name) -> Name {
+        let mut current = name.clone();
+        while !current.eq(&self.origin) {
+            current = current.parent(1).expect("name isn't under origin");
+            if self.name_exists(&current) {
+                break;
+            }
+        }
+        current
+    }
+
+    //rrset owned by the nearest name before the given one in dns order,
+    //nsec3 chain is a loop, so search restarts from the last name
+    fn previous_rrset(&self, name: &Name, typ: RRType, wrap: bool) -> Option<RRset> {
+        let mut chain = NodeChain::new(&self.data);
+        let result = self.data.find_node(name, &mut chain);
+        let node = if result.flag == FindResultFlag::ExacatMatch {
+            chain.pop();
+            self.data.previous_node(result.node, &mut chain)
+        } else {
+            self.data.previous_node_of_missing(&mut chain)
+        };
+        if let Some(rrset) = self.search_backward(node, &mut chain, typ) {
+            return Some(rrset);
+        }
+        if !wrap {
+            return None;
+        }
+        let mut chain = NodeChain::new(&self.data);
+        let node = self.data.last_node(&mut chain);
+        self.search_backward(node, &mut chain, typ)
+    }
+
+    fn search_backward<'a>(
+        &'a self,
+        mut node: NodePtr<Rdataset>,
+        chain: &mut NodeChain<'a, Rdataset>,
+        typ: RRType,
+    ) -> Option<RRset> {
+        while !node.is_null() {
+            if let Some(rdataset) = node.get_value().as_ref() {
+                let name = chain.get_absolute_name(node.get_name());
+                if let Some(rrset) = rdataset.get_rrset(&name, typ) {
+                    return Some(rrset);
+                }
+            }
+            node = self.data.previous_node(node, chain);
+        }
+        None
+    }
+
+    fn matching_nsec3(&self, param: &Nsec3Param, name: &Name) -> Option<RRset> {
+        self.get_rrset(&param.hashed_name(name, &self.origin), RRType::NSEC3)
+    }
+
+    fn covering_nsec3(&self, param: &Nsec3Param, name: &Name) -> Option<RRset> {
+        self.previous_rrset(&param.hashed_name(name, &self.origin), RRType::NSEC3, true)
+    }
+
+    //nsec3 matches the closest encloser and nsec3 covers the next closer
+    //name, the closest encloser is returned with the proof
+    fn closest_encloser_proof(&self, param: &Nsec3Param, name: &Name) -> (Name, Vec<RRset>) {
+        let mut next_closer = name.clone();
+        while !next_closer.eq(&self.origin) {
+            let parent = next_closer.parent(1).expect("name isn't under origin");
+            if let Some(nsec3) = self.matching_nsec3(param, &parent) {
+                let mut proof = vec![nsec3];
+                proof.extend(self.covering_nsec3(param, &next_closer));
+                return (parent, proof);
+            }
+            next_closer = parent;
+        }
+        (self.origin.clone(), Vec::new())
+    }
 }
 
 impl ZoneUpdater for MemoryZone {
@@ -189,6 +344,145 @@ impl<'a> MemoryZoneFindResult<'a> {
             rrset: None,
         }
     }
+
+    //all the rrsets of the matched node, which is the answer of any query
+    pub fn get_all_rrsets(&self) -> Vec<RRset> {
+        if self.node.is_null() {
+            return Vec::new();
+        }
+        match (self.rrset.as_ref(), self.node.get_value().as_ref()) {
+            (Some(rrset), Some(rdataset)) => rdataset.get_all_rrsets(&rrset.name),
+            _ => Vec::new(),
+        }
+    }
+
+    //signatures of the rrset in the zone, rrset synthesized from wildcard
+    //uses the signatures of the wildcard
+    pub fn get_signatures(&self, rrset: &RRset) -> Option<RRset> {
+        let result = self.zone.data.find(&rrset.name);
+        let node = if result.flag == FindResultFlag::ExacatMatch {
+            result.node
+        } else {
+            self.node
+        };
+        if node.is_null() {
+            return None;
+        }
+        node.get_value()
+            .as_ref()?
+            .get_signatures(&rrset.name, rrset.typ)
+    }
+
+    //ds at the zone cut of the delegation, which is owned by this zone
+    pub fn get_delegation_ds(&self) -> Option<RRset> {
+        if self.typ != FindResultType::Delegation || self.node.is_null() {
+            return None;
+        }
+        let ns = self.rrset.as_ref()?;
+        self.node
+            .get_value()
+            .as_ref()?
+            .get_rrset(&ns.name, RRType::DS)
+    }
+
+    //nsec or nsec3 records proving the name or type doesn't exist, or the
+    //answer synthesized from wildcard is the only match, rfc4035 and rfc5155
+    pub fn get_denial_proof(&self, name: &Name) -> Vec<RRset> {
+        let zone = self.zone;
+        let denial = match zone.get_denial_type() {
+            Some(denial) => denial,
+            None => return Vec::new(),
+        };
+        let mut proof = Vec::new();
+        match (self.typ, &denial) {
+            (FindResultType::Delegation, _) => {
+                if self.get_delegation_ds().is_some() {
+                    return proof;
+                }
+                let cut = &self.rrset.as_ref().unwrap().name;
+                match &denial {
+                    DenialType::Nsec => proof.extend(zone.get_rrset(cut, RRType::NSEC)),
+                    DenialType::Nsec3(param) => match zone.matching_nsec3(param, cut) {
+                        Some(nsec3) => proof.push(nsec3),
+                        //delegation is in an opt-out span
+                        None => proof = zone.closest_encloser_proof(param, cut).1,
+                    },
+                }
+            }
+            (FindResultType::NXDomain, DenialType::Nsec) => {
+                let wildcard = wildcard_name(&zone.closest_encloser(name));
+                proof.extend(zone.previous_rrset(name, RRType::NSEC, false));
+                proof.extend(zone.previous_rrset(&wildcard, RRType::NSEC, false));
+            }
+            (FindResultType::NXDomain, DenialType::Nsec3(param)) => {
+                let (closest_encloser, ce_proof) = zone.closest_encloser_proof(param, name);
+                proof = ce_proof;
+                proof.extend(zone.covering_nsec3(param, &wildcard_name(&closest_encloser)));
+            }
+            (FindResultType::NXRRset, DenialType::Nsec) => {
+                if zone.name_exists(name) {
+                    //empty non-terminal has no nsec
+                    proof.extend(
+                        zone.get_rrset(name, RRType::NSEC)
+                            .or_else(|| zone.previous_rrset(name, RRType::NSEC, false)),
+                    );
+                } else {
+                    let wildcard = wildcard_name(&zone.closest_encloser(name));
+                    proof.extend(zone.previous_rrset(name, RRType::NSEC, false));
+                    proof.extend(zone.get_rrset(&wildcard, RRType::NSEC));
+                }
+            }
+            (FindResultType::NXRRset, DenialType::Nsec3(param)) => {
+                if zone.name_exists(name) {
+                    proof.extend(zone.matching_nsec3(param, name));
+                } else {
+                    let (closest_encloser, ce_proof) = zone.closest_encloser_proof(param, name);
+                    proof = ce_proof;
+                    proof.extend(zone.matching_nsec3(param, &wildcard_name(&closest_encloser)));
+                }
+            }
+            (_, DenialType::Nsec) => {
+                if !zone.name_exists(name) {
+                    proof.extend(zone.previous_rrset(name, RRType::NSEC, false));
+                }
+            }
+            (_, DenialType::Nsec3(param)) => {
+                if !zone.name_exists(name) {
+                    let closest_encloser = zone.closest_encloser(name);
+                    let next_closer = next_closer_name(name, &closest_encloser);
+                    proof.extend(zone.covering_nsec3(param, &next_closer));
+                }
+            }
+        }
+
+        proof.into_iter().fold(Vec::new(), |mut rrsets, rrset| {
+            if !rrsets
+                .iter()
+                .any(|r: &RRset| r.name.eq(&rrset.name) && r.typ == rrset.typ)
+            {
+                rrsets.push(rrset);
+            }
+            rrsets
+        })
+    }
+}
+
+fn wildcard_name(closest_encloser: &Name) -> Name {
+    Name::new(&format!("*.{}", closest_encloser)).expect("wildcard name is invalid")
+}
+
+//ancestor of the name which is one label longer than the closest encloser
+fn next_closer_name(name: &Name, closest_encloser: &Name) -> Name {
+    let mut next_closer = name.clone();
+    loop {
+        let parent = next_closer
+            .parent(1)
+            .expect("name isn't under closest encloser");
+        if parent.eq(closest_encloser) {
+            return next_closer;
+        }
+        next_closer = parent;
+    }
 }
 
 impl<'a> FindResult for MemoryZoneFindResult<'a> {
@@ -284,6 +578,23 @@ impl<'a> FindResult for MemoryZoneFindResult<'a> {
     }
 }
 
+//any query matches all the rrsets of the node, the first one is set as the
+//representative, all of them could be got from the result
+fn find_any<'a>(
+    mut find_result: MemoryZoneFindResult<'a>,
+    rdataset: &Rdataset,
+    name: &Name,
+) -> MemoryZoneFindResult<'a> {
+    match rdataset.get_all_rrsets(name).into_iter().next() {
+        Some(rrset) => {
+            find_result.typ = FindResultType::Success;
+            find_result.rrset = Some(rrset);
+        }
+        None => find_result.typ = FindResultType::NXRRset,
+    }
+    find_result
+}
+
 struct FindState {
     zone_cut: NodePtr<Rdataset>,
     rrset: Option<RRset>,
@@ -359,13 +670,19 @@ impl<'a> ZoneFinder<'a> for MemoryZone {
                         .get_value()
                         .as_ref()
                         .expect("wildcard domain is empty");
+                    if typ == RRType::ANY {
+                        find_result.node = result.node;
+                        return find_any(find_result, rdataset, name);
+                    }
                     if let Some(rrset) = rdataset.get_rrset(name, typ) {
+                        find_result.node = result.node;
                         find_result.rrset = Some(rrset);
                         find_result.typ = FindResultType::Success;
                         return find_result;
                     }
                     if let Some(mut cname) = rdataset.get_rrset(name, RRType::CNAME) {
                         cname.name = name.clone();
+                        find_result.node = result.node;
                         find_result.rrset = Some(cname);
                         find_result.typ = FindResultType::CName;
                         return find_result;
@@ -387,7 +704,8 @@ impl<'a> ZoneFinder<'a> for MemoryZone {
                 }
 
                 find_result.node = result.node;
-                if !name.eq(&self.origin) {
+                //ds at zone cut belongs to the parent zone, rfc4035
+                if !name.eq(&self.origin) && typ != RRType::DS {
                     if let Some(ns) = result
                         .node
                         .get_value()
@@ -401,6 +719,10 @@ impl<'a> ZoneFinder<'a> for MemoryZone {
                     }
                 }
 
+                if typ == RRType::ANY {
+                    return find_any(find_result, result.node.get_value().as_ref().unwrap(), name);
+                }
+
                 if let Some(rrset) = result
                     .node
                     .get_value()
@@ -1,6 +1,7 @@
 use crate::memory_zone::MemoryZone;
+use crate::nsec::Nsec3Param;
 use crate::zone::{FindOption, FindResult, FindResultType, ZoneFinder, ZoneUpdater};
-use r53::{Name, RRType, RRset};
+use r53::{Name, RData, RRType, RRset};
 use std::str::FromStr;
 
 fn default_zone() -> Vec<&'static str> {
@@ -376,3 +377,351 @@ fn test_empty_node() {
     );
     assert_eq!(result.typ, FindResultType::NXDomain);
 }
+
+#[test]
+fn test_get_all_rrsets() {
+    let zone = build_zone("example.org", default_zone());
+    let rrsets = zone.get_all_rrsets();
+    assert_eq!(rrsets.len(), default_zone().len());
+    assert!(rrsets[0].name.eq(&Name::new("example.org.").unwrap()));
+    assert_eq!(rrsets[0].typ, RRType::SOA);
+    assert!(rrsets
+        .iter()
+        .all(|rrset| rrset.name.is_subdomain(&Name::new("example.org.").unwrap())));
+}
+
+#[test]
+fn test_clone_zone() {
+    let mut zone = build_zone("example.org", default_zone());
+    let copy = zone.clone();
+    zone.delete_domain(&Name::new("ns.example.org.").unwrap())
+        .unwrap();
+    assert_eq!(
+        copy.get_all_rrsets(),
+        build_zone("example.org", default_zone()).get_all_rrsets()
+    );
+    assert!(copy
+        .get_rrsets(&Name::new("ns.example.org.").unwrap())
+        .iter()
+        .any(|rrset| rrset.typ == RRType::AAAA));
+}
+
+#[test]
+fn test_get_rrsets() {
+    let zone = build_zone("example.org", default_zone());
+    let rrsets = zone.get_rrsets(&Name::new("example.org.").unwrap());
+    assert!(rrsets.iter().any(|rrset| rrset.typ == RRType::SOA));
+    assert!(rrsets.iter().any(|rrset| rrset.typ == RRType::NS));
+    assert!(zone
+        .get_rrsets(&Name::new("unknown.example.org.").unwrap())
+        .is_empty());
+}
+
+#[test]
+fn test_any_find() {
+    let zone = build_zone("example.org", default_zone());
+    let result = zone.find(
+        &Name::new("ns.example.org.").unwrap(),
+        RRType::ANY,
+        FindOption::FollowZoneCut,
+    );
+    assert_eq!(result.typ, FindResultType::Success);
+    let rrsets = result.get_all_rrsets();
+    assert_eq!(rrsets.len(), 2);
+    assert!(rrsets
+        .iter()
+        .any(|rrset| rrset.typ == RRType::AAAA && rrset.name.to_string() == "ns.example.org."));
+
+    let result = zone.find(
+        &Name::new("child.example.org.").unwrap(),
+        RRType::ANY,
+        FindOption::FollowZoneCut,
+    );
+    assert_eq!(result.typ, FindResultType::Delegation);
+
+    let zone = build_zone(
+        "example",
+        vec![
+            "example. 300 IN SOA xxx.net. ns.example. 100 1800 900 604800 86400",
+            "*.example. 3600 IN AAAA ::1",
+            "*.example. 3600 IN MX 10 host1.example.",
+        ],
+    );
+    let result = zone.find(
+        &Name::new("host3.example").unwrap(),
+        RRType::ANY,
+        FindOption::FollowZoneCut,
+    );
+    assert_eq!(result.typ, FindResultType::Success);
+    let rrsets = result.get_all_rrsets();
+    assert_eq!(rrsets.len(), 2);
+    assert!(rrsets
+        .iter()
+        .all(|rrset| rrset.name.to_string() == "host3.example."));
+}
+
+#[test]
+fn test_find_signatures() {
+    let mut rrsets = default_zone();
+    rrsets.append(&mut vec![
+        "example.org. 300 IN RRSIG SOA 13 2 300 20300101000000 20200101000000 1 example.org. dGVzdA==",
+        "example.org. 300 IN RRSIG NS 13 2 300 20300101000000 20200101000000 1 example.org. dGVzdA==",
+        "ns.example.org. 300 IN RRSIG A 13 3 300 20300101000000 20200101000000 1 example.org. dGVzdA==",
+        "child.example.org. 300 IN DS 12345 13 2 3A6EA5C2B68D1F5A8A33F7E3B4D6A1E2C5F7A8B9C0D1E2F3A4B5C6D7E8F9A0B1",
+        "child.example.org. 300 IN RRSIG DS 13 3 300 20300101000000 20200101000000 1 example.org. dGVzdA==",
+        "*.wc.example.org. 300 IN A 192.0.2.1",
+        "*.wc.example.org. 300 IN RRSIG A 13 3 300 20300101000000 20200101000000 1 example.org. dGVzdA==",
+    ]);
+    let zone = build_zone("example.org", rrsets);
+
+    let mut result = zone.find(
+        &Name::new("ns.example.org.").unwrap(),
+        RRType::A,
+        FindOption::FollowZoneCut,
+    );
+    assert_eq!(result.typ, FindResultType::Success);
+    let rrset = result.rrset.take().unwrap();
+    assert_eq!(result.get_signatures(&rrset).unwrap().rdatas.len(), 1);
+    let (ns, _) = result.get_apex_ns_and_glue();
+    assert!(result.get_signatures(&ns).is_some());
+    assert!(result.get_signatures(&result.get_apex_soa()).is_some());
+
+    let mut result = zone.find(
+        &Name::new("www.child.example.org.").unwrap(),
+        RRType::A,
+        FindOption::FollowZoneCut,
+    );
+    assert_eq!(result.typ, FindResultType::Delegation);
+    let ds = result.get_delegation_ds().unwrap();
+    assert!(result.get_signatures(&ds).is_some());
+    assert!(result
+        .get_signatures(&result.rrset.take().unwrap())
+        .is_none());
+
+    //ds is answered by the parent
+    let result = zone.find(
+        &Name::new("child.example.org.").unwrap(),
+        RRType::DS,
+        FindOption::FollowZoneCut,
+    );
+    assert_eq!(result.typ, FindResultType::Success);
+
+    let mut result = zone.find(
+        &Name::new("www.wc.example.org.").unwrap(),
+        RRType::A,
+        FindOption::FollowZoneCut,
+    );
+    assert_eq!(result.typ, FindResultType::Success);
+    let rrset = result.rrset.take().unwrap();
+    let signatures = result.get_signatures(&rrset).unwrap();
+    assert_eq!(signatures.name.to_string(), "www.wc.example.org.");
+}
+
+fn denial_proof(zone: &MemoryZone, name: &str, typ: RRType) -> (FindResultType, Vec<String>) {
+    let name = Name::new(name).unwrap();
+    let result = zone.find(&name, typ, FindOption::FollowZoneCut);
+    let mut owners = result
+        .get_denial_proof(&name)
+        .iter()
+        .map(|rrset| rrset.name.to_string())
+        .collect::<Vec<String>>();
+    owners.sort();
+    (result.typ, owners)
+}
+
+#[test]
+fn test_nsec_denial_proof() {
+    let zone = build_zone("example.org", default_zone());
+    let (typ, owners) = denial_proof(&zone, "nx.example.org.", RRType::A);
+    assert_eq!(typ, FindResultType::NXDomain);
+    assert!(owners.is_empty());
+
+    let zone = build_zone(
+        "example.org",
+        vec![
+            "example.org. 300 IN SOA xxx.net. ns.example.org. 100 1800 900 604800 86400",
+            "example.org. 300 IN NS ns.example.org.",
+            "example.org. 300 IN NSEC a.example.org. NS SOA NSEC",
+            "a.example.org. 300 IN A 192.0.2.1",
+            "a.example.org. 300 IN NSEC child.example.org. A NSEC",
+            "child.example.org. 300 IN NS ns.child.example.org.",
+            "child.example.org. 300 IN NSEC *.w.example.org. NS NSEC",
+            "ns.child.example.org. 300 IN A 192.0.2.2",
+            "*.w.example.org. 300 IN A 192.0.2.3",
+            "*.w.example.org. 300 IN NSEC x.y.example.org. A NSEC",
+            "x.y.example.org. 300 IN A 192.0.2.4",
+            "x.y.example.org. 300 IN NSEC example.org. A NSEC",
+        ],
+    );
+    for (name, typ, result_type, expected) in vec![
+        (
+            "b.example.org.",
+            RRType::A,
+            FindResultType::NXDomain,
+            vec!["a.example.org.", "example.org."],
+        ),
+        (
+            "a.example.org.",
+            RRType::AAAA,
+            FindResultType::NXRRset,
+            vec!["a.example.org."],
+        ),
+        //empty non-terminal is covered
+        (
+            "y.example.org.",
+            RRType::A,
+            FindResultType::NXRRset,
+            vec!["*.w.example.org."],
+        ),
+        (
+            "z.w.example.org.",
+            RRType::A,
+            FindResultType::Success,
+            vec!["*.w.example.org."],
+        ),
+        (
+            "z.w.example.org.",
+            RRType::AAAA,
+            FindResultType::NXRRset,
+            vec!["*.w.example.org."],
+        ),
+        (
+            "www.child.example.org.",
+            RRType::A,
+            FindResultType::Delegation,
+            vec!["child.example.org."],
+        ),
+        (
+            "x.y.example.org.",
+            RRType::A,
+            FindResultType::Success,
+            vec![],
+        ),
+    ] {
+        let (typ, owners) = denial_proof(&zone, name, typ);
+        assert_eq!(typ, result_type);
+        assert_eq!(owners, expected);
+    }
+
+    let previous = |name: &str| {
+        let nsec = zone.get_previous_nsec(&Name::new(name).unwrap()).unwrap();
+        nsec.name.to_string()
+    };
+    assert_eq!(previous("b.example.org."), "a.example.org.");
+    assert_eq!(previous("a.example.org."), "example.org.");
+    assert_eq!(previous("ns.child.example.org."), "child.example.org.");
+    //the first name is preceded by the last nsec
+    assert_eq!(previous("example.org."), "x.y.example.org.");
+}
+
+#[test]
+fn test_nsec3_denial_proof() {
+    let param_rdata = RData::from_str(RRType::NSEC3PARAM, "1 0 1 aabb").unwrap();
+    let param = Nsec3Param::from_rdata(&param_rdata).unwrap();
+    let origin = Name::new("example.org.").unwrap();
+    let hashed = |name: &str| {
+        param
+            .hashed_name(&Name::new(name).unwrap(), &origin)
+            .to_string()
+    };
+
+    let mut hashes = vec![
+        "example.org.",
+        "a.example.org.",
+        "child.example.org.",
+        "w.example.org.",
+        "*.w.example.org.",
+    ]
+    .into_iter()
+    .map(|name| param.hash(&Name::new(name).unwrap()))
+    .collect::<Vec<String>>();
+    hashes.sort();
+    let mut rrset_strs = vec![
+        "example.org. 300 IN SOA xxx.net. ns.example.org. 100 1800 900 604800 86400".to_string(),
+        "example.org. 300 IN NS ns.example.org.".to_string(),
+        "example.org. 300 IN NSEC3PARAM 1 0 1 aabb".to_string(),
+        "a.example.org. 300 IN A 192.0.2.1".to_string(),
+        "child.example.org. 300 IN NS ns.child.example.org.".to_string(),
+        "ns.child.example.org. 300 IN A 192.0.2.2".to_string(),
+        "*.w.example.org. 300 IN A 192.0.2.3".to_string(),
+    ];
+    for (i, hash) in hashes.iter().enumerate() {
+        rrset_strs.push(format!(
+            "{}.example.org. 300 IN NSEC3 1 0 1 aabb {} A",
+            hash,
+            hashes[(i + 1) % hashes.len()]
+        ));
+    }
+    let mut zone = MemoryZone::new(origin.clone());
+    for rrset_str in rrset_strs {
+        zone.add_rrset(RRset::from_str(&rrset_str).unwrap())
+            .unwrap();
+    }
+
+    //nsec3 whose owner is the nearest one before the hash, the chain is a loop
+    let covering = |name: &str| {
+        let hash = param.hash(&Name::new(name).unwrap());
+        let previous = hashes
+            .iter()
+            .filter(|h| **h < hash)
+            .last()
+            .unwrap_or_else(|| hashes.last().unwrap());
+        format!("{}.example.org.", previous)
+    };
+    let sorted = |mut names: Vec<String>| {
+        names.sort();
+        names.dedup();
+        names
+    };
+
+    for (name, typ, result_type, expected) in vec![
+        (
+            "b.example.org.",
+            RRType::A,
+            FindResultType::NXDomain,
+            vec![
+                hashed("example.org."),
+                covering("b.example.org."),
+                covering("*.example.org."),
+            ],
+        ),
+        (
+            "a.example.org.",
+            RRType::AAAA,
+            FindResultType::NXRRset,
+            vec![hashed("a.example.org.")],
+        ),
+        (
+            "w.example.org.",
+            RRType::A,
+            FindResultType::NXRRset,
+            vec![hashed("w.example.org.")],
+        ),
+        (
+            "z.w.example.org.",
+            RRType::A,
+            FindResultType::Success,
+            vec![covering("z.w.example.org.")],
+        ),
+        (
+            "z.w.example.org.",
+            RRType::AAAA,
+            FindResultType::NXRRset,
+            vec![
+                hashed("w.example.org."),
+                covering("z.w.example.org."),
+                hashed("*.w.example.org."),
+            ],
+        ),
+        (
+            "www.child.example.org.",
+            RRType::A,
+            FindResultType::Delegation,
+            vec![hashed("child.example.org.")],
+        ),
+    ] {
+        let (typ, owners) = denial_proof(&zone, name, typ);
+        assert_eq!(typ, result_type);
+        assert_eq!(owners, sorted(expected));
+    }
+}
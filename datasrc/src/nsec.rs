@@ -0,0 +1,101 @@
+use crate::wire::rdata_to_wire;
+use r53::{Name, RData, RRType};
+use ring::digest;
+
+const NSEC3_SHA1: u8 = 1;
+//each query hashes names with the iterations, validators are allowed to
+//treat proofs with larger count as insecure anyway, rfc9276 section 3.2
+const MAX_NSEC3_ITERATIONS: u16 = 150;
+const BASE32HEX: &[u8] = b"0123456789abcdefghijklmnopqrstuv";
+
+//hash parameters of the nsec3 chain, rfc5155
+pub struct Nsec3Param {
+    iterations: u16,
+    salt: Vec<u8>,
+}
+
+impl Nsec3Param {
+    //rdata is algorithm(1) flags(1) iterations(2) salt length(1) salt
+    pub fn from_rdata(rdata: &RData) -> Option<Self> {
+        let wire = rdata_to_wire(RRType::NSEC3PARAM, rdata)?;
+        if wire.len() < 5 || wire[0] != NSEC3_SHA1 {
+            return None;
+        }
+        let iterations = u16::from_be_bytes([wire[2], wire[3]]);
+        if iterations > MAX_NSEC3_ITERATIONS {
+            return None;
+        }
+        let salt = wire.get(5..5 + wire[4] as usize)?.to_vec();
+        Some(Nsec3Param { iterations, salt })
+    }
+
+    //hash is calculated over the name in canonical form, only ascii
+    //letters are lowercased
+    pub fn hash(&self, name: &Name) -> String {
+        let wire = name.raw_data().to_ascii_lowercase();
+        let mut hash = self.digest(&wire);
+        for _ in 0..self.iterations {
+            hash = self.digest(&hash);
+        }
+        encode_base32hex(&hash)
+    }
+
+    //owner of the nsec3 record for the name
+    pub fn hashed_name(&self, name: &Name, origin: &Name) -> Name {
+        Name::new(&format!("{}.{}", self.hash(name), origin)).expect("hashed name is invalid")
+    }
+
+    fn digest(&self, data: &[u8]) -> Vec<u8> {
+        let mut ctx = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+        ctx.update(data);
+        ctx.update(&self.salt);
+        ctx.finish().as_ref().to_vec()
+    }
+}
+
+//lowercase without padding, which keeps the order of the hash
+fn encode_base32hex(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for &b in data {
+        buffer = (buffer << 8) | u16::from(b);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32HEX[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32HEX[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nsec3_hash() {
+        let rdata = RData::from_str(RRType::NSEC3PARAM, "1 0 12 aabbccdd").unwrap();
+        let param = Nsec3Param::from_rdata(&rdata).unwrap();
+        //rfc5155 appendix a
+        for (name, hash) in vec![
+            ("example", "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom"),
+            ("a.example", "35mthgpgcu1qg68fab165klnsnk3dpvl"),
+            ("w.example", "k8udemvp1j2f7eg6jebps17vp3n8i58h"),
+            ("*.w.example", "r53bq7cc2uvmubfu5ocmm6pers9tk9en"),
+        ] {
+            assert_eq!(param.hash(&Name::new(name).unwrap()), hash);
+        }
+
+        let rdata = RData::from_str(RRType::NSEC3PARAM, "2 0 12 aabbccdd").unwrap();
+        assert!(Nsec3Param::from_rdata(&rdata).is_none());
+        let rdata = RData::from_str(RRType::NSEC3PARAM, "1 0 500 aabbccdd").unwrap();
+        assert!(Nsec3Param::from_rdata(&rdata).is_none());
+        let rdata = RData::from_str(RRType::NSEC3PARAM, "1 0 0 -").unwrap();
+        let param = Nsec3Param::from_rdata(&rdata).unwrap();
+        assert!(param.salt.is_empty());
+    }
+}
@@ -1,39 +1,56 @@
-use crate::error::DataSrcError;
+use crate::{error::DataSrcError, wire::rdata_to_wire};
 use failure::Result;
 use r53::{Name, RData, RRClass, RRTtl, RRType, RRset};
 use std::mem::swap;
 
 type RRsetTuple = (RRType, RRTtl, Vec<RData>);
+//rrsig is indexed by its covered type
+type SignatureTuple = (RRType, RRTtl, Vec<RData>);
 
 pub struct Rdataset {
     rrsets: Vec<RRsetTuple>,
+    //signatures are kept with the covered type, signatures of each type
+    //are one rrsig rrset with their own ttl
+    signatures: Vec<SignatureTuple>,
 }
 
 impl Rdataset {
     pub fn new() -> Self {
-        Rdataset { rrsets: Vec::new() }
+        Rdataset {
+            rrsets: Vec::new(),
+            signatures: Vec::new(),
+        }
     }
 
     #[inline]
     pub fn len(&self) -> usize {
-        self.rrsets.len()
+        self.rrsets.len() + self.signatures.len()
     }
 
     #[inline]
     pub fn is_empty(&self) -> bool {
-        self.rrsets.is_empty()
+        self.rrsets.is_empty() && self.signatures.is_empty()
     }
 
     pub fn add_rrset(&mut self, rrset: RRset) -> Result<()> {
         self.validate_rrset(&rrset)?;
+        if rrset.typ == RRType::RRSIG {
+            return self.add_signatures(rrset);
+        }
 
         if let Some(index) = self.get_rrset_tuple(rrset.typ) {
             self.merge_rrset(index, rrset);
         } else {
-            if rrset.typ == RRType::CNAME && !self.rrsets.is_empty() {
+            //dnssec records could coexist with cname
+            if rrset.typ == RRType::CNAME
+                && self.rrsets.iter().any(|rrset| !is_dnssec_type(rrset.0))
+            {
                 return Err(DataSrcError::CNameCoExistsWithOtherRR.into());
             }
-            if rrset.typ != RRType::CNAME && self.get_rrset_tuple(RRType::CNAME).is_some() {
+            if rrset.typ != RRType::CNAME
+                && !is_dnssec_type(rrset.typ)
+                && self.get_rrset_tuple(RRType::CNAME).is_some()
+            {
                 return Err(DataSrcError::CNameCoExistsWithOtherRR.into());
             }
             self.rrsets.push((rrset.typ, rrset.ttl, rrset.rdatas));
@@ -64,7 +81,56 @@ impl Rdataset {
         }
     }
 
+    //rrsig is added as a signature of the covered rrset, which may not
+    //exist yet when the zone is loading
+    fn add_signatures(&mut self, rrset: RRset) -> Result<()> {
+        let mut signatures = Vec::with_capacity(rrset.rdatas.len());
+        for rdata in rrset.rdatas {
+            let covered = covered_type(&rdata)
+                .ok_or_else(|| DataSrcError::InvalidRRSIG(rdata.to_string()))?;
+            signatures.push((covered, rdata));
+        }
+        for (covered, rdata) in signatures {
+            match self.get_signature_tuple(covered) {
+                Some(index) => {
+                    self.signatures[index].1 = rrset.ttl;
+                    if !self.signatures[index].2.contains(&rdata) {
+                        self.signatures[index].2.push(rdata);
+                    }
+                }
+                None => self.signatures.push((covered, rrset.ttl, vec![rdata])),
+            }
+        }
+        Ok(())
+    }
+
+    //signatures of the rrset with the type
+    pub fn get_signatures(&self, name: &Name, typ: RRType) -> Option<RRset> {
+        self.get_signature_tuple(typ).map(|index| RRset {
+            name: name.clone(),
+            typ: RRType::RRSIG,
+            class: RRClass::IN,
+            ttl: self.signatures[index].1,
+            rdatas: self.signatures[index].2.clone(),
+        })
+    }
+
+    fn get_rrsigs<'a>(&'a self, name: &'a Name) -> impl Iterator<Item = RRset> + 'a {
+        self.signatures.iter().map(move |(_, ttl, rdatas)| RRset {
+            name: name.clone(),
+            typ: RRType::RRSIG,
+            class: RRClass::IN,
+            ttl: *ttl,
+            rdatas: rdatas.clone(),
+        })
+    }
+
+    //signatures of the first covered type are returned when rrsig is
+    //asked, all of them are returned by get_all_rrsets
     pub fn get_rrset(&self, name: &Name, typ: RRType) -> Option<RRset> {
+        if typ == RRType::RRSIG {
+            return self.get_rrsigs(name).next();
+        }
         self.get_rrset_tuple(typ).map(|index| RRset {
             name: name.clone(),
             typ,
@@ -74,9 +140,37 @@ impl Rdataset {
         })
     }
 
+    pub fn get_all_rrsets(&self, name: &Name) -> Vec<RRset> {
+        let mut rrsets = self
+            .rrsets
+            .iter()
+            .map(|(typ, ttl, rdatas)| RRset {
+                name: name.clone(),
+                typ: *typ,
+                class: RRClass::IN,
+                ttl: *ttl,
+                rdatas: rdatas.clone(),
+            })
+            .collect::<Vec<RRset>>();
+        rrsets.extend(self.get_rrsigs(name));
+        rrsets
+    }
+
+    //signatures are deleted with the covered rrset, while deleting rdatas
+    //keeps them, since the diff of zone transfer deletes them explicitly
     pub fn delete_rrset(&mut self, typ: RRType) -> Result<()> {
+        if typ == RRType::RRSIG {
+            if self.signatures.is_empty() {
+                return Err(DataSrcError::RRsetNotFound(typ.to_string()).into());
+            }
+            self.signatures.clear();
+            return Ok(());
+        }
         if let Some(index) = self.get_rrset_tuple(typ) {
             self.rrsets.remove(index);
+            if let Some(index) = self.get_signature_tuple(typ) {
+                self.signatures.remove(index);
+            }
             Ok(())
         } else {
             Err(DataSrcError::RRsetNotFound(typ.to_string()).into())
@@ -84,6 +178,9 @@ impl Rdataset {
     }
 
     pub fn delete_rdata(&mut self, rrset: &RRset) -> Result<()> {
+        if rrset.typ == RRType::RRSIG {
+            return self.delete_signatures(rrset);
+        }
         if let Some(index) = self.get_rrset_tuple(rrset.typ) {
             for rdata in &rrset.rdatas {
                 if let Some(index_) = self.rrsets[index]
@@ -106,7 +203,34 @@ impl Rdataset {
         }
     }
 
+    fn delete_signatures(&mut self, rrset: &RRset) -> Result<()> {
+        for rdata in &rrset.rdatas {
+            let index = covered_type(rdata)
+                .and_then(|covered| self.get_signature_tuple(covered))
+                .ok_or_else(|| DataSrcError::RdataNotFound(rdata.to_string()))?;
+            match self.signatures[index]
+                .2
+                .iter()
+                .position(|current| rdata.eq(current))
+            {
+                Some(index_) => {
+                    self.signatures[index].2.remove(index_);
+                }
+                None => return Err(DataSrcError::RdataNotFound(rdata.to_string()).into()),
+            }
+            if self.signatures[index].2.is_empty() {
+                self.signatures.remove(index);
+            }
+        }
+        Ok(())
+    }
+
     pub fn update_rdata(&mut self, old_rrset: &RRset, mut new_rrset: RRset) -> Result<()> {
+        if old_rrset.typ == RRType::RRSIG {
+            self.delete_signatures(old_rrset)?;
+            new_rrset.ttl = old_rrset.ttl;
+            return self.add_signatures(new_rrset);
+        }
         if let Some(index) = self.get_rrset_tuple(old_rrset.typ) {
             for (pos, rdata) in old_rrset.rdatas.iter().enumerate() {
                 if let Some(index_) = self.rrsets[index]
@@ -131,6 +255,23 @@ impl Rdataset {
     fn get_rrset_tuple(&self, typ: RRType) -> Option<usize> {
         self.rrsets.iter().position(|rrset| rrset.0 == typ)
     }
+
+    fn get_signature_tuple(&self, covered: RRType) -> Option<usize> {
+        self.signatures
+            .iter()
+            .position(|signature| signature.0 == covered)
+    }
+}
+
+//type covered is the first field of rrsig
+fn covered_type(rdata: &RData) -> Option<RRType> {
+    let wire = rdata_to_wire(RRType::RRSIG, rdata)?;
+    let typ = wire.get(0..2)?;
+    Some(RRType::new(u16::from_be_bytes([typ[0], typ[1]])))
+}
+
+fn is_dnssec_type(typ: RRType) -> bool {
+    typ == RRType::RRSIG || typ == RRType::NSEC
 }
 
 #[cfg(test)]
@@ -214,4 +355,50 @@ mod tests {
         let result = rrset.add_rrset(cname.clone());
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_signatures() {
+        let name = Name::new("a.cn").unwrap();
+        let mut rdataset = Rdataset::new();
+        let rrsig = |covered: &str| {
+            RRset::from_str(&format!(
+                "a.cn. 3600 IN RRSIG {} 13 2 3600 20300101000000 20200101000000 12345 cn. dGVzdA==",
+                covered
+            ))
+            .unwrap()
+        };
+        //signature could be added before the covered rrset
+        rdataset.add_rrset(rrsig("A")).unwrap();
+        rdataset
+            .add_rrset(build_a_rrset("a.cn", &["1.1.1.1"]))
+            .unwrap();
+        let mut txt_rrsig = rrsig("TXT");
+        txt_rrsig.ttl = RRTtl(300);
+        rdataset.add_rrset(txt_rrsig.clone()).unwrap();
+        assert_eq!(rdataset.len(), 3);
+        assert_eq!(rdataset.get_signatures(&name, RRType::A), Some(rrsig("A")));
+        assert_eq!(rdataset.get_rrset(&name, RRType::RRSIG), Some(rrsig("A")));
+        //signatures of each covered type keep their own ttl
+        let rrsets = rdataset.get_all_rrsets(&name);
+        assert_eq!(rrsets.len(), 3);
+        assert!(rrsets.contains(&rrsig("A")));
+        assert!(rrsets.contains(&txt_rrsig));
+
+        rdataset.delete_rdata(&rrsig("TXT")).unwrap();
+        assert_eq!(rdataset.get_signatures(&name, RRType::TXT), None);
+        assert!(rdataset.delete_rdata(&rrsig("TXT")).is_err());
+        //signatures are deleted with the covered rrset
+        rdataset.add_rrset(txt_rrsig).unwrap();
+        rdataset.delete_rrset(RRType::A).unwrap();
+        assert_eq!(rdataset.get_signatures(&name, RRType::A), None);
+        assert!(!rdataset.is_empty());
+        rdataset.delete_rrset(RRType::RRSIG).unwrap();
+        assert!(rdataset.is_empty());
+
+        //signature could coexist with cname
+        let cname = RRset::from_str("a.cn. 3600 IN CNAME a.com.").unwrap();
+        rdataset.add_rrset(cname).unwrap();
+        rdataset.add_rrset(rrsig("CNAME")).unwrap();
+        assert!(rdataset.get_signatures(&name, RRType::CNAME).is_some());
+    }
 }
@@ -0,0 +1,48 @@
+use r53::{Message, MessageBuilder, MessageRender, Name, RData, RRClass, RRTtl, RRType, RRset};
+
+const HEADER_LEN: usize = 12;
+//root name and type, class of the question
+const QUESTION_LEN: usize = 5;
+
+//rdata is rendered with root as owner and read back, so fields could be
+//got from wire format instead of the text of rdata
+pub fn rdata_to_wire(typ: RRType, rdata: &RData) -> Option<Vec<u8>> {
+    let root = Name::new(".").ok()?;
+    let mut message = Message::with_query(root.clone(), typ);
+    MessageBuilder::new(&mut message)
+        .add_answer(RRset {
+            name: root,
+            typ,
+            class: RRClass::IN,
+            ttl: RRTtl(0),
+            rdatas: vec![rdata.clone()],
+        })
+        .done();
+    let mut render = MessageRender::new();
+    message.rend(&mut render);
+    let wire = render.data();
+
+    //owner of the answer is root or a pointer to the question
+    let mut pos = HEADER_LEN + QUESTION_LEN;
+    pos += if *wire.get(pos)? == 0 { 1 } else { 2 };
+    let fields = wire.get(pos..pos + 10)?;
+    let rdlen = u16::from_be_bytes([fields[8], fields[9]]) as usize;
+    wire.get(pos + 10..pos + 10 + rdlen)
+        .map(|rdata| rdata.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rdata_to_wire() {
+        let rdata = RData::from_str(RRType::A, "1.2.3.4").unwrap();
+        assert_eq!(rdata_to_wire(RRType::A, &rdata), Some(vec![1, 2, 3, 4]));
+        let rdata = RData::from_str(RRType::NSEC3PARAM, "1 0 12 aabbccdd").unwrap();
+        assert_eq!(
+            rdata_to_wire(RRType::NSEC3PARAM, &rdata),
+            Some(vec![1, 0, 0, 12, 4, 0xaa, 0xbb, 0xcc, 0xdd])
+        );
+    }
+}
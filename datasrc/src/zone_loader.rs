@@ -1,18 +1,49 @@
+use crate::master_file::MasterFileParser;
 use crate::memory_zone::MemoryZone;
 use crate::zone::ZoneUpdater;
 use failure::Result;
-use r53::{Name, RRset};
-use std::str::FromStr;
+use r53::Name;
+use std::path::Path;
 
 pub fn load_zone(name: Name, content: &str) -> Result<MemoryZone> {
-    let mut zone = MemoryZone::new(name);
-    for line in content.lines() {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
+    let parser = MasterFileParser::new(&name, content);
+    add_rrsets(MemoryZone::new(name), parser)
+}
+
+//$INCLUDE in zone file is relative to the directory of the file
+pub fn load_zone_file<P: AsRef<Path>>(name: Name, path: P) -> Result<MemoryZone> {
+    let parser = MasterFileParser::from_file(&name, path)?;
+    add_rrsets(MemoryZone::new(name), parser)
+}
+
+fn add_rrsets(mut zone: MemoryZone, mut parser: MasterFileParser) -> Result<MemoryZone> {
+    loop {
+        let rrset = match parser.next() {
+            Some(rrset) => rrset?,
+            None => return Ok(zone),
+        };
+        if let Err(e) = zone.add_rrset(rrset) {
+            return Err(parser.entry_error(e.to_string()));
         }
-        let rrset = RRset::from_str(line)?;
-        zone.add_rrset(rrset)?;
     }
-    Ok(zone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_zone() {
+        let content = "$ORIGIN example.org.\n$TTL 300\n@ SOA ns hostmaster 1 2 3 4 5\n  NS ns\nns A 1.1.1.1\n";
+        assert!(load_zone(Name::new("example.org").unwrap(), content).is_ok());
+
+        let content = "$TTL 300\nwww A 1.1.1.1\nwww.example.com. A 1.1.1.2\n";
+        let err = load_zone(Name::new("example.org").unwrap(), content).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("line 3 column 1: rrset isn't belong current zone"));
+
+        let content = "$TTL 300\n$GENERATE 1-3 www$ A 1.1.1.$\nwww2 CNAME host\n";
+        assert!(load_zone(Name::new("example.org").unwrap(), content).is_err());
+    }
 }
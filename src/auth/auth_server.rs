@@ -1,12 +1,55 @@
-use super::zones::AuthZone;
-use crate::{config::AuthorityConfig, server::Query};
-use failure;
+use super::{
+    dnssec::DnssecKey,
+    error::AuthError,
+    key_manager::KeyManager,
+    notify::{Notifier, NotifyTarget},
+    persist::{flush_zones, run_flusher},
+    secondary::{parse_key_name, parse_server_addresses, SecondaryManager, SecondaryZone},
+    signer::{resign_delegations, run_refresher},
+    update::{handle_update, make_update_response},
+    xfr::{make_error_response, make_error_xfr_response},
+    zones::AuthZone,
+};
+use crate::{
+    config::{AuthZoneConfig, AuthorityConfig, SerialPolicy, ZoneType},
+    server::{normalize_key_name, AnswerSource, KeyRing, Query, ShutdownListener, XfrResponse},
+};
+use datasrc::{load_zone_file, MemoryZone, ZoneFinder};
+use failure::{self, Result};
 use futures::{prelude::*, Future};
-use std::sync::{Arc, RwLock};
+use r53::{HeaderFlag, Message, MessageBuilder, Name, Opcode, RRType, Rcode};
+use std::{
+    collections::HashMap,
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+//keys configured in files, or generated and rolled over by policy
+enum ZoneKeys {
+    Static(Vec<DnssecKey>),
+    Managed(KeyManager),
+}
 
 #[derive(Clone)]
 pub struct AuthServer {
     zones: Arc<RwLock<AuthZone>>,
+    secondaries: SecondaryManager,
+    notifier: Notifier,
+    allow_transfer: Arc<RwLock<Vec<IpAddr>>>,
+    allow_update: Arc<RwLock<Vec<IpAddr>>>,
+    key_policies: Arc<RwLock<HashMap<Name, KeyPolicy>>>,
+    keyring: KeyRing,
+    flush_interval: Duration,
+}
+
+//keys which may transfer or update the zone besides the allowed addresses
+#[derive(Debug, Default)]
+struct KeyPolicy {
+    transfer_keys: Vec<String>,
+    update_keys: Vec<String>,
 }
 
 pub struct AuthFuture {
@@ -15,19 +58,308 @@ pub struct AuthFuture {
 }
 
 impl AuthServer {
-    pub fn new(conf: &AuthorityConfig) -> Self {
-        AuthServer {
-            zones: Arc::new(RwLock::new(AuthZone::new())),
+    pub fn new(conf: &AuthorityConfig, keyring: KeyRing) -> Result<Self> {
+        let allow_transfer = parse_addresses(&conf.allow_transfer)?;
+        let allow_update = parse_addresses(&conf.allow_update)?;
+        let key_policies = parse_key_policies(conf, &keyring)?;
+        let notifier = Notifier::new(keyring.clone());
+        notifier.set_targets(parse_notify_targets(conf, &keyring)?);
+        if !conf.journal_dir.is_empty() {
+            fs::create_dir_all(&conf.journal_dir)
+                .map_err(|e| AuthError::JournalFailed(conf.journal_dir.clone(), e.to_string()))?;
         }
+        let zones = Arc::new(RwLock::new(AuthZone::new(
+            &conf.journal_dir,
+            conf.max_journal_records,
+            notifier.clone(),
+        )));
+        if !conf.zone_dir.is_empty() {
+            fs::create_dir_all(&conf.zone_dir)
+                .map_err(|e| AuthError::ZoneFlushFailed(conf.zone_dir.clone(), e.to_string()))?;
+        }
+        {
+            let mut zones = zones.write().unwrap();
+            zones.set_serial_policies(parse_serial_policies(conf)?);
+            zones.set_zone_dir(&conf.zone_dir);
+            zones.set_any_query_mode(conf.any_query_mode);
+            zones.set_signature_lifetime(conf.signature_validity, conf.signature_refresh);
+        }
+        let secondaries = SecondaryManager::new();
+        for zone_conf in &conf.zones {
+            let result = match zone_conf.zone_type {
+                ZoneType::Primary => {
+                    load_primary_zone(zone_conf, &conf.key_dir).and_then(|(zone, keys)| {
+                        insert_primary_zone(&mut zones.write().unwrap(), zone, keys, zone_conf)
+                    })
+                }
+                ZoneType::Secondary => {
+                    SecondaryZone::new(zone_conf, zones.clone(), keyring.clone())
+                        .map(|zone| secondaries.add_zone(zone))
+                }
+            };
+            if let Err(e) = result {
+                if conf.skip_invalid_zone {
+                    eprintln!("{}, skip it", e);
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+        load_managed_zones(&mut zones.write().unwrap(), conf)?;
+
+        Ok(AuthServer {
+            zones,
+            secondaries,
+            notifier,
+            allow_transfer: Arc::new(RwLock::new(allow_transfer)),
+            allow_update: Arc::new(RwLock::new(allow_update)),
+            key_policies: Arc::new(RwLock::new(key_policies)),
+            keyring,
+            flush_interval: Duration::from_secs(conf.flush_interval),
+        })
+    }
+
+    //zones removed from config are deleted, new added zones and zones
+    //whose file path or primaries are changed are loaded, the other zones
+    //are kept untouched to avoid losing the dynamic updates. Zone files are
+    //loaded before any change is applied, so when zone fails to load and
+    //invalid zone isn't skipped, current zones won't be changed
+    pub fn reload(&self, old_conf: &AuthorityConfig, new_conf: &AuthorityConfig) -> Result<()> {
+        let allow_transfer = parse_addresses(&new_conf.allow_transfer)?;
+        let allow_update = parse_addresses(&new_conf.allow_update)?;
+        let key_policies = parse_key_policies(new_conf, &self.keyring)?;
+        let notify_targets = parse_notify_targets(new_conf, &self.keyring)?;
+        let serial_policies = parse_serial_policies(new_conf)?;
+        let mut new_zones = Vec::new();
+        let mut new_secondaries = Vec::new();
+        {
+            let zones = self.zones.read().unwrap();
+            for zone_conf in &new_conf.zones {
+                let unchanged = old_conf.zones.iter().any(|old| {
+                    is_same_zone(old, zone_conf)
+                        && old.zone_type == zone_conf.zone_type
+                        && old.file_path == zone_conf.file_path
+                        && old.primaries == zone_conf.primaries
+                        && old.tsig_key == zone_conf.tsig_key
+                        && old.dnssec_keys == zone_conf.dnssec_keys
+                        && old.dnssec_policy == zone_conf.dnssec_policy
+                });
+                //zone failed to load last time will be retried
+                let loaded = Name::new(zone_conf.name.as_ref())
+                    .map(|name| match zone_conf.zone_type {
+                        ZoneType::Primary => zones.has_zone(&name),
+                        ZoneType::Secondary => self.secondaries.has_zone(&name),
+                    })
+                    .unwrap_or(false);
+                if unchanged && loaded {
+                    continue;
+                }
+
+                let result = match zone_conf.zone_type {
+                    ZoneType::Primary => load_primary_zone(zone_conf, &new_conf.key_dir)
+                        .map(|(zone, keys)| new_zones.push((zone, keys, zone_conf))),
+                    ZoneType::Secondary => {
+                        SecondaryZone::new(zone_conf, self.zones.clone(), self.keyring.clone())
+                            .map(|zone| new_secondaries.push(zone))
+                    }
+                };
+                if let Err(e) = result {
+                    if new_conf.skip_invalid_zone {
+                        eprintln!("{}, skip it", e);
+                    } else {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        let mut zones = self.zones.write().unwrap();
+        for old in &old_conf.zones {
+            if new_conf.zones.iter().all(|new| !is_same_zone(old, new)) {
+                if let Ok(name) = Name::new(old.name.as_ref()) {
+                    self.secondaries.remove_zone(&name);
+                    let _ = zones.remove_zone(&name);
+                }
+            }
+        }
+        for (zone, keys, zone_conf) in new_zones {
+            let name = zone.get_origin().clone();
+            self.secondaries.remove_zone(&name);
+            if zones.has_zone(&name) {
+                zones.remove_zone(&name)?;
+            }
+            insert_primary_zone(&mut zones, zone, keys, zone_conf)?;
+        }
+        //zone which is secondary already is served until it's refreshed
+        //from the new primaries
+        for zone in new_secondaries {
+            if !self.secondaries.has_zone(zone.get_name()) {
+                let _ = zones.remove_zone(zone.get_name());
+            }
+            self.secondaries.add_zone(zone);
+        }
+        *self.allow_transfer.write().unwrap() = allow_transfer;
+        *self.allow_update.write().unwrap() = allow_update;
+        *self.key_policies.write().unwrap() = key_policies;
+        zones.set_serial_policies(serial_policies);
+        zones.set_any_query_mode(new_conf.any_query_mode);
+        zones.set_signature_lifetime(new_conf.signature_validity, new_conf.signature_refresh);
+        self.notifier.set_targets(notify_targets);
+        Ok(())
     }
 
     pub fn zones(&self) -> Arc<RwLock<AuthZone>> {
         self.zones.clone()
     }
 
-    pub fn handle_query(&self, query: Query) -> AuthFuture {
+    pub fn secondary_zones(&self) -> SecondaryManager {
+        self.secondaries.clone()
+    }
+
+    pub fn notifier(&self) -> Notifier {
+        self.notifier.clone()
+    }
+
+    pub fn keyring(&self) -> KeyRing {
+        self.keyring.clone()
+    }
+
+    pub fn flush_zones(&self) {
+        flush_zones(&self.zones);
+    }
+
+    pub fn run_flusher(
+        &self,
+        shutdown: ShutdownListener,
+    ) -> impl Future<Item = (), Error = ()> + Send {
+        run_flusher(self.zones.clone(), self.flush_interval, shutdown)
+    }
+
+    pub fn run_signer(
+        &self,
+        shutdown: ShutdownListener,
+    ) -> impl Future<Item = (), Error = ()> + Send {
+        run_refresher(self.zones.clone(), shutdown)
+    }
+
+    pub fn handle_query(&self, mut query: Query) -> AuthFuture {
+        if !is_tsig_verified(&query) {
+            MessageBuilder::new(&mut query.message)
+                .make_response()
+                .rcode(Rcode::NotAuth)
+                .done();
+            query.done = true;
+            query.answer_source = Some(AnswerSource::Auth);
+            return AuthFuture::new(self.zones.clone(), query);
+        }
+        //zone transfer is only served over tcp
+        if query.over_udp && is_xfr(&query.message) {
+            query.message = make_error_response(&query.message, Rcode::Refused);
+            query.done = true;
+            query.answer_source = Some(AnswerSource::Auth);
+            return AuthFuture::new(self.zones.clone(), query);
+        }
+        match query.message.header.opcode {
+            Opcode::Notify => self.handle_notify(&mut query),
+            Opcode::Update => self.handle_update(&mut query),
+            _ => {}
+        }
         AuthFuture::new(self.zones.clone(), query)
     }
+
+    //notify from primary triggers the refresh of secondary zone
+    fn handle_notify(&self, query: &mut Query) {
+        let rcode = match query.message.question.as_ref() {
+            Some(question) => self.secondaries.notify(
+                &question.name,
+                query.client.ip(),
+                query.tsig.as_ref().map(|tsig| tsig.get_key_name()),
+            ),
+            None => Rcode::FormErr,
+        };
+        MessageBuilder::new(&mut query.message)
+            .make_response()
+            .rcode(rcode)
+            .set_flag(HeaderFlag::AuthAnswer)
+            .done();
+        query.done = true;
+        query.answer_source = Some(AnswerSource::Auth);
+    }
+
+    //secondary zone is only updated by zone transfer, update forwarding
+    //isn't supported
+    fn handle_update(&self, query: &mut Query) {
+        let allowed = self
+            .allow_update
+            .read()
+            .unwrap()
+            .contains(&query.client.ip())
+            || self.is_signed_by_allowed_key(query, |policy| &policy.update_keys);
+        let is_secondary = query
+            .message
+            .question
+            .as_ref()
+            .map(|question| self.secondaries.has_zone(&question.name))
+            .unwrap_or(false);
+        if !allowed || is_secondary {
+            make_update_response(&mut query.message, Rcode::Refused);
+        } else {
+            handle_update(&mut self.zones.write().unwrap(), &mut query.message);
+            resign_delegations(&self.zones);
+        }
+        query.done = true;
+        query.answer_source = Some(AnswerSource::Auth);
+    }
+
+    //return None if the query isn't zone transfer
+    pub fn handle_xfr(&self, query: &Query) -> Option<XfrResponse> {
+        if !is_xfr(&query.message) {
+            return None;
+        }
+
+        if !is_tsig_verified(query) {
+            return Some(make_error_xfr_response(&query.message, Rcode::NotAuth));
+        }
+        let allowed = self
+            .allow_transfer
+            .read()
+            .unwrap()
+            .contains(&query.client.ip())
+            || self.is_signed_by_allowed_key(query, |policy| &policy.transfer_keys);
+        if !allowed {
+            return Some(make_error_xfr_response(&query.message, Rcode::Refused));
+        }
+        //responses are built from a snapshot of the zone, so they won't be
+        //torn by updates made after the lock is released
+        Some(self.zones.read().unwrap().handle_xfr(&query.message))
+    }
+
+    fn is_signed_by_allowed_key<F>(&self, query: &Query, get_keys: F) -> bool
+    where
+        F: Fn(&KeyPolicy) -> &Vec<String>,
+    {
+        let (question, tsig) = match (query.message.question.as_ref(), query.tsig.as_ref()) {
+            (Some(question), Some(tsig)) if tsig.is_verified() => (question, tsig),
+            _ => return false,
+        };
+        let key_name = normalize_key_name(tsig.get_key_name());
+        self.key_policies
+            .read()
+            .unwrap()
+            .get(&question.name)
+            .map_or(false, |policy| get_keys(policy).contains(&key_name))
+    }
+}
+
+//signed query with bad key, signature or time is rejected
+fn is_tsig_verified(query: &Query) -> bool {
+    query.tsig.as_ref().map_or(true, |tsig| tsig.is_verified())
+}
+
+fn is_xfr(message: &Message) -> bool {
+    message.question.as_ref().map_or(false, |question| {
+        question.typ == RRType::AXFR || question.typ == RRType::IXFR
+    })
 }
 
 impl AuthFuture {
@@ -46,9 +378,557 @@ impl Future for AuthFuture {
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let zones = self.zones.read().unwrap();
         let query = self.query.as_mut().unwrap();
-        if zones.handle_query(&mut query.message) {
+        if !query.done && zones.handle_query(&mut query.message, query.over_udp) {
             query.done = true;
+            query.answer_source = Some(AnswerSource::Auth);
         }
         return Ok(Async::Ready(self.query.take().unwrap()));
     }
 }
+
+fn load_zone_from_file(conf: &AuthZoneConfig) -> Result<MemoryZone> {
+    let load_failed = |e: failure::Error| -> failure::Error {
+        AuthError::ZoneLoadFailed(conf.name.clone(), conf.file_path.clone(), e.to_string()).into()
+    };
+
+    let name = Name::new(conf.name.as_ref()).map_err(load_failed)?;
+    load_zone_file(name, &conf.file_path).map_err(load_failed)
+}
+
+//keys are loaded with the zone, so invalid key is found before the zones
+//are changed
+fn load_primary_zone(conf: &AuthZoneConfig, key_dir: &str) -> Result<(MemoryZone, ZoneKeys)> {
+    let zone = load_zone_from_file(conf)?;
+    let keys = match conf.dnssec_policy {
+        Some(ref policy) => {
+            if !conf.dnssec_keys.is_empty() {
+                return Err(AuthError::InvalidDnssecKey(
+                    conf.name.clone(),
+                    "dnssec keys and dnssec policy are exclusive".to_string(),
+                )
+                .into());
+            }
+            ZoneKeys::Managed(KeyManager::load(zone.get_origin(), policy, key_dir)?)
+        }
+        None => ZoneKeys::Static(
+            conf.dnssec_keys
+                .iter()
+                .map(DnssecKey::load)
+                .collect::<Result<Vec<DnssecKey>>>()?,
+        ),
+    };
+    Ok((zone, keys))
+}
+
+//changes of the zone loaded from config are written back to its file,
+//zone with keys is signed before it's served
+fn insert_primary_zone(
+    zones: &mut AuthZone,
+    zone: MemoryZone,
+    keys: ZoneKeys,
+    conf: &AuthZoneConfig,
+) -> Result<()> {
+    let name = zone.get_origin().clone();
+    zones.insert_zone(zone)?;
+    let signed = match keys {
+        ZoneKeys::Static(ref keys) if keys.is_empty() => Ok(()),
+        ZoneKeys::Static(keys) => zones.set_zone_signer(&name, keys),
+        ZoneKeys::Managed(manager) => zones.set_key_manager(&name, manager),
+    };
+    if let Err(e) = signed {
+        let _ = zones.remove_zone(&name);
+        return Err(e);
+    }
+    zones.set_zone_file(&name, PathBuf::from(&conf.file_path));
+    Ok(())
+}
+
+//zones persisted in the zone dir are the ones added through grpc, zone
+//which is in config already is ignored
+fn load_managed_zones(zones: &mut AuthZone, conf: &AuthorityConfig) -> Result<()> {
+    if conf.zone_dir.is_empty() {
+        return Ok(());
+    }
+    let entries = fs::read_dir(&conf.zone_dir).map_err(|e| {
+        AuthError::ZoneLoadFailed(String::new(), conf.zone_dir.clone(), e.to_string())
+    })?;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        if !file_name.ends_with(".zone") {
+            continue;
+        }
+        let zone_conf = AuthZoneConfig {
+            name: file_name.trim_end_matches(".zone").to_string(),
+            zone_type: ZoneType::Primary,
+            file_path: path.to_string_lossy().into_owned(),
+            primaries: Vec::new(),
+            notify: Vec::new(),
+            allow_transfer_keys: Vec::new(),
+            allow_update_keys: Vec::new(),
+            tsig_key: String::new(),
+            serial_policy: SerialPolicy::default(),
+            dnssec_keys: Vec::new(),
+            dnssec_policy: None,
+        };
+        if conf.zones.iter().any(|zone| is_same_zone(zone, &zone_conf)) {
+            continue;
+        }
+        let result = load_zone_from_file(&zone_conf).and_then(|zone| {
+            let name = zone.get_origin().clone();
+            insert_primary_zone(zones, zone, ZoneKeys::Static(Vec::new()), &zone_conf)?;
+            zones.set_managed_zone(&name);
+            Ok(())
+        });
+        if let Err(e) = result {
+            if conf.skip_invalid_zone {
+                eprintln!("{}, skip it", e);
+            } else {
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_addresses(addrs: &[String]) -> Result<Vec<IpAddr>> {
+    addrs
+        .iter()
+        .map(|addr| {
+            addr.parse()
+                .map_err(|_| AuthError::InvalidAddress(addr.clone()).into())
+        })
+        .collect()
+}
+
+fn parse_notify_targets(
+    conf: &AuthorityConfig,
+    keyring: &KeyRing,
+) -> Result<HashMap<Name, NotifyTarget>> {
+    let mut targets = HashMap::new();
+    for zone_conf in conf.zones.iter().filter(|zone| !zone.notify.is_empty()) {
+        let name = Name::new(zone_conf.name.as_ref())?;
+        let target = NotifyTarget {
+            secondaries: parse_server_addresses(&zone_conf.notify)?,
+            tsig_key: parse_key_name(&zone_conf.tsig_key, keyring)?,
+        };
+        targets.insert(name, target);
+    }
+    Ok(targets)
+}
+
+fn parse_serial_policies(conf: &AuthorityConfig) -> Result<HashMap<Name, SerialPolicy>> {
+    let mut policies = HashMap::new();
+    for zone_conf in &conf.zones {
+        policies.insert(Name::new(zone_conf.name.as_ref())?, zone_conf.serial_policy);
+    }
+    Ok(policies)
+}
+
+fn parse_key_policies(
+    conf: &AuthorityConfig,
+    keyring: &KeyRing,
+) -> Result<HashMap<Name, KeyPolicy>> {
+    let parse_keys = |names: &[String]| -> Result<Vec<String>> {
+        names
+            .iter()
+            .filter(|name| !name.is_empty())
+            .map(|name| parse_key_name(name, keyring).map(Option::unwrap_or_default))
+            .collect()
+    };
+    let mut policies = HashMap::new();
+    for zone_conf in &conf.zones {
+        let name = Name::new(zone_conf.name.as_ref())?;
+        let policy = KeyPolicy {
+            transfer_keys: parse_keys(&zone_conf.allow_transfer_keys)?,
+            update_keys: parse_keys(&zone_conf.allow_update_keys)?,
+        };
+        policies.insert(name, policy);
+    }
+    Ok(policies)
+}
+
+fn is_same_zone(a: &AuthZoneConfig, b: &AuthZoneConfig) -> bool {
+    match (Name::new(a.name.as_ref()), Name::new(b.name.as_ref())) {
+        (Ok(a), Ok(b)) => a.eq(&b),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::journal::get_serial,
+        config::{AnyQueryMode, TsigKeyConfig},
+        server::TsigSigner,
+    };
+    use datasrc::ZoneUpdater;
+    use r53::{edns::Edns, message::SectionType, MessageRender, RRset};
+    use std::str::FromStr;
+
+    fn zone_conf(name: &str, file_path: &str) -> AuthZoneConfig {
+        AuthZoneConfig {
+            name: name.to_string(),
+            zone_type: ZoneType::Primary,
+            file_path: file_path.to_string(),
+            primaries: Vec::new(),
+            notify: Vec::new(),
+            allow_transfer_keys: Vec::new(),
+            allow_update_keys: Vec::new(),
+            tsig_key: String::new(),
+            serial_policy: SerialPolicy::Increment,
+            dnssec_keys: Vec::new(),
+            dnssec_policy: None,
+        }
+    }
+
+    #[test]
+    fn test_load_zones_from_config() {
+        let mut conf = AuthorityConfig {
+            zones: vec![
+                zone_conf("example.org", "testdata/example.org.zone"),
+                zone_conf("example.com", "testdata/unknown.zone"),
+            ],
+            ..AuthorityConfig::default()
+        };
+        assert!(AuthServer::new(&conf, KeyRing::default()).is_err());
+
+        conf.skip_invalid_zone = true;
+        let server = AuthServer::new(&conf, KeyRing::default()).unwrap();
+        let zones = server.zones();
+        let zones = zones.read().unwrap();
+        assert!(zones
+            .get_zone(&Name::new("www.example.org").unwrap())
+            .is_some());
+        assert!(zones
+            .get_zone(&Name::new("www.example.com").unwrap())
+            .is_none());
+    }
+
+    #[test]
+    fn test_reload_zones() {
+        let old_conf = AuthorityConfig {
+            zones: vec![zone_conf("example.org", "testdata/example.org.zone")],
+            ..AuthorityConfig::default()
+        };
+        let server = AuthServer::new(&old_conf, KeyRing::default()).unwrap();
+
+        let new_conf = AuthorityConfig {
+            zones: vec![
+                zone_conf("example.org", "testdata/example.org.zone"),
+                zone_conf("example.com", "testdata/unknown.zone"),
+            ],
+            ..AuthorityConfig::default()
+        };
+        assert!(server.reload(&old_conf, &new_conf).is_err());
+        assert!(server
+            .zones()
+            .read()
+            .unwrap()
+            .has_zone(&Name::new("example.org").unwrap()));
+
+        let new_conf = AuthorityConfig::default();
+        server.reload(&old_conf, &new_conf).unwrap();
+        assert!(!server
+            .zones()
+            .read()
+            .unwrap()
+            .has_zone(&Name::new("example.org").unwrap()));
+
+        server.reload(&new_conf, &old_conf).unwrap();
+        assert!(server
+            .zones()
+            .read()
+            .unwrap()
+            .has_zone(&Name::new("example.org").unwrap()));
+    }
+
+    #[test]
+    fn test_handle_any() {
+        let mut conf = AuthorityConfig {
+            zones: vec![zone_conf("example.org", "testdata/example.org.zone")],
+            any_query_mode: AnyQueryMode::Single,
+            ..AuthorityConfig::default()
+        };
+        let any = |server: &AuthServer, over_udp: bool| {
+            let message = Message::with_query(Name::new("ns.example.org").unwrap(), RRType::ANY);
+            let mut query = Query::new(message, "127.0.0.1:5353".parse().unwrap());
+            query.over_udp = over_udp;
+            let query = server.handle_query(query).wait().unwrap();
+            assert!(query.done);
+            assert_eq!(query.message.header.rcode, Rcode::NoError);
+            query.message
+        };
+
+        let server = AuthServer::new(&conf, KeyRing::default()).unwrap();
+        assert_eq!(any(&server, true).header.an_count, 1);
+        assert_eq!(any(&server, false).header.an_count, 2);
+        conf.any_query_mode = AnyQueryMode::Hinfo;
+        let server = AuthServer::new(&conf, KeyRing::default()).unwrap();
+        let response = any(&server, true);
+        assert_eq!(response.header.an_count, 1);
+        let answer = &response.section(SectionType::Answer).unwrap()[0];
+        assert_eq!(answer.typ, RRType::HINFO);
+        assert!(answer.rdatas[0].to_string().contains("RFC8482"));
+        conf.any_query_mode = AnyQueryMode::Full;
+        let server = AuthServer::new(&conf, KeyRing::default()).unwrap();
+        assert_eq!(any(&server, true).header.an_count, 2);
+    }
+
+    #[test]
+    fn test_handle_dnssec_query() {
+        let conf = AuthorityConfig {
+            zones: vec![zone_conf("example", "testdata/signed.example.zone")],
+            ..AuthorityConfig::default()
+        };
+        let server = AuthServer::new(&conf, KeyRing::default()).unwrap();
+        let query = |name: &str, typ: RRType, dnssec_ok: bool| {
+            let mut message = Message::with_query(Name::new(name).unwrap(), typ);
+            message.edns = Some(Edns {
+                versoin: 0,
+                extened_rcode: 0,
+                udp_size: 4096,
+                dnssec_aware: dnssec_ok,
+                options: None,
+            });
+            let query = Query::new(message, "127.0.0.1:5353".parse().unwrap());
+            let query = server.handle_query(query).wait().unwrap();
+            assert!(query.done);
+            query.message
+        };
+
+        let response = query("ns.example", RRType::A, false);
+        assert_eq!(response.header.an_count, 1);
+        assert_eq!(response.header.ns_count, 1);
+        //answer and apex ns are signed
+        let response = query("ns.example", RRType::A, true);
+        assert_eq!(response.header.an_count, 2);
+        assert_eq!(response.header.ns_count, 2);
+        let response = query("www.example", RRType::A, true);
+        assert_eq!(response.header.an_count, 2);
+        let response = query("none.example", RRType::A, true);
+        assert_eq!(response.header.rcode, Rcode::NXDomain);
+        assert_eq!(response.header.ns_count, 2);
+        //nodata is noerror with soa in authority
+        let response = query("ns.example", RRType::MX, true);
+        assert_eq!(response.header.rcode, Rcode::NoError);
+        assert_eq!(response.header.an_count, 0);
+        assert_eq!(response.header.ns_count, 2);
+        //signed delegation carries ds
+        let response = query("www.child.example", RRType::A, true);
+        assert_eq!(response.header.ns_count, 3);
+        let response = query("www.child.example", RRType::A, false);
+        assert_eq!(response.header.ns_count, 1);
+        let response = query("child.example", RRType::DS, true);
+        assert_eq!(response.header.an_count, 2);
+        //explicit rrsig query is answered without do bit
+        let response = query("example", RRType::RRSIG, false);
+        assert_eq!(response.header.an_count, 3);
+        let response = query("example", RRType::ANY, false);
+        assert_eq!(response.header.an_count, 3);
+        //signatures of each type are a separate rrset
+        let response = query("example", RRType::ANY, true);
+        assert_eq!(response.header.an_count, 6);
+    }
+
+    #[test]
+    fn test_handle_axfr() {
+        let conf = AuthorityConfig {
+            zones: vec![zone_conf("example.org", "testdata/example.org.zone")],
+            allow_transfer: vec!["127.0.0.1".to_string()],
+            ..AuthorityConfig::default()
+        };
+        let server = AuthServer::new(&conf, KeyRing::default()).unwrap();
+        let axfr = |name: &str, client: &str| {
+            let message = Message::with_query(Name::new(name).unwrap(), RRType::AXFR);
+            let responses = server
+                .handle_xfr(&Query::new(message, client.parse().unwrap()))
+                .unwrap();
+            responses.collect().wait().unwrap()
+        };
+
+        let responses = axfr("example.org", "127.0.0.1:5353");
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].header.rcode, Rcode::NoError);
+        assert_eq!(responses[0].header.an_count, 14);
+        assert_eq!(
+            axfr("example.org", "127.0.0.2:5353")[0].header.rcode,
+            Rcode::Refused
+        );
+        assert_eq!(
+            axfr("example.com", "127.0.0.1:5353")[0].header.rcode,
+            Rcode::NotAuth
+        );
+
+        //updates made after the transfer starts aren't sent
+        let responses = server
+            .handle_xfr(&Query::new(
+                Message::with_query(Name::new("example.org").unwrap(), RRType::AXFR),
+                "127.0.0.1:5353".parse().unwrap(),
+            ))
+            .unwrap();
+        let rrset = RRset::from_str("www.example.org. 300 IN A 192.0.2.4").unwrap();
+        server
+            .zones()
+            .write()
+            .unwrap()
+            .update_zone(&Name::new("example.org").unwrap(), |updater| {
+                updater.add_rrset(rrset)
+            })
+            .unwrap();
+        let responses = responses.collect().wait().unwrap();
+        assert_eq!(responses[0].header.an_count, 14);
+        assert_eq!(axfr("example.org", "127.0.0.1:5353")[0].header.an_count, 15);
+
+        let message = Message::with_query(Name::new("example.org").unwrap(), RRType::SOA);
+        let query = Query::new(message, "127.0.0.1:5353".parse().unwrap());
+        assert!(server.handle_xfr(&query).is_none());
+
+        let message = Message::with_query(Name::new("example.org").unwrap(), RRType::AXFR);
+        let mut query = Query::new(message, "127.0.0.1:5353".parse().unwrap());
+        query.over_udp = true;
+        let response = server.handle_query(query).wait().unwrap();
+        assert_eq!(response.message.header.rcode, Rcode::Refused);
+    }
+
+    #[test]
+    fn test_handle_xfr_with_tsig() {
+        let key_conf = |name: &str| TsigKeyConfig {
+            name: name.to_string(),
+            algorithm: "hmac-sha256".to_string(),
+            secret: base64::encode(name.as_bytes()),
+        };
+        let keyring = KeyRing::new(&[key_conf("xfr-key"), key_conf("other-key")]).unwrap();
+        let mut conf = AuthorityConfig {
+            zones: vec![zone_conf("example.org", "testdata/example.org.zone")],
+            ..AuthorityConfig::default()
+        };
+        conf.zones[0].allow_transfer_keys = vec!["XFR-KEY".to_string()];
+        let server = AuthServer::new(&conf, keyring.clone()).unwrap();
+        let axfr = |key: Option<&str>| {
+            let message = Message::with_query(Name::new("example.org").unwrap(), RRType::AXFR);
+            let mut render = MessageRender::new();
+            message.rend(&mut render);
+            let mut wire = render.take_data();
+            if let Some(key) = key {
+                let key = KeyRing::new(&[key_conf(key)])
+                    .unwrap()
+                    .get_key(key)
+                    .unwrap();
+                TsigSigner::new(key).sign(&mut wire);
+            }
+            let query =
+                Query::from_wire(&wire, "127.0.0.1:5353".parse().unwrap(), &keyring).unwrap();
+            let responses = server.handle_xfr(&query).unwrap();
+            responses.collect().wait().unwrap()[0].header.rcode
+        };
+
+        assert_eq!(axfr(None), Rcode::Refused);
+        assert_eq!(axfr(Some("xfr-key")), Rcode::NoError);
+        assert_eq!(axfr(Some("other-key")), Rcode::Refused);
+        assert_eq!(axfr(Some("unknown-key")), Rcode::NotAuth);
+
+        conf.zones[0].allow_update_keys = vec!["unknown-key".to_string()];
+        assert!(AuthServer::new(&conf, keyring).is_err());
+    }
+
+    #[test]
+    fn test_handle_notify() {
+        let mut conf = AuthorityConfig {
+            zones: vec![zone_conf("example.org", "testdata/example.org.zone")],
+            ..AuthorityConfig::default()
+        };
+        conf.zones[0].notify = vec!["10.0.0.1".to_string(), "10.0.0.2:5353".to_string()];
+        let server = AuthServer::new(&conf, KeyRing::default()).unwrap();
+
+        //notify is only accepted by secondary zone
+        let mut message = Message::with_query(Name::new("example.org").unwrap(), RRType::SOA);
+        MessageBuilder::new(&mut message)
+            .opcode(Opcode::Notify)
+            .done();
+        let query = Query::new(message, "127.0.0.1:5353".parse().unwrap());
+        let query = server.handle_query(query).wait().unwrap();
+        assert!(query.done);
+        assert_eq!(query.message.header.rcode, Rcode::NotAuth);
+
+        conf.zones[0].notify = vec!["10.0.0.1:port".to_string()];
+        assert!(AuthServer::new(&conf, KeyRing::default()).is_err());
+    }
+
+    #[test]
+    fn test_handle_update() {
+        let mut conf = AuthorityConfig {
+            zones: vec![zone_conf("example.org", "testdata/example.org.zone")],
+            allow_update: vec!["127.0.0.1".to_string()],
+            ..AuthorityConfig::default()
+        };
+        let server = AuthServer::new(&conf, KeyRing::default()).unwrap();
+        let update = |client: &str| {
+            let mut message = Message::with_query(Name::new("example.org").unwrap(), RRType::SOA);
+            MessageBuilder::new(&mut message)
+                .opcode(Opcode::Update)
+                .add_auth(RRset::from_str("mail.example.org. 300 IN A 192.0.2.10").unwrap())
+                .done();
+            let query = Query::new(message, client.parse().unwrap());
+            server.handle_query(query).wait().unwrap()
+        };
+
+        let query = update("127.0.0.2:5353");
+        assert!(query.done);
+        assert_eq!(query.message.header.rcode, Rcode::Refused);
+        let query = update("127.0.0.1:5353");
+        assert_eq!(query.message.header.rcode, Rcode::NoError);
+        assert_eq!(query.message.header.ns_count, 0);
+        let soa = server
+            .zones()
+            .read()
+            .unwrap()
+            .get_soa(&Name::new("example.org").unwrap())
+            .unwrap();
+        assert_eq!(get_serial(&soa), 101);
+
+        conf.allow_update = vec!["127.0.0.1:53".to_string()];
+        assert!(AuthServer::new(&conf, KeyRing::default()).is_err());
+    }
+
+    #[test]
+    fn test_handle_ixfr() {
+        let conf = AuthorityConfig {
+            zones: vec![zone_conf("example.org", "testdata/example.org.zone")],
+            allow_transfer: vec!["127.0.0.1".to_string()],
+            ..AuthorityConfig::default()
+        };
+        let server = AuthServer::new(&conf, KeyRing::default()).unwrap();
+        let zone = Name::new("example.org").unwrap();
+        let rrset = RRset::from_str("www.example.org. 300 IN A 192.0.2.4").unwrap();
+        server
+            .zones()
+            .write()
+            .unwrap()
+            .update_zone(&zone, |updater| updater.add_rrset(rrset))
+            .unwrap();
+
+        let ixfr = |serial: u32| {
+            let mut message = Message::with_query(zone.clone(), RRType::IXFR);
+            let soa = RRset::from_str(&format!(
+                "example.org. 300 IN SOA xxx.net. ns.example.org. {} 1800 900 604800 86400",
+                serial
+            ))
+            .unwrap();
+            MessageBuilder::new(&mut message).add_auth(soa).done();
+            let responses = server
+                .handle_xfr(&Query::new(message, "127.0.0.1:5353".parse().unwrap()))
+                .unwrap();
+            responses.collect().wait().unwrap()[0].header.an_count
+        };
+        //soa, old soa, new soa, added record and soa
+        assert_eq!(ixfr(100), 5);
+        assert_eq!(ixfr(101), 1);
+        //full transfer
+        assert_eq!(ixfr(99), 15);
+    }
+}
@@ -0,0 +1,581 @@
+use super::{error::AuthError, journal::date_of};
+use crate::{
+    config::DnssecKeyConfig,
+    server::{name_to_wire, read_name, wire_to_string},
+};
+use failure::Result;
+use r53::{Message, MessageBuilder, MessageRender, Name, RData, RRClass, RRTtl, RRType, RRset};
+use ring::{
+    digest,
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use std::fs;
+
+const HEADER_LEN: usize = 12;
+const ZONE_KEY_FLAG: u16 = 256;
+const SEP_FLAG: u16 = 1;
+const DNSKEY_PROTOCOL: u8 = 3;
+const DS_DIGEST_SHA256: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnssecAlgorithm {
+    EcdsaP256Sha256 = 13,
+    Ed25519 = 15,
+}
+
+impl DnssecAlgorithm {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_ref() {
+            "ecdsap256sha256" => Some(DnssecAlgorithm::EcdsaP256Sha256),
+            "ed25519" => Some(DnssecAlgorithm::Ed25519),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DnssecAlgorithm::EcdsaP256Sha256 => "ecdsap256sha256",
+            DnssecAlgorithm::Ed25519 => "ed25519",
+        }
+    }
+}
+
+//new private key in pkcs8 format
+pub fn generate_pkcs8(algorithm: DnssecAlgorithm) -> std::result::Result<Vec<u8>, String> {
+    let rng = SystemRandom::new();
+    let pkcs8 = match algorithm {
+        DnssecAlgorithm::EcdsaP256Sha256 => {
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+        }
+        DnssecAlgorithm::Ed25519 => Ed25519KeyPair::generate_pkcs8(&rng),
+    }
+    .map_err(|e| e.to_string())?;
+    Ok(pkcs8.as_ref().to_vec())
+}
+
+enum SigningKey {
+    Ecdsa(EcdsaKeyPair),
+    Ed25519(Ed25519KeyPair),
+}
+
+pub struct DnssecKey {
+    algorithm: DnssecAlgorithm,
+    ksk: bool,
+    signing_key: SigningKey,
+    public_key: Vec<u8>,
+    tag: u16,
+}
+
+impl DnssecKey {
+    //private key is in pkcs8 format, pem file is decoded to der first
+    pub fn load(conf: &DnssecKeyConfig) -> Result<Self> {
+        let invalid_key = |reason: String| -> failure::Error {
+            AuthError::InvalidDnssecKey(conf.file.clone(), reason).into()
+        };
+        let algorithm = DnssecAlgorithm::from_name(&conf.algorithm)
+            .ok_or_else(|| invalid_key(format!("unknown algorithm {}", conf.algorithm)))?;
+        let content = fs::read(&conf.file).map_err(|e| invalid_key(e.to_string()))?;
+        let der = if content.starts_with(b"-----BEGIN") {
+            decode_pem(&content).map_err(invalid_key)?
+        } else {
+            content
+        };
+        DnssecKey::from_pkcs8(algorithm, &der, conf.ksk).map_err(invalid_key)
+    }
+
+    pub fn from_pkcs8(
+        algorithm: DnssecAlgorithm,
+        pkcs8: &[u8],
+        ksk: bool,
+    ) -> std::result::Result<Self, String> {
+        let (signing_key, public_key) = match algorithm {
+            DnssecAlgorithm::EcdsaP256Sha256 => {
+                let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8)
+                    .map_err(|e| e.to_string())?;
+                //uncompressed point prefix isn't in dnskey, rfc6605
+                let public_key = key.public_key().as_ref()[1..].to_vec();
+                (SigningKey::Ecdsa(key), public_key)
+            }
+            DnssecAlgorithm::Ed25519 => {
+                let key =
+                    Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8).map_err(|e| e.to_string())?;
+                let public_key = key.public_key().as_ref().to_vec();
+                (SigningKey::Ed25519(key), public_key)
+            }
+        };
+        let mut key = DnssecKey {
+            algorithm,
+            ksk,
+            signing_key,
+            public_key,
+            tag: 0,
+        };
+        key.tag = key_tag(&key.dnskey_wire());
+        Ok(key)
+    }
+
+    pub fn is_ksk(&self) -> bool {
+        self.ksk
+    }
+
+    pub fn tag(&self) -> u16 {
+        self.tag
+    }
+
+    pub fn flags(&self) -> u16 {
+        if self.ksk {
+            ZONE_KEY_FLAG | SEP_FLAG
+        } else {
+            ZONE_KEY_FLAG
+        }
+    }
+
+    fn dnskey_wire(&self) -> Vec<u8> {
+        let mut wire = Vec::with_capacity(4 + self.public_key.len());
+        wire.extend_from_slice(&self.flags().to_be_bytes());
+        wire.push(DNSKEY_PROTOCOL);
+        wire.push(self.algorithm as u8);
+        wire.extend_from_slice(&self.public_key);
+        wire
+    }
+
+    pub fn dnskey(&self) -> Result<RData> {
+        RData::from_str(RRType::DNSKEY, &self.dnskey_text())
+    }
+
+    //cdnskey has the same rdata as dnskey, rfc7344
+    pub fn cdnskey(&self) -> Result<RData> {
+        RData::from_str(RRType::CDNSKEY, &self.dnskey_text())
+    }
+
+    //digest is sha256 over the owner and the dnskey rdata, rfc4509
+    pub fn cds(&self, owner: &Name) -> Result<RData> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&name_to_wire(owner));
+        data.extend_from_slice(&self.dnskey_wire());
+        let digest = digest::digest(&digest::SHA256, &data);
+        let digest = digest
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<String>();
+        RData::from_str(
+            RRType::CDS,
+            &format!(
+                "{} {} {} {}",
+                self.tag, self.algorithm as u8, DS_DIGEST_SHA256, digest
+            ),
+        )
+    }
+
+    fn dnskey_text(&self) -> String {
+        format!(
+            "{} {} {} {}",
+            self.flags(),
+            DNSKEY_PROTOCOL,
+            self.algorithm as u8,
+            base64::encode(&self.public_key)
+        )
+    }
+
+    fn sign(&self, data: &[u8]) -> std::result::Result<Vec<u8>, String> {
+        match self.signing_key {
+            SigningKey::Ecdsa(ref key) => key
+                .sign(&SystemRandom::new(), data)
+                .map(|signature| signature.as_ref().to_vec())
+                .map_err(|e| e.to_string()),
+            SigningKey::Ed25519(ref key) => Ok(key.sign(data).as_ref().to_vec()),
+        }
+    }
+
+    //signature is calculated over the rrsig rdata without the signature
+    //and the rrset in canonical form, rfc4034 section 3.1.8.1
+    pub fn sign_rrset(
+        &self,
+        rrset: &RRset,
+        signer: &Name,
+        inception: u64,
+        expiration: u64,
+    ) -> Result<RData> {
+        let sign_failed = |reason: String| -> failure::Error {
+            AuthError::SignFailed(rrset.name.to_string(), reason).into()
+        };
+        let canonical = CanonicalRRset::new(rrset).map_err(sign_failed)?;
+        let labels = label_count(&rrset.name);
+        let signer = name_to_wire(signer);
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&canonical.typ);
+        data.push(self.algorithm as u8);
+        data.push(labels);
+        data.extend_from_slice(&rrset.ttl.0.to_be_bytes());
+        data.extend_from_slice(&(expiration as u32).to_be_bytes());
+        data.extend_from_slice(&(inception as u32).to_be_bytes());
+        data.extend_from_slice(&self.tag.to_be_bytes());
+        data.extend_from_slice(&signer);
+        canonical.write_records(&mut data, rrset.ttl);
+
+        let signature = self.sign(&data).map_err(sign_failed)?;
+        RData::from_str(
+            RRType::RRSIG,
+            &format!(
+                "{} {} {} {} {} {} {} {} {}",
+                rrset.typ,
+                self.algorithm as u8,
+                labels,
+                rrset.ttl.0,
+                format_time(expiration),
+                format_time(inception),
+                self.tag,
+                wire_to_string(&signer),
+                base64::encode(&signature)
+            ),
+        )
+    }
+}
+
+//rrset in canonical form defined in rfc4034 section 6, owner and names in
+//rdata are lowercased and uncompressed, rdatas are sorted
+struct CanonicalRRset {
+    owner: Vec<u8>,
+    typ: [u8; 2],
+    class: [u8; 2],
+    rdatas: Vec<Vec<u8>>,
+}
+
+impl CanonicalRRset {
+    //rrset is rendered into a message and parsed back, so rdata could be
+    //handled as wire format without knowing its fields
+    fn new(rrset: &RRset) -> std::result::Result<Self, String> {
+        let mut message = Message::with_query(rrset.name.clone(), rrset.typ);
+        MessageBuilder::new(&mut message)
+            .add_answer(rrset.clone())
+            .done();
+        let mut render = MessageRender::new();
+        message.rend(&mut render);
+        let wire = render.data();
+
+        let truncated = || "rendered rrset is truncated".to_string();
+        let (_, mut pos) = read_name(wire, HEADER_LEN).ok_or_else(truncated)?;
+        pos += 4;
+        let mut owner = Vec::new();
+        let mut typ = [0; 2];
+        let mut class = [0; 2];
+        let mut rdatas = Vec::new();
+        for _ in 0..rrset.rdatas.len() {
+            let (name, name_end) = read_name(wire, pos).ok_or_else(truncated)?;
+            let fields = wire.get(name_end..name_end + 10).ok_or_else(truncated)?;
+            let rdlen = u16::from_be_bytes([fields[8], fields[9]]) as usize;
+            let start = name_end + 10;
+            let end = start + rdlen;
+            if end > wire.len() {
+                return Err(truncated());
+            }
+            owner = name;
+            typ.copy_from_slice(&fields[0..2]);
+            class.copy_from_slice(&fields[2..4]);
+            rdatas.push(canonical_rdata(wire, rrset.typ, start, end).ok_or_else(truncated)?);
+            pos = end;
+        }
+        rdatas.sort();
+        rdatas.dedup();
+        Ok(CanonicalRRset {
+            owner,
+            typ,
+            class,
+            rdatas,
+        })
+    }
+
+    fn write_records(&self, buf: &mut Vec<u8>, ttl: RRTtl) {
+        for rdata in &self.rdatas {
+            buf.extend_from_slice(&self.owner);
+            buf.extend_from_slice(&self.typ);
+            buf.extend_from_slice(&self.class);
+            buf.extend_from_slice(&ttl.0.to_be_bytes());
+            buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            buf.extend_from_slice(rdata);
+        }
+    }
+}
+
+//types with names in rdata which have no constant in RRType
+const MINFO: u16 = 14;
+const RP: u16 = 17;
+const AFSDB: u16 = 18;
+const PX: u16 = 26;
+const NAPTR: u16 = 35;
+const KX: u16 = 36;
+
+//names in rdata of these types are lowercased, bytes and character
+//strings before the names and bytes after them are copied as is
+fn canonical_rdata(wire: &[u8], typ: RRType, start: usize, end: usize) -> Option<Vec<u8>> {
+    let (prefix_len, string_count, name_count) = if typ == RRType::NS
+        || typ == RRType::CNAME
+        || typ == RRType::PTR
+        || typ == RRType::DNAME
+    {
+        (0, 0, 1)
+    } else if typ == RRType::MX {
+        (2, 0, 1)
+    } else if typ == RRType::SOA {
+        (0, 0, 2)
+    } else if typ == RRType::SRV {
+        (6, 0, 1)
+    } else if typ == RRType::RRSIG {
+        (18, 0, 1)
+    } else {
+        match typ.to_u16() {
+            MINFO | RP => (0, 0, 2),
+            AFSDB | KX => (2, 0, 1),
+            PX => (2, 0, 2),
+            //order, preference, flags, services and regexp before replacement
+            NAPTR => (4, 3, 1),
+            _ => (0, 0, 0),
+        }
+    };
+
+    let mut rdata = Vec::with_capacity(end - start);
+    let mut pos = start + prefix_len;
+    for _ in 0..string_count {
+        pos += usize::from(*wire.get(pos)?) + 1;
+    }
+    rdata.extend_from_slice(wire.get(start..pos)?);
+    for _ in 0..name_count {
+        let (name, name_end) = read_name(wire, pos)?;
+        rdata.extend_from_slice(&name);
+        pos = name_end;
+    }
+    rdata.extend_from_slice(wire.get(pos..end)?);
+    Some(rdata)
+}
+
+//rfc4034 appendix b
+pub fn key_tag(dnskey: &[u8]) -> u16 {
+    let mut ac: u32 = 0;
+    for (i, b) in dnskey.iter().enumerate() {
+        if i & 1 == 0 {
+            ac += u32::from(*b) << 8;
+        } else {
+            ac += u32::from(*b);
+        }
+    }
+    ac += (ac >> 16) & 0xffff;
+    (ac & 0xffff) as u16
+}
+
+//root label and the leftmost wildcard label aren't counted
+fn label_count(name: &Name) -> u8 {
+    let name = name.to_string();
+    let count = name.split('.').filter(|label| !label.is_empty()).count();
+    if name.starts_with("*.") {
+        (count - 1) as u8
+    } else {
+        count as u8
+    }
+}
+
+//YYYYMMDDHHmmSS in utc
+fn format_time(secs: u64) -> String {
+    let seconds = secs % 86400;
+    format!(
+        "{}{:02}{:02}{:02}",
+        date_of(secs),
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+fn decode_pem(content: &[u8]) -> std::result::Result<Vec<u8>, String> {
+    let content = String::from_utf8_lossy(content);
+    let body = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("-----"))
+        .collect::<String>();
+    base64::decode(&body).map_err(|e| e.to_string())
+}
+
+//dnskey rrset of the zone, the ttl follows the soa
+pub fn make_dnskey_rrset<'a>(
+    origin: &Name,
+    ttl: RRTtl,
+    keys: impl Iterator<Item = &'a DnssecKey>,
+) -> Result<RRset> {
+    Ok(RRset {
+        name: origin.clone(),
+        typ: RRType::DNSKEY,
+        class: RRClass::IN,
+        ttl,
+        rdatas: keys
+            .map(|key| key.dnskey())
+            .collect::<Result<Vec<RData>>>()?,
+    })
+}
+
+//ksks the parent should have ds for, empty if there is no ksk
+pub fn make_cds_rrsets(origin: &Name, ttl: RRTtl, keys: &[DnssecKey]) -> Result<Vec<RRset>> {
+    let ksks = keys.iter().filter(|key| key.is_ksk()).collect::<Vec<_>>();
+    if ksks.is_empty() {
+        return Ok(Vec::new());
+    }
+    let make_rrset = |typ: RRType, rdatas: Vec<RData>| RRset {
+        name: origin.clone(),
+        typ,
+        class: RRClass::IN,
+        ttl,
+        rdatas,
+    };
+    Ok(vec![
+        make_rrset(
+            RRType::CDS,
+            ksks.iter()
+                .map(|key| key.cds(origin))
+                .collect::<Result<Vec<RData>>>()?,
+        ),
+        make_rrset(
+            RRType::CDNSKEY,
+            ksks.iter()
+                .map(|key| key.cdnskey())
+                .collect::<Result<Vec<RData>>>()?,
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{
+        UnparsedPublicKey, VerificationAlgorithm, ECDSA_P256_SHA256_FIXED, ED25519,
+    };
+    use std::str::FromStr;
+
+    #[test]
+    fn test_key_tag() {
+        //dnskey in rfc4034 section 2.3, whose tag is 60485
+        let dnskey = base64::decode(
+            "AQOeiiR0GOMYkDshWoSKz9XzfwJr1AYtsmx3TGkJaNXVbfi/2pHm822aJ5iI9BMzNXxeYCmZDRD99WYwYqUSdjMmmAphXdvxegXd/M5+X7OrzKBaMbCVdFLUUh6DhweJBjEVv5f2wwjM9XzcnOf+EPbtG9DMBmADjFDc2w/rljwvFw==",
+        )
+        .unwrap();
+        let mut wire = vec![1, 0, 3, 5];
+        wire.extend_from_slice(&dnskey);
+        assert_eq!(key_tag(&wire), 60485);
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0), "19700101000000");
+        assert_eq!(format_time(1_136_214_245), "20060102150405");
+        assert_eq!(label_count(&Name::new("*.a.example.org").unwrap()), 3);
+        assert_eq!(label_count(&Name::new("a.example.org").unwrap()), 3);
+    }
+
+    //signed data is rebuilt from the returned rrsig, and the signature in
+    //it is verified by the public key in dnskey, rfc4034 section 3.1.8.1
+    fn verify_rrsig(key: &DnssecKey, rrset: &RRset) {
+        let signer = Name::new("Example.org").unwrap();
+        let rrsig = RRset {
+            name: rrset.name.clone(),
+            typ: RRType::RRSIG,
+            class: RRClass::IN,
+            ttl: rrset.ttl,
+            rdatas: vec![key.sign_rrset(rrset, &signer, 0, 3600).unwrap()],
+        };
+        let rdata = CanonicalRRset::new(&rrsig).unwrap().rdatas.remove(0);
+        let canonical = CanonicalRRset::new(rrset).unwrap();
+        assert_eq!(&rdata[0..2], &canonical.typ);
+        assert_eq!(rdata[2], key.algorithm as u8);
+        assert_eq!(rdata[3], 3);
+        assert_eq!(&rdata[4..8], &300u32.to_be_bytes());
+        assert_eq!(&rdata[8..12], &3600u32.to_be_bytes());
+        assert_eq!(&rdata[12..16], &0u32.to_be_bytes());
+        assert_eq!(&rdata[16..18], &key.tag().to_be_bytes());
+        let (signer, signature_pos) = read_name(&rdata, 18).unwrap();
+        assert_eq!(signer, b"\x07example\x03org\x00".to_vec());
+
+        let mut data = rdata[..signature_pos].to_vec();
+        canonical.write_records(&mut data, rrset.ttl);
+        let (algorithm, public_key): (&'static dyn VerificationAlgorithm, Vec<u8>) =
+            match key.algorithm {
+                DnssecAlgorithm::Ed25519 => (&ED25519, key.public_key.clone()),
+                DnssecAlgorithm::EcdsaP256Sha256 => {
+                    let mut public_key = vec![4];
+                    public_key.extend_from_slice(&key.public_key);
+                    (&ECDSA_P256_SHA256_FIXED, public_key)
+                }
+            };
+        UnparsedPublicKey::new(algorithm, &public_key)
+            .verify(&data, &rdata[signature_pos..])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_sign_rrset() {
+        let rrset = RRset::from_str("WWW.example.org. 300 IN CNAME Web.Example.org.").unwrap();
+        let canonical = CanonicalRRset::new(&rrset).unwrap();
+        assert_eq!(canonical.owner, b"\x03www\x07example\x03org\x00".to_vec());
+        assert_eq!(
+            canonical.rdatas,
+            vec![b"\x03web\x07example\x03org\x00".to_vec()]
+        );
+
+        for &algorithm in &[DnssecAlgorithm::Ed25519, DnssecAlgorithm::EcdsaP256Sha256] {
+            let pkcs8 = generate_pkcs8(algorithm).unwrap();
+            let key = DnssecKey::from_pkcs8(algorithm, &pkcs8, false).unwrap();
+            assert_eq!(key.flags(), 256);
+            verify_rrsig(&key, &rrset);
+        }
+    }
+
+    #[test]
+    fn test_canonical_rdata() {
+        let canonical =
+            |typ: u16, wire: &[u8]| canonical_rdata(wire, RRType::new(typ), 0, wire.len()).unwrap();
+        let rp = b"\x05Admin\x07Example\x00\x04Info\x07Example\x00";
+        assert_eq!(
+            canonical(RP, rp),
+            b"\x05admin\x07example\x00\x04info\x07example\x00".to_vec()
+        );
+        let px = b"\x00\x0a\x03Map\x00\x04X400\x00";
+        assert_eq!(
+            canonical(PX, px),
+            b"\x00\x0a\x03map\x00\x04x400\x00".to_vec()
+        );
+        //character strings of naptr aren't changed
+        let naptr = b"\x00\x64\x00\x0a\x01U\x07E2U+sip\x00\x04_Sip\x07Example\x00";
+        assert_eq!(
+            canonical(NAPTR, naptr),
+            b"\x00\x64\x00\x0a\x01U\x07E2U+sip\x00\x04_sip\x07example\x00".to_vec()
+        );
+        let kx = b"\x00\x0a\x02KX\x00";
+        assert_eq!(canonical(KX, kx), b"\x00\x0a\x02kx\x00".to_vec());
+    }
+
+    #[test]
+    fn test_cds() {
+        //ksk in rfc8080 section 6.1, pkcs8 v1 is the prefix and the seed
+        let mut pkcs8 = vec![
+            0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22,
+            0x04, 0x20,
+        ];
+        pkcs8.extend(base64::decode("ODIyNjAzODQ2MjgwODAxMjI2NDUxOTAyMDQxNDIyNjI=").unwrap());
+        let key = DnssecKey::from_pkcs8(DnssecAlgorithm::Ed25519, &pkcs8, true).unwrap();
+        assert_eq!(key.tag(), 3613);
+        assert_eq!(
+            base64::encode(&key.public_key),
+            "l02Woi0iS8Aa25FQkUd9RMzZHJpBoRQwAQEX1SxZJA4="
+        );
+        let cds = key.cds(&Name::new("example.com").unwrap()).unwrap();
+        assert_eq!(
+            cds.to_string().to_uppercase(),
+            "3613 15 2 3AA5AB37EFCE57F737FC1627013FEE07BDF241BD10F3B1964AB55C78E79A304B"
+        );
+
+        let rrsets =
+            make_cds_rrsets(&Name::new("example.com").unwrap(), RRTtl(3600), &[key]).unwrap();
+        assert_eq!(rrsets.len(), 2);
+        assert_eq!(rrsets[1].typ, RRType::CDNSKEY);
+    }
+}
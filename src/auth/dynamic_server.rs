@@ -1,6 +1,7 @@
 use super::{
-    error::AuthError,
+    journal::JournalUpdater,
     proto::{self, dynamic_update_interface, dynamic_update_interface_grpc},
+    signer::resign_delegations,
     zones::AuthZone,
 };
 use datasrc::ZoneUpdater;
@@ -15,7 +16,8 @@ pub struct DynamicUpdateHandler {
     zones: Arc<RwLock<AuthZone>>,
 }
 
-//note: current implementation doesn't support transaction
+//note: current implementation doesn't support transaction, all the changes
+//of one request are made in one zone update, so soa serial is changed once
 impl DynamicUpdateHandler {
     pub fn new(zones: Arc<RwLock<AuthZone>>) -> Self {
         DynamicUpdateHandler { zones }
@@ -41,61 +43,55 @@ impl DynamicUpdateHandler {
         Ok(())
     }
 
+    //delegations changed by the update are signed after the write lock is
+    //released
+    fn update_zone<F>(&self, zone: &Name, update: F) -> Result<()>
+    where
+        F: FnOnce(&mut JournalUpdater) -> Result<()>,
+    {
+        let result = self.zones.write().unwrap().update_zone(zone, update);
+        resign_delegations(&self.zones);
+        result
+    }
+
     fn do_add_rrsets(&mut self, zone: &Name, rrsets: Vec<RRset>) -> Result<()> {
-        let mut zones = self.zones.write().unwrap();
-        if let Some(zone) = zones.get_exact_zone(zone) {
+        self.update_zone(zone, |updater| {
             for rrset in rrsets {
-                zone.add_rrset(rrset)?;
+                updater.add_rrset(rrset)?;
             }
             Ok(())
-        } else {
-            Err(AuthError::UnknownZone(zone.to_string()).into())
-        }
+        })
     }
 
     fn do_delete_domains(&mut self, zone: &Name, names: &Vec<Name>) -> Result<()> {
-        let mut zones = self.zones.write().unwrap();
-        if let Some(zone) = zones.get_exact_zone(&zone) {
+        self.update_zone(zone, |updater| {
             for name in names {
-                zone.delete_domain(name)?;
+                updater.delete_domain(name)?;
             }
             Ok(())
-        } else {
-            Err(AuthError::UnknownZone(zone.to_string()).into())
-        }
+        })
     }
 
     fn do_delete_rrsets(&mut self, zone: &Name, rrset_headers: &Vec<(Name, RRType)>) -> Result<()> {
-        let mut zones = self.zones.write().unwrap();
-        if let Some(zone) = zones.get_exact_zone(&zone) {
+        self.update_zone(zone, |updater| {
             for rrset_header in rrset_headers {
-                zone.delete_rrset(&rrset_header.0, rrset_header.1)?;
+                updater.delete_rrset(&rrset_header.0, rrset_header.1)?;
             }
             Ok(())
-        } else {
-            Err(AuthError::UnknownZone(zone.to_string()).into())
-        }
+        })
     }
 
     fn do_delete_rdatas(&mut self, zone: &Name, rrsets: &Vec<RRset>) -> Result<()> {
-        let mut zones = self.zones.write().unwrap();
-        if let Some(zone) = zones.get_exact_zone(&zone) {
+        self.update_zone(zone, |updater| {
             for rrset in rrsets {
-                zone.delete_rdata(rrset)?;
+                updater.delete_rdata(rrset)?;
             }
             Ok(())
-        } else {
-            Err(AuthError::UnknownZone(zone.to_string()).into())
-        }
+        })
     }
 
     fn do_update_rdatas(&mut self, zone: &Name, old_rrset: &RRset, new_rrset: RRset) -> Result<()> {
-        let mut zones = self.zones.write().unwrap();
-        if let Some(zone) = zones.get_exact_zone(&zone) {
-            zone.update_rdata(old_rrset, new_rrset)
-        } else {
-            Err(AuthError::UnknownZone(zone.to_string()).into())
-        }
+        self.update_zone(zone, |updater| updater.update_rdata(old_rrset, new_rrset))
     }
 }
 
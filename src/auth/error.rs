@@ -7,4 +7,52 @@ pub enum AuthError {
 
     #[fail(display = "zone {} doesn't exist", _0)]
     UnknownZone(String),
+
+    #[fail(display = "load zone {} from {} failed: {}", _0, _1, _2)]
+    ZoneLoadFailed(String, String, String),
+
+    #[fail(display = "invalid address {}", _0)]
+    InvalidAddress(String),
+
+    #[fail(display = "journal {} is invalid: {}", _0, _1)]
+    InvalidJournal(String, String),
+
+    #[fail(display = "write journal {} failed: {}", _0, _1)]
+    JournalFailed(String, String),
+
+    #[fail(
+        display = "diff of zone {} from serial {} doesn't match the zone",
+        _0, _1
+    )]
+    InvalidDiff(String, u32),
+
+    #[fail(display = "transfer zone {} from {} failed: {}", _0, _1, _2)]
+    TransferFailed(String, String, String),
+
+    #[fail(display = "invalid zone transfer response: {}", _0)]
+    InvalidXfrResponse(String),
+
+    #[fail(display = "primary is idle too long during zone transfer")]
+    XfrIdleTimeout,
+
+    #[fail(display = "notify zone {} to {} failed: {}", _0, _1, _2)]
+    NotifyFailed(String, String, String),
+
+    #[fail(display = "invalid notify response: {}", _0)]
+    InvalidNotifyResponse(String),
+
+    #[fail(display = "tsig key {} doesn't exist", _0)]
+    UnknownTsigKey(String),
+
+    #[fail(display = "write zone {} to file failed: {}", _0, _1)]
+    ZoneFlushFailed(String, String),
+
+    #[fail(display = "dnssec key {} is invalid: {}", _0, _1)]
+    InvalidDnssecKey(String, String),
+
+    #[fail(display = "sign rrset {} failed: {}", _0, _1)]
+    SignFailed(String, String),
+
+    #[fail(display = "dnssec keys of zone {} failed to store: {}", _0, _1)]
+    KeyStoreFailed(String, String),
 }
@@ -0,0 +1,658 @@
+use super::error::AuthError;
+use crate::config::SerialPolicy;
+use datasrc::{MemoryZone, ZoneFinder, ZoneUpdater};
+use failure::Result;
+use r53::{Name, RData, RRType, RRset};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    mem,
+    path::PathBuf,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+//serial number arithmetic defined in rfc1982
+pub fn serial_lt(a: u32, b: u32) -> bool {
+    a != b && b.wrapping_sub(a) < 0x8000_0000
+}
+
+//serial generated by the policy is only used when it's bigger than the
+//current one, otherwise the current one is increased
+pub fn next_serial(policy: SerialPolicy, serial: u32, now: u64) -> u32 {
+    let candidate = match policy {
+        SerialPolicy::Increment => serial.wrapping_add(1),
+        SerialPolicy::Unixtime => now as u32,
+        SerialPolicy::Date => date_of(now).wrapping_mul(100),
+    };
+    if serial_lt(serial, candidate) {
+        candidate
+    } else {
+        serial.wrapping_add(1)
+    }
+}
+
+//YYYYMMDD of the utc date, days are converted to civil date by the
+//algorithm from http://howardhinnant.github.io/date_algorithms.html
+pub fn date_of(secs: u64) -> u32 {
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days / 146_097;
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year * 10000 + month * 100 + day) as u32
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn get_serial(soa: &RRset) -> u32 {
+    match soa.rdatas[0] {
+        RData::SOA(ref soa) => soa.serial,
+        _ => 0,
+    }
+}
+
+fn set_serial(soa: &mut RRset, serial: u32) {
+    if let RData::SOA(ref mut soa) = soa.rdatas[0] {
+        soa.serial = serial;
+    }
+}
+
+pub fn get_zone_soa(zone: &MemoryZone) -> Option<RRset> {
+    zone.get_rrsets(zone.get_origin())
+        .into_iter()
+        .find(|rrset| rrset.typ == RRType::SOA)
+}
+
+//every record is kept as a rrset with only one rdata
+pub fn split_rrset(rrset: RRset) -> Vec<RRset> {
+    let RRset {
+        name,
+        typ,
+        class,
+        ttl,
+        rdatas,
+    } = rrset;
+    rdatas
+        .into_iter()
+        .map(|rdata| RRset {
+            name: name.clone(),
+            typ,
+            class,
+            ttl,
+            rdatas: vec![rdata],
+        })
+        .collect()
+}
+
+fn is_same_record(a: &RRset, b: &RRset) -> bool {
+    a.typ == b.typ && a.ttl.0 == b.ttl.0 && a.name.eq(&b.name) && a.rdatas[0].eq(&b.rdatas[0])
+}
+
+//changes made by one update, the serial of new soa is bigger than the old one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diff {
+    pub old_soa: RRset,
+    pub deleted: Vec<RRset>,
+    pub new_soa: RRset,
+    pub added: Vec<RRset>,
+}
+
+impl Diff {
+    pub fn from_serial(&self) -> u32 {
+        get_serial(&self.old_soa)
+    }
+
+    pub fn to_serial(&self) -> u32 {
+        get_serial(&self.new_soa)
+    }
+
+    fn record_count(&self) -> usize {
+        self.deleted.len() + self.added.len() + 2
+    }
+
+    pub fn apply<U: ZoneUpdater>(&self, updater: &mut U) -> Result<()> {
+        for rrset in &self.deleted {
+            updater.delete_rdata(rrset)?;
+        }
+        for rrset in &self.added {
+            updater.add_rrset(rrset.clone())?;
+        }
+        updater.add_rrset(self.new_soa.clone())
+    }
+}
+
+//record the changes by comparing the rrsets of the changed name before
+//and after each update, changes canceled by later updates are dropped
+pub struct JournalUpdater<'a> {
+    zone: &'a mut MemoryZone,
+    old_soa: Option<RRset>,
+    deleted: Vec<RRset>,
+    added: Vec<RRset>,
+}
+
+impl<'a> JournalUpdater<'a> {
+    pub fn new(zone: &'a mut MemoryZone) -> Self {
+        let old_soa = get_zone_soa(zone);
+        JournalUpdater {
+            zone,
+            old_soa,
+            deleted: Vec::new(),
+            added: Vec::new(),
+        }
+    }
+
+    pub fn zone(&self) -> &MemoryZone {
+        self.zone
+    }
+
+    //revert the changes made through the updater, so nothing is committed
+    pub fn rollback(&mut self) -> Result<()> {
+        for record in mem::replace(&mut self.added, Vec::new()) {
+            self.zone.delete_rdata(&record)?;
+        }
+        for record in mem::replace(&mut self.deleted, Vec::new()) {
+            self.zone.add_rrset(record)?;
+        }
+        if let Some(ref soa) = self.old_soa {
+            self.zone.add_rrset(soa.clone())?;
+        }
+        Ok(())
+    }
+
+    fn record<F>(&mut self, name: &Name, update: F) -> Result<()>
+    where
+        F: FnOnce(&mut MemoryZone) -> Result<()>,
+    {
+        let before = self.zone.get_rrsets(name);
+        let result = update(self.zone);
+        let after = self.zone.get_rrsets(name);
+
+        let before = before
+            .into_iter()
+            .filter(|rrset| rrset.typ != RRType::SOA)
+            .flat_map(split_rrset)
+            .collect::<Vec<RRset>>();
+        let after = after
+            .into_iter()
+            .filter(|rrset| rrset.typ != RRType::SOA)
+            .flat_map(split_rrset)
+            .collect::<Vec<RRset>>();
+        for record in before.iter() {
+            if !after.iter().any(|r| is_same_record(r, record)) {
+                push_record(&mut self.deleted, &mut self.added, record.clone());
+            }
+        }
+        for record in after.iter() {
+            if !before.iter().any(|r| is_same_record(r, record)) {
+                push_record(&mut self.added, &mut self.deleted, record.clone());
+            }
+        }
+        result
+    }
+
+    //rrsets changed by the updates, soa isn't included
+    pub fn changed_rrsets(&self) -> Vec<(Name, RRType)> {
+        let mut changed: Vec<(Name, RRType)> = Vec::new();
+        for record in self.deleted.iter().chain(self.added.iter()) {
+            if !changed
+                .iter()
+                .any(|(name, typ)| *typ == record.typ && name.eq(&record.name))
+            {
+                changed.push((record.name.clone(), record.typ));
+            }
+        }
+        changed
+    }
+
+    //soa serial is changed by the policy if the zone is changed, unless
+    //it's already increased by the updates, return false if nothing changed
+    pub fn bump_serial(&mut self, policy: SerialPolicy) -> Result<bool> {
+        let old_soa = match self.old_soa {
+            Some(ref soa) => soa,
+            None => return Ok(false),
+        };
+        let mut new_soa = match get_zone_soa(self.zone) {
+            Some(soa) => soa,
+            None => return Ok(false),
+        };
+        let soa_changed = !is_same_record(old_soa, &new_soa);
+        if self.deleted.is_empty() && self.added.is_empty() && !soa_changed {
+            return Ok(false);
+        }
+
+        let old_serial = get_serial(old_soa);
+        if !serial_lt(old_serial, get_serial(&new_soa)) {
+            set_serial(&mut new_soa, next_serial(policy, old_serial, now()));
+            self.zone.add_rrset(new_soa)?;
+        }
+        Ok(true)
+    }
+
+    pub fn commit(mut self, policy: SerialPolicy) -> Option<Diff> {
+        match self.bump_serial(policy) {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => {
+                eprintln!("update soa serial failed: {}", e);
+                return None;
+            }
+        }
+        Some(Diff {
+            old_soa: self.old_soa?,
+            deleted: self.deleted,
+            new_soa: get_zone_soa(self.zone)?,
+            added: self.added,
+        })
+    }
+}
+
+//record which is added after deleted or deleted after added isn't changed
+fn push_record(records: &mut Vec<RRset>, opposite: &mut Vec<RRset>, record: RRset) {
+    if let Some(index) = opposite.iter().position(|r| is_same_record(r, &record)) {
+        opposite.remove(index);
+    } else {
+        records.push(record);
+    }
+}
+
+impl<'a> ZoneUpdater for JournalUpdater<'a> {
+    fn add_rrset(&mut self, rrset: RRset) -> Result<()> {
+        let name = rrset.name.clone();
+        self.record(&name, |zone| zone.add_rrset(rrset))
+    }
+
+    fn delete_rrset(&mut self, name: &Name, typ: RRType) -> Result<()> {
+        self.record(name, |zone| zone.delete_rrset(name, typ))
+    }
+
+    fn delete_rdata(&mut self, rrset: &RRset) -> Result<()> {
+        self.record(&rrset.name, |zone| zone.delete_rdata(rrset))
+    }
+
+    fn update_rdata(&mut self, old_rrset: &RRset, new_rrset: RRset) -> Result<()> {
+        self.record(&old_rrset.name, |zone| {
+            zone.update_rdata(old_rrset, new_rrset)
+        })
+    }
+
+    fn delete_domain(&mut self, name: &Name) -> Result<()> {
+        self.record(name, |zone| zone.delete_domain(name))
+    }
+}
+
+//diffs of one zone in the order of serial, the oldest diffs are discarded
+//when the records exceed the limit. In the journal file, each diff starts
+//with a "diff" line, followed by the deleted records prefixed by "del" and
+//the added records prefixed by "add", the first of which are the soa
+pub struct Journal {
+    path: Option<PathBuf>,
+    max_records: usize,
+    diffs: Vec<Diff>,
+    record_count: usize,
+}
+
+impl Journal {
+    pub fn new(path: Option<PathBuf>, max_records: usize) -> Self {
+        Journal {
+            path,
+            max_records,
+            diffs: Vec::new(),
+            record_count: 0,
+        }
+    }
+
+    //zone loaded from file is brought up to date with the persisted diffs,
+    //journal which doesn't match the zone is discarded
+    pub fn open(path: Option<PathBuf>, max_records: usize, zone: &mut MemoryZone) -> Self {
+        let mut journal = Journal::new(path, max_records);
+        if let Err(e) = journal.recover(zone) {
+            eprintln!("{}, discard it", e);
+            journal.clear();
+        }
+        journal
+    }
+
+    fn recover(&mut self, zone: &mut MemoryZone) -> Result<()> {
+        self.diffs = self.load()?;
+        self.record_count = self.diffs.iter().map(|diff| diff.record_count()).sum();
+        if self.diffs.is_empty() {
+            return Ok(());
+        }
+
+        let serial = get_zone_soa(zone).map(|soa| get_serial(&soa));
+        if serial == self.diffs.last().map(|diff| diff.to_serial()) {
+            return Ok(());
+        }
+        match serial.and_then(|serial| self.get_diffs(serial)) {
+            Some(diffs) => {
+                //zone is kept as loaded from file if any diff fails
+                let mut updater = JournalUpdater::new(zone);
+                if let Err(e) = diffs.iter().try_for_each(|diff| diff.apply(&mut updater)) {
+                    updater.rollback()?;
+                    return Err(e);
+                }
+            }
+            None => self.clear(),
+        }
+        Ok(())
+    }
+
+    //diffs from the serial to the latest, None if they are discarded
+    pub fn get_diffs(&self, serial: u32) -> Option<&[Diff]> {
+        self.diffs
+            .iter()
+            .position(|diff| diff.from_serial() == serial)
+            .map(|index| &self.diffs[index..])
+    }
+
+    pub fn append(&mut self, diff: Diff) -> Result<()> {
+        self.record_count += diff.record_count();
+        self.diffs.push(diff);
+
+        //the latest diff is kept even it exceeds the limit
+        let mut discarded = 0;
+        while self.record_count > self.max_records && discarded + 1 < self.diffs.len() {
+            self.record_count -= self.diffs[discarded].record_count();
+            discarded += 1;
+        }
+        if discarded > 0 {
+            self.diffs.drain(..discarded);
+            self.save()
+        } else {
+            self.save_last()
+        }
+    }
+
+    fn clear(&mut self) {
+        self.diffs.clear();
+        self.record_count = 0;
+        if let Err(e) = self.save() {
+            eprintln!("{}", e);
+        }
+    }
+
+    pub fn remove_file(&self) {
+        if let Some(ref path) = self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    fn load(&self) -> Result<Vec<Diff>> {
+        let path = match self.path {
+            Some(ref path) if path.exists() => path,
+            _ => return Ok(Vec::new()),
+        };
+        let invalid = |reason: String| -> failure::Error {
+            AuthError::InvalidJournal(path.display().to_string(), reason).into()
+        };
+
+        let file = File::open(path).map_err(|e| invalid(e.to_string()))?;
+        let mut diffs = Vec::new();
+        let mut records: Option<(Vec<RRset>, Vec<RRset>)> = None;
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| invalid(e.to_string()))?;
+            if line == "diff" {
+                if let Some((deleted, added)) = records.take() {
+                    diffs.push(
+                        make_diff(deleted, added)
+                            .ok_or_else(|| invalid("diff without soa".to_string()))?,
+                    );
+                }
+                records = Some((Vec::new(), Vec::new()));
+                continue;
+            }
+
+            let (deleted, added) = records
+                .as_mut()
+                .ok_or_else(|| invalid(format!("record {} isn't in diff", line)))?;
+            let (target, record) = if line.starts_with("del ") {
+                (deleted, &line[4..])
+            } else if line.starts_with("add ") {
+                (added, &line[4..])
+            } else {
+                return Err(invalid(format!("unknown line {}", line)));
+            };
+            target.push(RRset::from_str(record).map_err(|e| invalid(e.to_string()))?);
+        }
+        if let Some((deleted, added)) = records {
+            diffs.push(
+                make_diff(deleted, added).ok_or_else(|| invalid("diff without soa".to_string()))?,
+            );
+        }
+        Ok(diffs)
+    }
+
+    //write to a temporary file then rename, so journal won't be torn
+    fn save(&self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let result = File::create(&tmp_path)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                for diff in &self.diffs {
+                    write_diff(&mut writer, diff)?;
+                }
+                writer.flush()
+            })
+            .and_then(|_| fs::rename(&tmp_path, path));
+        result
+            .map_err(|e| AuthError::JournalFailed(path.display().to_string(), e.to_string()).into())
+    }
+
+    fn save_last(&self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                write_diff(&mut writer, self.diffs.last().unwrap())?;
+                writer.flush()
+            });
+        result
+            .map_err(|e| AuthError::JournalFailed(path.display().to_string(), e.to_string()).into())
+    }
+}
+
+fn make_diff(mut deleted: Vec<RRset>, mut added: Vec<RRset>) -> Option<Diff> {
+    if deleted.is_empty() || added.is_empty() {
+        return None;
+    }
+    let old_soa = deleted.remove(0);
+    let new_soa = added.remove(0);
+    if old_soa.typ != RRType::SOA || new_soa.typ != RRType::SOA {
+        return None;
+    }
+    Some(Diff {
+        old_soa,
+        deleted,
+        new_soa,
+        added,
+    })
+}
+
+fn write_diff<W: Write>(writer: &mut W, diff: &Diff) -> io::Result<()> {
+    writeln!(writer, "diff")?;
+    for rrset in Some(&diff.old_soa).into_iter().chain(diff.deleted.iter()) {
+        writeln!(writer, "del {}", record_to_string(rrset))?;
+    }
+    for rrset in Some(&diff.new_soa).into_iter().chain(diff.added.iter()) {
+        writeln!(writer, "add {}", record_to_string(rrset))?;
+    }
+    Ok(())
+}
+
+pub fn record_to_string(rrset: &RRset) -> String {
+    format!(
+        "{} {} IN {} {}",
+        rrset.name, rrset.ttl.0, rrset.typ, rrset.rdatas[0]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datasrc::load_zone;
+    use std::{env, process};
+
+    const ZONE: &str =
+        "example.org. 300 IN SOA ns.example.org. root.example.org. 100 1800 900 604800 86400
+example.org. 300 IN NS ns.example.org.
+ns.example.org. 300 IN A 192.0.2.1
+www.example.org. 300 IN A 192.0.2.2";
+
+    fn add_www(zone: &mut MemoryZone, ip: &str) -> Option<Diff> {
+        let rrset = RRset::from_str(&format!("www.example.org. 300 IN A {}", ip)).unwrap();
+        let mut updater = JournalUpdater::new(zone);
+        updater.add_rrset(rrset).unwrap();
+        updater.commit(SerialPolicy::Increment)
+    }
+
+    #[test]
+    fn test_record_changes() {
+        let mut zone = load_zone(Name::new("example.org").unwrap(), ZONE).unwrap();
+        let diff = add_www(&mut zone, "192.0.2.3").unwrap();
+        assert_eq!(diff.from_serial(), 100);
+        assert_eq!(diff.to_serial(), 101);
+        assert!(diff.deleted.is_empty());
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(get_serial(&get_zone_soa(&zone).unwrap()), 101);
+
+        let mut updater = JournalUpdater::new(&mut zone);
+        let www = Name::new("www.example.org").unwrap();
+        let rrset = RRset::from_str("www.example.org. 300 IN A 192.0.2.4").unwrap();
+        updater.add_rrset(rrset).unwrap();
+        updater.delete_rrset(&www, RRType::A).unwrap();
+        assert!(updater.delete_domain(&www).is_err());
+        let diff = updater.commit(SerialPolicy::Increment).unwrap();
+        assert_eq!(diff.to_serial(), 102);
+        assert_eq!(diff.deleted.len(), 2);
+        assert!(diff.added.is_empty());
+
+        let updater = JournalUpdater::new(&mut zone);
+        assert!(updater.commit(SerialPolicy::Increment).is_none());
+
+        let mut updater = JournalUpdater::new(&mut zone);
+        let rrset = RRset::from_str("www.example.org. 300 IN A 192.0.2.5").unwrap();
+        updater.add_rrset(rrset).unwrap();
+        let diff = updater.commit(SerialPolicy::Date).unwrap();
+        assert!(serial_lt(102, diff.to_serial()));
+        assert_eq!(diff.to_serial() % 100, 0);
+    }
+
+    #[test]
+    fn test_apply_diff() {
+        let mut zone = load_zone(Name::new("example.org").unwrap(), ZONE).unwrap();
+        let mut diff = add_www(&mut zone, "192.0.2.3").unwrap();
+        let mut secondary = load_zone(Name::new("example.org").unwrap(), ZONE).unwrap();
+        let mut updater = JournalUpdater::new(&mut secondary);
+        diff.apply(&mut updater).unwrap();
+        assert_eq!(get_serial(&get_zone_soa(updater.zone()).unwrap()), 101);
+
+        //changes of the diff failed to apply are rolled back
+        let mut secondary = load_zone(Name::new("example.org").unwrap(), ZONE).unwrap();
+        for ip in &["192.0.2.2", "192.0.2.9"] {
+            let rrset = RRset::from_str(&format!("www.example.org. 300 IN A {}", ip)).unwrap();
+            diff.deleted.push(rrset);
+        }
+        let mut updater = JournalUpdater::new(&mut secondary);
+        assert!(diff.apply(&mut updater).is_err());
+        updater.rollback().unwrap();
+        let www = secondary.get_rrsets(&Name::new("www.example.org").unwrap());
+        assert_eq!(www[0].rdatas[0].to_string(), "192.0.2.2");
+        assert_eq!(get_serial(&get_zone_soa(&secondary).unwrap()), 100);
+    }
+
+    #[test]
+    fn test_next_serial() {
+        //2019-11-05 08:00:00 utc
+        let now = 1_572_940_800;
+        assert_eq!(next_serial(SerialPolicy::Increment, 100, now), 101);
+        assert_eq!(
+            next_serial(SerialPolicy::Increment, u32::max_value(), now),
+            0
+        );
+        assert_eq!(next_serial(SerialPolicy::Unixtime, 100, now), now as u32);
+        assert_eq!(next_serial(SerialPolicy::Date, 100, now), 2019110500);
+        assert_eq!(next_serial(SerialPolicy::Date, 2019110500, now), 2019110501);
+        //serial never goes backward
+        assert_eq!(next_serial(SerialPolicy::Date, 2019110600, now), 2019110601);
+        assert_eq!(
+            next_serial(SerialPolicy::Unixtime, 2019110500, now),
+            2019110501
+        );
+        assert_eq!(date_of(0), 19700101);
+        assert_eq!(date_of(951_782_400), 20000229);
+    }
+
+    #[test]
+    fn test_journal_limit() {
+        let mut zone = load_zone(Name::new("example.org").unwrap(), ZONE).unwrap();
+        let mut journal = Journal::new(None, 6);
+        for i in 3..6 {
+            let diff = add_www(&mut zone, &format!("192.0.2.{}", i)).unwrap();
+            journal.append(diff).unwrap();
+        }
+        assert!(journal.get_diffs(100).is_none());
+        assert_eq!(journal.get_diffs(101).unwrap().len(), 2);
+        assert_eq!(journal.get_diffs(102).unwrap().len(), 1);
+        assert!(journal.get_diffs(103).is_none());
+    }
+
+    #[test]
+    fn test_recover_zone() {
+        let path = env::temp_dir().join(format!("vanguard2_test_journal_{}.jnl", process::id()));
+        let _ = fs::remove_file(&path);
+        let name = Name::new("example.org").unwrap();
+        let mut zone = load_zone(name.clone(), ZONE).unwrap();
+        let mut journal = Journal::open(Some(path.clone()), 100, &mut zone);
+        for i in 3..5 {
+            let diff = add_www(&mut zone, &format!("192.0.2.{}", i)).unwrap();
+            journal.append(diff).unwrap();
+        }
+
+        let mut zone = load_zone(name.clone(), ZONE).unwrap();
+        let mut journal = Journal::open(Some(path.clone()), 100, &mut zone);
+        assert_eq!(journal.get_diffs(100).unwrap().len(), 2);
+        assert_eq!(get_serial(&get_zone_soa(&zone).unwrap()), 102);
+        let www = zone.get_rrsets(&Name::new("www.example.org").unwrap());
+        assert_eq!(www[0].rdatas.len(), 3);
+
+        //diffs applied before the failed one are rolled back
+        let mut diff = add_www(&mut zone, "192.0.2.5").unwrap();
+        diff.deleted
+            .push(RRset::from_str("www.example.org. 300 IN A 192.0.2.9").unwrap());
+        journal.append(diff).unwrap();
+        let mut zone = load_zone(name.clone(), ZONE).unwrap();
+        let journal = Journal::open(Some(path.clone()), 100, &mut zone);
+        assert!(journal.get_diffs(100).is_none());
+        assert_eq!(get_serial(&get_zone_soa(&zone).unwrap()), 100);
+        let www = zone.get_rrsets(&Name::new("www.example.org").unwrap());
+        assert_eq!(www[0].rdatas.len(), 1);
+
+        //zone file is changed, journal is discarded
+        let mut zone = load_zone(name, &ZONE.replace(" 100 ", " 200 ")).unwrap();
+        let journal = Journal::open(Some(path.clone()), 100, &mut zone);
+        assert!(journal.get_diffs(100).is_none());
+        assert_eq!(get_serial(&get_zone_soa(&zone).unwrap()), 200);
+        journal.remove_file();
+    }
+}
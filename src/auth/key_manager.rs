@@ -0,0 +1,335 @@
+use super::{
+    dnssec::{generate_pkcs8, DnssecAlgorithm, DnssecKey},
+    error::AuthError,
+};
+use crate::config::DnssecPolicyConfig;
+use failure::Result;
+use r53::Name;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, DirBuilder, File, OpenOptions},
+    io::Write,
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+};
+
+//times are seconds since epoch, key is in the dnskey rrset from publish
+//to remove, and signs the zone from activate to retire
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+struct KeyState {
+    tag: u16,
+    algorithm: String,
+    ksk: bool,
+    //base64 encoded private key in pkcs8 format
+    private_key: String,
+    publish: u64,
+    activate: u64,
+    retire: u64,
+    remove: u64,
+}
+
+impl KeyState {
+    fn is_published(&self, now: u64) -> bool {
+        self.publish <= now && now < self.remove
+    }
+
+    fn is_active(&self, now: u64) -> bool {
+        self.activate <= now && now < self.retire
+    }
+
+    fn to_key(&self) -> Result<DnssecKey> {
+        let invalid_key = |reason: String| -> failure::Error {
+            AuthError::InvalidDnssecKey(self.tag.to_string(), reason).into()
+        };
+        let algorithm = DnssecAlgorithm::from_name(&self.algorithm)
+            .ok_or_else(|| invalid_key(format!("unknown algorithm {}", self.algorithm)))?;
+        let pkcs8 = base64::decode(&self.private_key).map_err(|e| invalid_key(e.to_string()))?;
+        DnssecKey::from_pkcs8(algorithm, &pkcs8, self.ksk).map_err(invalid_key)
+    }
+}
+
+//keys of the zone are generated by policy and persisted in key dir, new zsk
+//is published before it signs (pre-publish), and new ksk signs along with
+//the old one until the old one retires (double signature), rfc6781
+pub struct KeyManager {
+    origin: Name,
+    path: PathBuf,
+    algorithm: DnssecAlgorithm,
+    policy: DnssecPolicyConfig,
+    keys: Vec<KeyState>,
+    //keys changed but failed to save
+    dirty: bool,
+    //tag, ksk and active of the published keys last used to sign the zone
+    applied: Vec<(u16, bool, bool)>,
+}
+
+impl KeyManager {
+    pub fn load(origin: &Name, policy: &DnssecPolicyConfig, key_dir: &str) -> Result<Self> {
+        let load_failed = |reason: String| -> failure::Error {
+            AuthError::KeyStoreFailed(origin.to_string(), reason).into()
+        };
+        let algorithm = DnssecAlgorithm::from_name(&policy.algorithm)
+            .ok_or_else(|| load_failed(format!("unknown algorithm {}", policy.algorithm)))?;
+        if key_dir.is_empty() {
+            return Err(load_failed("key dir isn't set".to_string()));
+        }
+        //key dir is only accessible by the owner
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(key_dir)
+            .map_err(|e| load_failed(e.to_string()))?;
+        let name = origin.to_string();
+        let path = Path::new(key_dir).join(format!("{}.keys", name.trim_end_matches('.')));
+        let keys = if path.exists() {
+            let content = fs::read_to_string(&path).map_err(|e| load_failed(e.to_string()))?;
+            serde_yaml::from_str(&content).map_err(|e| load_failed(e.to_string()))?
+        } else {
+            Vec::new()
+        };
+        Ok(KeyManager {
+            origin: origin.clone(),
+            path,
+            algorithm,
+            policy: policy.clone(),
+            keys,
+            dirty: false,
+            applied: Vec::new(),
+        })
+    }
+
+    //successor is generated when the latest key of each kind retires within
+    //the propagation time, removed keys are dropped. Return true if the
+    //keys differ from the ones last used to sign the zone
+    pub fn rollover(&mut self, now: u64) -> Result<bool> {
+        //ds in parent may still refer to the latest ksk even if the server
+        //was down past its remove time, so it's kept until replaced
+        let latest_ksk = self.latest_key(true).map(|index| self.keys[index].activate);
+        let count = self.keys.len();
+        self.keys
+            .retain(|key| key.remove > now || (key.ksk && Some(key.activate) == latest_ksk));
+        if self.keys.len() != count {
+            self.dirty = true;
+        }
+
+        for &ksk in &[true, false] {
+            let activate = match self.latest_key(ksk) {
+                None => now,
+                Some(index) if self.keys[index].retire <= now + self.policy.propagation => {
+                    let key = &mut self.keys[index];
+                    if ksk {
+                        //old ksk signs until the new ds is in parent
+                        let retire = key
+                            .retire
+                            .max(now + self.policy.propagation + self.policy.ds_propagation);
+                        key.retire = retire;
+                        key.remove = retire;
+                        now
+                    } else {
+                        key.retire.max(now)
+                    }
+                }
+                _ => continue,
+            };
+            let key = self.generate_key(ksk, now, activate)?;
+            self.keys.push(key);
+            self.dirty = true;
+        }
+
+        if self.dirty {
+            self.save()?;
+            self.dirty = false;
+        }
+        Ok(self.key_set(now) != self.applied)
+    }
+
+    fn latest_key(&self, ksk: bool) -> Option<usize> {
+        self.keys
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| key.ksk == ksk)
+            .max_by_key(|(_, key)| key.activate)
+            .map(|(index, _)| index)
+    }
+
+    fn generate_key(&self, ksk: bool, now: u64, activate: u64) -> Result<KeyState> {
+        let pkcs8 = generate_pkcs8(self.algorithm)
+            .map_err(|e| AuthError::KeyStoreFailed(self.origin.to_string(), e))?;
+        let key = DnssecKey::from_pkcs8(self.algorithm, &pkcs8, ksk)
+            .map_err(|e| AuthError::KeyStoreFailed(self.origin.to_string(), e))?;
+        let (retire, remove) = if ksk {
+            let retire = activate + self.policy.ksk_lifetime;
+            (retire, retire)
+        } else {
+            //zsk is kept until the signatures made by it expire in caches
+            let retire = activate + self.policy.zsk_lifetime;
+            (retire, retire + self.policy.propagation)
+        };
+        Ok(KeyState {
+            tag: key.tag(),
+            algorithm: self.algorithm.name().to_string(),
+            ksk,
+            private_key: base64::encode(&pkcs8),
+            publish: now,
+            activate,
+            retire,
+            remove,
+        })
+    }
+
+    //signing keys and the standby keys which are only published
+    pub fn zone_keys(&self, now: u64) -> Result<(Vec<DnssecKey>, Vec<DnssecKey>)> {
+        let mut signing = Vec::new();
+        let mut standby = Vec::new();
+        for state in self.keys.iter().filter(|key| key.is_published(now)) {
+            if state.is_active(now) {
+                signing.push(state.to_key()?);
+            } else {
+                standby.push(state.to_key()?);
+            }
+        }
+        Ok((signing, standby))
+    }
+
+    pub fn mark_applied(&mut self, now: u64) {
+        self.applied = self.key_set(now);
+    }
+
+    fn key_set(&self, now: u64) -> Vec<(u16, bool, bool)> {
+        self.keys
+            .iter()
+            .filter(|key| key.is_published(now))
+            .map(|key| (key.tag, key.ksk, key.is_active(now)))
+            .collect()
+    }
+
+    //private keys are only readable by the owner, file is written to a
+    //temporary file first like the zone file, and the dir is synced so
+    //the rename survives crash
+    fn save(&self) -> Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        serde_yaml::to_string(&self.keys)
+            .map_err(|e| e.to_string())
+            .and_then(|content| {
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .mode(0o600)
+                    .open(&tmp_path)
+                    .and_then(|mut file| {
+                        file.write_all(content.as_bytes())?;
+                        file.sync_all()
+                    })
+                    .and_then(|_| fs::rename(&tmp_path, &self.path))
+                    .and_then(|_| match self.path.parent() {
+                        Some(dir) => File::open(dir)?.sync_all(),
+                        None => Ok(()),
+                    })
+                    .map_err(|e| {
+                        let _ = fs::remove_file(&tmp_path);
+                        e.to_string()
+                    })
+            })
+            .map_err(|e| AuthError::KeyStoreFailed(self.origin.to_string(), e).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, os::unix::fs::PermissionsExt, process};
+
+    fn policy() -> DnssecPolicyConfig {
+        DnssecPolicyConfig {
+            algorithm: "ed25519".to_string(),
+            zsk_lifetime: 1000,
+            ksk_lifetime: 5000,
+            propagation: 100,
+            ds_propagation: 300,
+        }
+    }
+
+    fn tags(keys: &[DnssecKey]) -> Vec<(u16, bool)> {
+        keys.iter().map(|key| (key.tag(), key.is_ksk())).collect()
+    }
+
+    #[test]
+    fn test_rollover() {
+        let dir = env::temp_dir().join(format!("vanguard2_test_key_manager_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let key_dir = dir.to_str().unwrap();
+        let origin = Name::new("example.org").unwrap();
+        let mut manager = KeyManager::load(&origin, &policy(), key_dir).unwrap();
+        let mode = fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        let start = 10000;
+        assert!(manager.rollover(start).unwrap());
+        let (signing, standby) = manager.zone_keys(start).unwrap();
+        assert_eq!(signing.len(), 2);
+        assert!(standby.is_empty());
+        manager.mark_applied(start);
+        assert!(!manager.rollover(start + 10).unwrap());
+
+        //new zsk is published before the old one retires
+        let now = start + 950;
+        assert!(manager.rollover(now).unwrap());
+        let (signing, standby) = manager.zone_keys(now).unwrap();
+        assert_eq!(signing.len(), 2);
+        assert_eq!(standby.len(), 1);
+        assert!(!standby[0].is_ksk());
+        let new_zsk = standby[0].tag();
+        manager.mark_applied(now);
+
+        //old zsk stays published after it retires
+        let now = start + 1000;
+        assert!(manager.rollover(now).unwrap());
+        let (signing, standby) = manager.zone_keys(now).unwrap();
+        assert!(tags(&signing).contains(&(new_zsk, false)));
+        assert_eq!(standby.len(), 1);
+        manager.mark_applied(now);
+
+        let now = start + 1100;
+        assert!(manager.rollover(now).unwrap());
+        let (_, standby) = manager.zone_keys(now).unwrap();
+        assert!(standby.is_empty());
+
+        //keys are loaded from the key dir
+        let loaded = KeyManager::load(&origin, &policy(), key_dir).unwrap();
+        assert_eq!(loaded.keys, manager.keys);
+
+        //new ksk signs along with the old one
+        let now = start + 4950;
+        manager.rollover(now).unwrap();
+        let (signing, _) = manager.zone_keys(now).unwrap();
+        assert_eq!(signing.iter().filter(|key| key.is_ksk()).count(), 2);
+        //old ksk signs until the new ds is in parent
+        let now = start + 5000;
+        manager.rollover(now).unwrap();
+        let (signing, _) = manager.zone_keys(now).unwrap();
+        assert_eq!(signing.iter().filter(|key| key.is_ksk()).count(), 2);
+        let now = start + 5350;
+        manager.rollover(now).unwrap();
+        let (signing, standby) = manager.zone_keys(now).unwrap();
+        let ksks = signing
+            .iter()
+            .filter(|key| key.is_ksk())
+            .map(|key| key.tag())
+            .collect::<Vec<u16>>();
+        assert_eq!(ksks.len(), 1);
+        assert!(standby.iter().all(|key| !key.is_ksk()));
+
+        //the only ksk isn't dropped after the server was down past its
+        //remove time, it signs along with the new one
+        let now = start + 20000;
+        manager.rollover(now).unwrap();
+        let (signing, _) = manager.zone_keys(now).unwrap();
+        assert!(tags(&signing).contains(&(ksks[0], true)));
+        assert_eq!(signing.iter().filter(|key| key.is_ksk()).count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
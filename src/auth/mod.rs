@@ -1,8 +1,19 @@
 mod auth_server;
+mod dnssec;
 mod dynamic_server;
 mod error;
+mod journal;
+mod key_manager;
+mod notify;
+mod persist;
 mod proto;
+mod secondary;
+mod signer;
+mod update;
+mod xfr;
 mod zones;
 
 pub use auth_server::{AuthFuture, AuthServer};
 pub use dynamic_server::DynamicUpdateHandler;
+pub use notify::Notifier;
+pub use secondary::SecondaryManager;
@@ -0,0 +1,274 @@
+use super::error::AuthError;
+use crate::{
+    error::VgError,
+    server::{KeyRing, ShutdownListener, TsigKey, TsigSigner},
+};
+use failure::{self, Result};
+use futures::{
+    future::{self, Loop},
+    sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    Future, Stream,
+};
+use r53::{HeaderFlag, Message, MessageBuilder, MessageRender, Name, Opcode, RRType, Rcode};
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{executor::spawn, net::UdpSocket};
+use tokio_timer::Timeout;
+
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+const NOTIFY_RETRY_COUNT: usize = 5;
+const NOTIFY_RECV_BUF_SIZE: usize = 512;
+
+type NotifyTask = (Name, SocketAddr, Option<TsigKey>);
+
+#[derive(Clone, Debug, Default)]
+pub struct NotifyTarget {
+    pub secondaries: Vec<SocketAddr>,
+    pub tsig_key: Option<String>,
+}
+
+//secondaries of the zone are notified once the zone is changed, notifies
+//are queued until the notifier is run
+#[derive(Clone)]
+pub struct Notifier {
+    inner: Arc<Mutex<NotifierInner>>,
+}
+
+struct NotifierInner {
+    keyring: KeyRing,
+    targets: HashMap<Name, NotifyTarget>,
+    sender: UnboundedSender<NotifyTask>,
+    receiver: Option<UnboundedReceiver<NotifyTask>>,
+}
+
+impl Notifier {
+    pub fn new(keyring: KeyRing) -> Self {
+        let (sender, receiver) = unbounded();
+        Notifier {
+            inner: Arc::new(Mutex::new(NotifierInner {
+                keyring,
+                targets: HashMap::new(),
+                sender,
+                receiver: Some(receiver),
+            })),
+        }
+    }
+
+    //targets of all the zones are replaced
+    pub fn set_targets(&self, targets: HashMap<Name, NotifyTarget>) {
+        self.inner.lock().unwrap().targets = targets;
+    }
+
+    pub fn notify(&self, zone: &Name) {
+        let inner = self.inner.lock().unwrap();
+        if let Some(target) = inner.targets.get(zone) {
+            let key = match target.tsig_key {
+                Some(ref name) => match inner.keyring.get_key(name) {
+                    Some(key) => Some(key),
+                    None => {
+                        eprintln!("{}", AuthError::UnknownTsigKey(name.clone()));
+                        return;
+                    }
+                },
+                None => None,
+            };
+            for secondary in &target.secondaries {
+                let _ = inner
+                    .sender
+                    .unbounded_send((zone.clone(), *secondary, key.clone()));
+            }
+        }
+    }
+
+    pub fn run(&self, shutdown: ShutdownListener) -> impl Future<Item = (), Error = ()> + Send {
+        let receiver = self
+            .inner
+            .lock()
+            .unwrap()
+            .receiver
+            .take()
+            .expect("notifier could only be run once");
+        receiver
+            .for_each(|(zone, target, key)| {
+                spawn(send_notify(zone, target, key).map_err(|e| eprintln!("{}", e)));
+                Ok(())
+            })
+            .select2(shutdown)
+            .then(|_| Ok(()))
+    }
+}
+
+//notify is resent if the secondary doesn't answer it in time
+pub fn send_notify(
+    zone: Name,
+    target: SocketAddr,
+    key: Option<TsigKey>,
+) -> impl Future<Item = (), Error = failure::Error> + Send + 'static {
+    future::loop_fn(1, move |tried| {
+        let zone = zone.clone();
+        notify_once(zone.clone(), target, key.clone()).then(move |result| match result {
+            Ok(_) => Ok(Loop::Break(())),
+            Err(_) if tried < NOTIFY_RETRY_COUNT => Ok(Loop::Continue(tried + 1)),
+            Err(e) => {
+                Err(
+                    AuthError::NotifyFailed(zone.to_string(), target.to_string(), e.to_string())
+                        .into(),
+                )
+            }
+        })
+    })
+}
+
+//response has to be signed by the same key as the notify
+fn notify_once(
+    zone: Name,
+    target: SocketAddr,
+    key: Option<TsigKey>,
+) -> impl Future<Item = (), Error = failure::Error> + Send + 'static {
+    let notify = make_notify(zone);
+    let id = notify.header.id;
+    let mut render = MessageRender::new();
+    notify.rend(&mut render);
+    let mut buffer = render.take_data();
+    let mut signer = key.map(TsigSigner::new);
+    if let Some(ref mut signer) = signer {
+        signer.sign(&mut buffer);
+    }
+
+    let local = if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let send = future::result(UdpSocket::bind(&local.parse().unwrap()))
+        .and_then(move |socket| socket.send_dgram(buffer, &target))
+        .and_then(move |(socket, _)| recv_response(socket, target, id));
+    Timeout::new(send, NOTIFY_TIMEOUT)
+        .map_err(move |e| {
+            if e.is_elapsed() {
+                VgError::Timeout(target.to_string()).into()
+            } else if let Some(e) = e.into_inner() {
+                VgError::IoError(e).into()
+            } else {
+                VgError::TimerErr("timer is shutdown".to_string()).into()
+            }
+        })
+        .and_then(move |buffer| match signer {
+            Some(ref mut signer) => check_notify_response(&signer.verify(&buffer)?, id),
+            None => check_notify_response(&buffer, id),
+        })
+}
+
+//datagrams from other addresses or with other id are ignored, so spoofed
+//or stale responses don't acknowledge the notify
+fn recv_response(
+    socket: UdpSocket,
+    target: SocketAddr,
+    id: u16,
+) -> impl Future<Item = Vec<u8>, Error = io::Error> + Send + 'static {
+    future::loop_fn(socket, move |socket| {
+        let buffer = vec![0; NOTIFY_RECV_BUF_SIZE];
+        socket
+            .recv_dgram(buffer)
+            .map(move |(socket, mut buffer, len, from)| {
+                let matched = len >= 2 && u16::from_be_bytes([buffer[0], buffer[1]]) == id;
+                if from == target && matched {
+                    buffer.truncate(len);
+                    Loop::Break(buffer)
+                } else {
+                    Loop::Continue(socket)
+                }
+            })
+    })
+}
+
+fn make_notify(zone: Name) -> Message {
+    let mut notify = Message::with_query(zone, RRType::SOA);
+    MessageBuilder::new(&mut notify)
+        .opcode(Opcode::Notify)
+        .set_flag(HeaderFlag::AuthAnswer)
+        .clear_flag(HeaderFlag::RecursionDesired)
+        .done();
+    notify.header.id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos() as u16;
+    notify
+}
+
+fn check_notify_response(buffer: &[u8], id: u16) -> Result<()> {
+    let response = Message::from_wire(buffer)?;
+    if response.header.id != id || response.header.opcode != Opcode::Notify {
+        return Err(AuthError::InvalidNotifyResponse("response doesn't match".to_string()).into());
+    }
+    if response.header.rcode != Rcode::NoError {
+        return Err(AuthError::InvalidNotifyResponse(response.header.rcode.to_string()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::shutdown_channel;
+    use std::{net::UdpSocket as StdUdpSocket, thread};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_send_notify() {
+        let secondary = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        secondary
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let target = secondary.local_addr().unwrap();
+        let spoofer = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        //first notify is answered by another address which is ignored, so
+        //the notify is resent, and the resent one is answered
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (len, client) = secondary.recv_from(&mut buf).unwrap();
+            let mut notify = Message::from_wire(&buf[..len]).unwrap();
+            MessageBuilder::new(&mut notify).make_response().done();
+            let mut render = MessageRender::new();
+            notify.rend(&mut render);
+            spoofer.send_to(&render.take_data(), client).unwrap();
+
+            let (len, client) = secondary.recv_from(&mut buf).unwrap();
+            let mut notify = Message::from_wire(&buf[..len]).unwrap();
+            assert_eq!(notify.header.opcode, Opcode::Notify);
+            assert_eq!(
+                notify.question.as_ref().unwrap().name,
+                Name::new("example.org").unwrap()
+            );
+            MessageBuilder::new(&mut notify).make_response().done();
+            let mut render = MessageRender::new();
+            notify.rend(&mut render);
+            secondary.send_to(&render.take_data(), client).unwrap();
+        });
+
+        let notifier = Notifier::new(KeyRing::default());
+        let zone = Name::new("example.org").unwrap();
+        let mut targets = HashMap::new();
+        targets.insert(
+            zone.clone(),
+            NotifyTarget {
+                secondaries: vec![target],
+                tsig_key: None,
+            },
+        );
+        notifier.set_targets(targets);
+        let (trigger, shutdown) = shutdown_channel();
+        let mut rt = Runtime::new().unwrap();
+        rt.spawn(notifier.run(shutdown));
+        notifier.notify(&Name::new("example.com").unwrap());
+        notifier.notify(&zone);
+        handle.join().unwrap();
+        trigger.shutdown();
+        rt.shutdown_on_idle().wait().unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+use super::{
+    error::AuthError,
+    journal::{record_to_string, split_rrset},
+    zones::AuthZone,
+};
+use crate::server::ShutdownListener;
+use datasrc::MemoryZone;
+use failure::Result;
+use futures::{
+    future::{self, Either},
+    Future, Stream,
+};
+use r53::RRType;
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio_timer::Interval;
+
+//zone is written in master file format, soa comes first and each record
+//is in one line with absolute name. Content is written to a temporary file
+//which is renamed to the zone file, so the zone file is never half written
+pub fn write_zone_file(zone: &MemoryZone, path: &Path) -> Result<()> {
+    let mut tmp_path = path.to_path_buf().into_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    write_records(zone, &tmp_path)
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp_path);
+            AuthError::ZoneFlushFailed(zone.get_origin().to_string(), e.to_string()).into()
+        })
+}
+
+fn write_records(zone: &MemoryZone, path: &Path) -> io::Result<()> {
+    let rrsets = zone.get_all_rrsets();
+    let soa = rrsets.iter().filter(|rrset| rrset.typ == RRType::SOA);
+    let others = rrsets.iter().filter(|rrset| rrset.typ != RRType::SOA);
+    let file = File::create(path)?;
+    let mut writer = BufWriter::new(file);
+    for rrset in soa.chain(others) {
+        for record in split_rrset(rrset.clone()) {
+            writeln!(writer, "{}", record_to_string(&record))?;
+        }
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()
+}
+
+//zones are written under read lock, so queries aren't blocked, zone failed
+//to write is retried in next flush
+pub fn flush_zones(zones: &RwLock<AuthZone>) {
+    let dirty_zones = zones.write().unwrap().take_dirty_zones();
+    for (name, path) in dirty_zones {
+        let result = match zones.read().unwrap().get_zone(&name) {
+            Some(zone) if zone.get_origin().eq(&name) => write_zone_file(zone, &path),
+            _ => continue,
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            zones.write().unwrap().mark_dirty(&name);
+        }
+    }
+}
+
+//changed zones are flushed periodically, and once more when shutdown,
+//zero interval means zones are only flushed when shutdown
+pub fn run_flusher(
+    zones: Arc<RwLock<AuthZone>>,
+    interval: Duration,
+    shutdown: ShutdownListener,
+) -> impl Future<Item = (), Error = ()> + Send {
+    let flusher = zones.clone();
+    let periodic = if interval == Duration::from_secs(0) {
+        Either::A(future::empty())
+    } else {
+        Either::B(
+            Interval::new_interval(interval)
+                .map_err(|e| eprintln!("timer get err {:?}", e))
+                .for_each(move |_| {
+                    flush_zones(&flusher);
+                    Ok(())
+                }),
+        )
+    };
+    periodic.select2(shutdown).then(move |_| {
+        flush_zones(&zones);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auth::notify::Notifier, server::KeyRing};
+    use datasrc::{load_zone_file, ZoneUpdater};
+    use r53::{Name, RRset};
+    use std::{env, process, str::FromStr};
+
+    //each test has its own dir, so tests running at the same time don't
+    //share files
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("vanguard2_test_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_flush_zones() {
+        let dir = test_dir("persist");
+        let path = dir.join("example.org.zone");
+        fs::copy("testdata/example.org.zone", &path).unwrap();
+
+        let name = Name::new("example.org").unwrap();
+        let zone = load_zone_file(name.clone(), &path).unwrap();
+        let mut zones = AuthZone::new("", 100, Notifier::new(KeyRing::default()));
+        zones.insert_zone(zone).unwrap();
+        zones.set_zone_file(&name, path.clone());
+        assert!(zones.take_dirty_zones().is_empty());
+
+        let rrset = RRset::from_str("mail.example.org. 300 IN A 192.0.2.10").unwrap();
+        zones
+            .update_zone(&name, |updater| updater.add_rrset(rrset))
+            .unwrap();
+        let zones = RwLock::new(zones);
+        flush_zones(&zones);
+        assert!(zones.write().unwrap().take_dirty_zones().is_empty());
+
+        let zone = load_zone_file(name.clone(), &path).unwrap();
+        let mail = zone.get_rrsets(&Name::new("mail.example.org").unwrap());
+        assert_eq!(mail.len(), 1);
+        assert_eq!(
+            zone.get_all_rrsets().len(),
+            zones
+                .read()
+                .unwrap()
+                .get_zone(&name)
+                .unwrap()
+                .get_all_rrsets()
+                .len()
+        );
+        assert!(!dir.join("example.org.zone.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_delete_zone_file() {
+        let dir = test_dir("delete_zone");
+        let name = Name::new("example.org").unwrap();
+        let path = dir.join("example.org.zone");
+        let mut zones = AuthZone::new("", 100, Notifier::new(KeyRing::default()));
+        zones.set_zone_dir(dir.to_str().unwrap());
+        let content = fs::read_to_string("testdata/example.org.zone").unwrap();
+        zones.add_zone(name.clone(), &content).unwrap();
+        let zones = RwLock::new(zones);
+        flush_zones(&zones);
+        assert!(path.exists());
+
+        //zone removed for reload keeps its file
+        let mut zones = zones.into_inner().unwrap();
+        zones.remove_zone(&name).unwrap();
+        assert!(path.exists());
+
+        //file of zone from config is kept
+        zones
+            .insert_zone(load_zone_file(name.clone(), &path).unwrap())
+            .unwrap();
+        zones.set_zone_file(&name, path.clone());
+        zones.delete_zone(&name).unwrap();
+        assert!(path.exists());
+
+        zones
+            .insert_zone(load_zone_file(name.clone(), &path).unwrap())
+            .unwrap();
+        zones.set_zone_file(&name, path.clone());
+        zones.set_managed_zone(&name);
+        zones.delete_zone(&name).unwrap();
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,775 @@
+use super::{
+    error::AuthError,
+    journal::{get_serial, serial_lt, split_rrset, Diff},
+    zones::AuthZone,
+};
+use crate::{
+    config::AuthZoneConfig,
+    error::VgError,
+    server::{normalize_key_name, KeyRing, ShutdownListener, TsigSigner},
+};
+use datasrc::{MemoryZone, ZoneUpdater};
+use failure::{self, Result};
+use futures::{
+    future::{self, Either, Loop},
+    sync::{
+        mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    Future, Stream,
+};
+use r53::{
+    message::SectionType, Message, MessageBuilder, MessageRender, Name, RData, RRType, RRset, Rcode,
+};
+use std::{
+    collections::HashMap,
+    mem,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::{
+    executor::spawn,
+    io::{read_exact, write_all},
+    net::TcpStream,
+    timer::Delay,
+};
+use tokio_timer::Timeout;
+
+const DEFAULT_DNS_PORT: u16 = 53;
+//used before the zone is transferred, since there is no soa timer
+const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+//transfer fails if primary is idle longer than it
+const XFR_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, PartialEq)]
+pub enum XfrResult {
+    UpToDate,
+    Full(Vec<RRset>),
+    Incremental(Vec<Diff>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum XfrState {
+    Start,
+    FirstSoa,
+    Full,
+    Deleting,
+    Adding,
+    Done,
+}
+
+//parse axfr or ixfr response defined in rfc1995, ixfr response could be
+//a single soa if zone is up to date, or the same as axfr response if
+//primary has no journal for the serial
+pub struct XfrParser {
+    //serial of current zone, only set for ixfr
+    serial: Option<u32>,
+    state: XfrState,
+    soa: Option<RRset>,
+    records: Vec<RRset>,
+    old_soa: Option<RRset>,
+    new_soa: Option<RRset>,
+    added: Vec<RRset>,
+    diffs: Vec<Diff>,
+}
+
+impl XfrParser {
+    pub fn new(serial: Option<u32>) -> Self {
+        XfrParser {
+            serial,
+            state: XfrState::Start,
+            soa: None,
+            records: Vec::new(),
+            old_soa: None,
+            new_soa: None,
+            added: Vec::new(),
+            diffs: Vec::new(),
+        }
+    }
+
+    //return the result once the last message is parsed
+    pub fn parse(&mut self, mut message: Message) -> Result<Option<XfrResult>> {
+        if message.header.rcode != Rcode::NoError {
+            return Err(invalid_response(format!(
+                "get rcode {}",
+                message.header.rcode.to_string()
+            )));
+        }
+
+        let answers = message
+            .take_section(SectionType::Answer)
+            .unwrap_or_default();
+        for rrset in answers {
+            for record in split_rrset(rrset) {
+                self.parse_record(record)?;
+            }
+        }
+
+        match self.state {
+            XfrState::Done => Ok(Some(self.take_result())),
+            XfrState::FirstSoa => {
+                let soa_serial = get_serial(self.soa.as_ref().unwrap());
+                match self.serial {
+                    Some(serial) if !serial_lt(serial, soa_serial) => Ok(Some(XfrResult::UpToDate)),
+                    _ => Ok(None),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn parse_record(&mut self, record: RRset) -> Result<()> {
+        let is_soa = record.typ == RRType::SOA;
+        match self.state {
+            XfrState::Start => {
+                if !is_soa {
+                    return Err(invalid_response("first record isn't soa".to_string()));
+                }
+                self.soa = Some(record);
+                self.state = XfrState::FirstSoa;
+            }
+            XfrState::FirstSoa => {
+                if is_soa && self.serial.is_some() {
+                    self.old_soa = Some(record);
+                    self.state = XfrState::Deleting;
+                } else if is_soa {
+                    return Err(invalid_response("zone has only soa".to_string()));
+                } else {
+                    self.records.push(self.soa.clone().unwrap());
+                    self.records.push(record);
+                    self.state = XfrState::Full;
+                }
+            }
+            XfrState::Full => {
+                if is_soa {
+                    self.state = XfrState::Done;
+                } else {
+                    self.records.push(record);
+                }
+            }
+            XfrState::Deleting => {
+                if is_soa {
+                    self.new_soa = Some(record);
+                    self.state = XfrState::Adding;
+                } else {
+                    self.records.push(record);
+                }
+            }
+            //soa in adding is either the old soa of next diff, or the last
+            //soa which has the same serial with the first one
+            XfrState::Adding => {
+                if is_soa {
+                    self.diffs.push(Diff {
+                        old_soa: self.old_soa.take().unwrap(),
+                        deleted: mem::replace(&mut self.records, Vec::new()),
+                        new_soa: self.new_soa.take().unwrap(),
+                        added: mem::replace(&mut self.added, Vec::new()),
+                    });
+                    if get_serial(&record) == get_serial(self.soa.as_ref().unwrap()) {
+                        self.state = XfrState::Done;
+                    } else {
+                        self.old_soa = Some(record);
+                        self.state = XfrState::Deleting;
+                    }
+                } else {
+                    self.added.push(record);
+                }
+            }
+            XfrState::Done => {
+                return Err(invalid_response("record after last soa".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    fn take_result(&mut self) -> XfrResult {
+        if self.diffs.is_empty() {
+            XfrResult::Full(mem::replace(&mut self.records, Vec::new()))
+        } else {
+            XfrResult::Incremental(mem::replace(&mut self.diffs, Vec::new()))
+        }
+    }
+}
+
+fn invalid_response(reason: String) -> failure::Error {
+    AuthError::InvalidXfrResponse(reason).into()
+}
+
+//ixfr is used if the zone is already transferred
+fn make_xfr_query(name: &Name, soa: Option<RRset>) -> Message {
+    match soa {
+        Some(soa) => {
+            let mut query = Message::with_query(name.clone(), RRType::IXFR);
+            MessageBuilder::new(&mut query).add_auth(soa).done();
+            query
+        }
+        None => Message::with_query(name.clone(), RRType::AXFR),
+    }
+}
+
+//responses are verified if the query is signed
+fn transfer(
+    primary: SocketAddr,
+    query: Message,
+    serial: Option<u32>,
+    mut signer: Option<TsigSigner>,
+) -> impl Future<Item = XfrResult, Error = failure::Error> + Send + 'static {
+    let mut render = MessageRender::new();
+    query.rend(&mut render);
+    let mut buffer = render.take_data();
+    if let Some(ref mut signer) = signer {
+        signer.sign(&mut buffer);
+    }
+    let mut bytes = Vec::with_capacity(buffer.len() + 2);
+    bytes.push((buffer.len() >> 8 & 0xFF) as u8);
+    bytes.push((buffer.len() & 0xFF) as u8);
+    bytes.extend_from_slice(buffer.as_ref());
+
+    let connect = TcpStream::connect(&primary).and_then(move |stream| write_all(stream, bytes));
+    with_timeout(connect).and_then(move |(stream, _)| {
+        let start = (stream, XfrParser::new(serial), signer);
+        future::loop_fn(start, |(stream, mut parser, mut signer)| {
+            let read = read_exact(stream, [0u8; 2]).and_then(|(stream, len)| {
+                let len = (len[0] as usize) << 8 | len[1] as usize;
+                read_exact(stream, vec![0; len])
+            });
+            with_timeout(read).and_then(move |(stream, buffer)| {
+                let buffer = match signer {
+                    Some(ref mut signer) => signer.verify(buffer.as_ref())?,
+                    None => buffer,
+                };
+                let message = Message::from_wire(buffer.as_ref())?;
+                match parser.parse(message)? {
+                    Some(_) if signer.as_ref().map_or(false, |s| !s.is_verified()) => {
+                        Err(invalid_response("last message isn't signed".to_string()))
+                    }
+                    Some(result) => Ok(Loop::Break(result)),
+                    None => Ok(Loop::Continue((stream, parser, signer))),
+                }
+            })
+        })
+    })
+}
+
+fn with_timeout<F>(f: F) -> impl Future<Item = F::Item, Error = failure::Error>
+where
+    F: Future<Error = std::io::Error>,
+{
+    Timeout::new(f, XFR_IDLE_TIMEOUT).map_err(|e| {
+        if e.is_elapsed() {
+            AuthError::XfrIdleTimeout.into()
+        } else if let Some(e) = e.into_inner() {
+            VgError::IoError(e).into()
+        } else {
+            VgError::TimerErr("timer is shutdown".to_string()).into()
+        }
+    })
+}
+
+//port could be omitted if it's the default one
+pub fn parse_server_addresses(addrs: &[String]) -> Result<Vec<SocketAddr>> {
+    addrs
+        .iter()
+        .map(|addr| {
+            addr.parse::<SocketAddr>()
+                .or_else(|_| {
+                    addr.parse::<IpAddr>()
+                        .map(|ip| SocketAddr::new(ip, DEFAULT_DNS_PORT))
+                })
+                .map_err(|_| AuthError::InvalidAddress(addr.clone()).into())
+        })
+        .collect()
+}
+
+//empty key name means no key is used, otherwise the key has to exist
+pub fn parse_key_name(name: &str, keyring: &KeyRing) -> Result<Option<String>> {
+    if name.is_empty() {
+        Ok(None)
+    } else if keyring.has_key(name) {
+        Ok(Some(normalize_key_name(name)))
+    } else {
+        Err(AuthError::UnknownTsigKey(name.to_string()).into())
+    }
+}
+
+//timers are from the soa of the zone
+struct SoaTimers {
+    refresh: Duration,
+    retry: Duration,
+    expire: Duration,
+}
+
+fn get_timers(soa: &RRset) -> Option<SoaTimers> {
+    match soa.rdatas[0] {
+        RData::SOA(ref soa) => Some(SoaTimers {
+            refresh: Duration::from_secs(soa.refresh.max(1) as u64),
+            retry: Duration::from_secs(soa.retry.max(1) as u64),
+            expire: Duration::from_secs(soa.expire as u64),
+        }),
+        _ => None,
+    }
+}
+
+//zone is served after it's transferred from primary, then it's checked
+//every refresh interval, and is removed if it can't be refreshed before
+//expire
+pub struct SecondaryZone {
+    name: Name,
+    primaries: Vec<SocketAddr>,
+    zones: Arc<RwLock<AuthZone>>,
+    keyring: KeyRing,
+    tsig_key: Option<String>,
+    last_refresh: Option<Instant>,
+    //incremental changes failed to apply, transfer the whole zone
+    force_full: bool,
+}
+
+impl SecondaryZone {
+    pub fn new(
+        conf: &AuthZoneConfig,
+        zones: Arc<RwLock<AuthZone>>,
+        keyring: KeyRing,
+    ) -> Result<Self> {
+        let name = Name::new(conf.name.as_ref())?;
+        if conf.primaries.is_empty() {
+            return Err(AuthError::TransferFailed(
+                conf.name.clone(),
+                String::new(),
+                "no primary is specified".to_string(),
+            )
+            .into());
+        }
+        let primaries = parse_server_addresses(&conf.primaries)?;
+        let tsig_key = parse_key_name(&conf.tsig_key, &keyring)?;
+        Ok(SecondaryZone {
+            name,
+            primaries,
+            zones,
+            keyring,
+            tsig_key,
+            last_refresh: None,
+            force_full: false,
+        })
+    }
+
+    pub fn get_name(&self) -> &Name {
+        &self.name
+    }
+
+    //notify from primary wakes up the refresh immediately, refresh is
+    //stopped once the notify sender is dropped
+    fn into_future(
+        self,
+        notify: UnboundedReceiver<()>,
+    ) -> impl Future<Item = (), Error = ()> + Send + 'static {
+        let start = (self, Duration::from_secs(0), notify);
+        future::loop_fn(start, |(zone, delay, notify)| {
+            Delay::new(Instant::now() + delay)
+                .select2(notify.into_future())
+                .then(|result| match result {
+                    Ok(Either::A((_, notify))) => Ok(notify.into_inner().unwrap()),
+                    Ok(Either::B(((Some(_), notify), _))) => Ok(notify),
+                    Ok(Either::B(((None, _), _))) => Err(()),
+                    Err(Either::A((e, _))) => {
+                        eprintln!("timer error {}", e);
+                        Err(())
+                    }
+                    Err(Either::B(_)) => Err(()),
+                })
+                .and_then(move |notify| {
+                    zone.refresh()
+                        .map(move |(zone, delay)| Loop::Continue((zone, delay, notify)))
+                })
+        })
+    }
+
+    //primaries are tried in order until one of them succeeds
+    fn refresh(self) -> impl Future<Item = (Self, Duration), Error = ()> + Send + 'static {
+        future::loop_fn((self, 0), |(zone, index)| {
+            let soa = if zone.force_full {
+                None
+            } else {
+                zone.zones.read().unwrap().get_soa(&zone.name)
+            };
+            let serial = soa.as_ref().map(get_serial);
+            let primary = zone.primaries[index];
+            let query = make_xfr_query(&zone.name, soa);
+            let xfr = match zone.get_signer() {
+                Ok(signer) => Either::A(transfer(primary, query, serial, signer)),
+                Err(e) => Either::B(future::err(e)),
+            };
+            xfr.then(move |result| {
+                let mut zone = zone;
+                match result.and_then(|result| zone.apply(result)) {
+                    Ok(_) => Ok(Loop::Break((zone, true))),
+                    Err(e) => {
+                        eprintln!(
+                            "{}",
+                            AuthError::TransferFailed(
+                                zone.name.to_string(),
+                                primary.to_string(),
+                                e.to_string()
+                            )
+                        );
+                        if index + 1 < zone.primaries.len() {
+                            Ok(Loop::Continue((zone, index + 1)))
+                        } else {
+                            Ok(Loop::Break((zone, false)))
+                        }
+                    }
+                }
+            })
+        })
+        .map(|(mut zone, succeed)| {
+            let delay = zone.schedule(succeed);
+            (zone, delay)
+        })
+    }
+
+    //key is got when it's used, since it may be changed by reload
+    fn get_signer(&self) -> Result<Option<TsigSigner>> {
+        match self.tsig_key {
+            Some(ref name) => self
+                .keyring
+                .get_key(name)
+                .map(|key| Some(TsigSigner::new(key)))
+                .ok_or_else(|| AuthError::UnknownTsigKey(name.clone()).into()),
+            None => Ok(None),
+        }
+    }
+
+    fn apply(&mut self, result: XfrResult) -> Result<()> {
+        match result {
+            XfrResult::UpToDate => {}
+            XfrResult::Full(records) => {
+                let mut zone = MemoryZone::new(self.name.clone());
+                for record in records {
+                    zone.add_rrset(record)?;
+                }
+                self.zones.write().unwrap().replace_zone(zone)?;
+                self.force_full = false;
+            }
+            XfrResult::Incremental(diffs) => {
+                let result = self.zones.write().unwrap().apply_diffs(&self.name, diffs);
+                if result.is_err() {
+                    self.force_full = true;
+                }
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    //return the delay before next refresh
+    fn schedule(&mut self, succeed: bool) -> Duration {
+        if self.force_full {
+            return Duration::from_secs(0);
+        }
+
+        let timers = self
+            .zones
+            .read()
+            .unwrap()
+            .get_soa(&self.name)
+            .and_then(|soa| get_timers(&soa));
+        let timers = match timers {
+            Some(timers) => timers,
+            None => return DEFAULT_RETRY_INTERVAL,
+        };
+        if succeed {
+            self.last_refresh = Some(Instant::now());
+            return timers.refresh;
+        }
+
+        if let Some(last_refresh) = self.last_refresh {
+            if last_refresh.elapsed() >= timers.expire {
+                eprintln!("zone {} is expired, stop serving it", self.name.to_string());
+                let _ = self.zones.write().unwrap().delete_zone(&self.name);
+                self.last_refresh = None;
+                return DEFAULT_RETRY_INTERVAL;
+            }
+        }
+        timers.retry
+    }
+}
+
+//secondary zones are refreshed in the runtime, zones added before the
+//runtime is started are pending until manager is run
+#[derive(Clone)]
+pub struct SecondaryManager {
+    inner: Arc<Mutex<SecondaryManagerInner>>,
+}
+
+struct SecondaryManagerInner {
+    shutdown: Option<ShutdownListener>,
+    pending: Vec<SecondaryZone>,
+    running: HashMap<Name, RunningZone>,
+}
+
+struct RunningZone {
+    //refresh of the zone is stopped once the senders are dropped
+    _cancel: oneshot::Sender<()>,
+    notify: UnboundedSender<()>,
+    primaries: Vec<SocketAddr>,
+    tsig_key: Option<String>,
+}
+
+impl SecondaryManager {
+    pub fn new() -> Self {
+        SecondaryManager {
+            inner: Arc::new(Mutex::new(SecondaryManagerInner {
+                shutdown: None,
+                pending: Vec::new(),
+                running: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn run(&self, shutdown: ShutdownListener) -> impl Future<Item = (), Error = ()> + Send {
+        let inner = self.inner.clone();
+        future::lazy(move || {
+            let mut inner = inner.lock().unwrap();
+            inner.shutdown = Some(shutdown);
+            for zone in mem::replace(&mut inner.pending, Vec::new()) {
+                inner.spawn(zone);
+            }
+            Ok(())
+        })
+    }
+
+    //zone with same name is replaced
+    pub fn add_zone(&self, zone: SecondaryZone) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove_zone(zone.get_name());
+        if inner.shutdown.is_some() {
+            inner.spawn(zone);
+        } else {
+            inner.pending.push(zone);
+        }
+    }
+
+    pub fn remove_zone(&self, name: &Name) {
+        self.inner.lock().unwrap().remove_zone(name);
+    }
+
+    pub fn has_zone(&self, name: &Name) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.running.contains_key(name) || inner.pending.iter().any(|zone| zone.name.eq(name))
+    }
+
+    //notify is only accepted from the primaries of the zone, and has to be
+    //signed if the zone has tsig key, pending zone will be refreshed once
+    //it's started, so notify is ignored
+    pub fn notify(&self, name: &Name, client: IpAddr, key: Option<&str>) -> Rcode {
+        let inner = self.inner.lock().unwrap();
+        let running = inner.running.get(name);
+        let (primaries, tsig_key) = match running {
+            Some(zone) => (&zone.primaries, &zone.tsig_key),
+            None => match inner.pending.iter().find(|zone| zone.name.eq(name)) {
+                Some(zone) => (&zone.primaries, &zone.tsig_key),
+                None => return Rcode::NotAuth,
+            },
+        };
+        if primaries.iter().all(|primary| primary.ip() != client) {
+            return Rcode::Refused;
+        }
+        if let Some(tsig_key) = tsig_key {
+            if key.map_or(true, |key| !key.eq(tsig_key)) {
+                return Rcode::Refused;
+            }
+        }
+        if let Some(zone) = running {
+            let _ = zone.notify.unbounded_send(());
+        }
+        Rcode::NoError
+    }
+}
+
+impl SecondaryManagerInner {
+    fn spawn(&mut self, zone: SecondaryZone) {
+        let (cancel, canceled) = oneshot::channel();
+        let (notify, notified) = unbounded();
+        self.running.insert(
+            zone.name.clone(),
+            RunningZone {
+                _cancel: cancel,
+                notify,
+                primaries: zone.primaries.clone(),
+                tsig_key: zone.tsig_key.clone(),
+            },
+        );
+        let shutdown = self.shutdown.clone().unwrap();
+        spawn(
+            zone.into_future(notified)
+                .select2(canceled)
+                .select2(shutdown)
+                .then(|_| Ok(())),
+        );
+    }
+
+    fn remove_zone(&mut self, name: &Name) {
+        self.running.remove(name);
+        self.pending.retain(|zone| !zone.name.eq(name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{
+        notify::Notifier,
+        xfr::{make_axfr_response, make_ixfr_response, make_xfr_response},
+    };
+    use crate::{
+        config::{SerialPolicy, ZoneType},
+        server::{shutdown_channel, XfrResponse},
+    };
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        str::FromStr,
+        thread,
+    };
+
+    fn soa(serial: u32) -> RRset {
+        RRset::from_str(&format!(
+            "example.org. 300 IN SOA ns.example.org. root.example.org. {} 3600 900 604800 300",
+            serial
+        ))
+        .unwrap()
+    }
+
+    fn primary_zone(serial: u32) -> Arc<MemoryZone> {
+        let mut zone = MemoryZone::new(Name::new("example.org").unwrap());
+        zone.add_rrset(soa(serial)).unwrap();
+        zone.add_rrset(RRset::from_str("example.org. 300 IN NS ns.example.org.").unwrap())
+            .unwrap();
+        zone.add_rrset(RRset::from_str("ns.example.org. 300 IN A 192.0.2.1").unwrap())
+            .unwrap();
+        Arc::new(zone)
+    }
+
+    fn parse(responses: XfrResponse, serial: Option<u32>) -> Option<XfrResult> {
+        let mut parser = XfrParser::new(serial);
+        let mut result = None;
+        for response in responses.wait() {
+            let response = response.unwrap();
+            result = parser.parse(response).unwrap();
+        }
+        result
+    }
+
+    #[test]
+    fn test_parse_xfr_response() {
+        let name = Name::new("example.org").unwrap();
+        let query = make_xfr_query(&name, None);
+        let responses = make_axfr_response(&query, primary_zone(1));
+        match parse(responses, None) {
+            Some(XfrResult::Full(records)) => {
+                assert_eq!(records.len(), 3);
+                assert_eq!(records[0].typ, RRType::SOA);
+            }
+            _ => panic!("axfr should get full zone"),
+        }
+
+        let query = make_xfr_query(&name, Some(soa(1)));
+        let diffs = vec![
+            Diff {
+                old_soa: soa(1),
+                deleted: Vec::new(),
+                new_soa: soa(2),
+                added: vec![RRset::from_str("www.example.org. 300 IN A 192.0.2.2").unwrap()],
+            },
+            Diff {
+                old_soa: soa(2),
+                deleted: vec![RRset::from_str("www.example.org. 300 IN A 192.0.2.2").unwrap()],
+                new_soa: soa(3),
+                added: Vec::new(),
+            },
+        ];
+        let responses = make_ixfr_response(&query, soa(3), diffs.clone());
+        assert_eq!(
+            parse(responses, Some(1)),
+            Some(XfrResult::Incremental(diffs))
+        );
+
+        //primary has no journal for the serial
+        let responses = make_axfr_response(&query, primary_zone(3));
+        match parse(responses, Some(1)) {
+            Some(XfrResult::Full(records)) => assert_eq!(records.len(), 3),
+            _ => panic!("ixfr should fall back to full zone"),
+        }
+
+        let responses = make_xfr_response(&query, vec![soa(1)].into_iter());
+        assert_eq!(parse(responses, Some(1)), Some(XfrResult::UpToDate));
+    }
+
+    #[test]
+    fn test_transfer_from_primary() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let primary = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut buffer = vec![0; (len[0] as usize) << 8 | len[1] as usize];
+            stream.read_exact(&mut buffer).unwrap();
+            let query = Message::from_wire(buffer.as_ref()).unwrap();
+            let mut render = MessageRender::new();
+            for response in make_axfr_response(&query, primary_zone(1)).wait() {
+                let response = response.unwrap();
+                response.rend(&mut render);
+                let data = render.take_data();
+                stream
+                    .write_all(&[(data.len() >> 8) as u8, data.len() as u8])
+                    .unwrap();
+                stream.write_all(&data).unwrap();
+            }
+        });
+
+        let zones = Arc::new(RwLock::new(AuthZone::new(
+            "",
+            100,
+            Notifier::new(KeyRing::default()),
+        )));
+        let conf = AuthZoneConfig {
+            name: "example.org".to_string(),
+            zone_type: ZoneType::Secondary,
+            file_path: String::new(),
+            primaries: vec![primary.to_string()],
+            notify: Vec::new(),
+            allow_transfer_keys: Vec::new(),
+            allow_update_keys: Vec::new(),
+            tsig_key: String::new(),
+            serial_policy: SerialPolicy::Increment,
+            dnssec_keys: Vec::new(),
+            dnssec_policy: None,
+        };
+        let zone = SecondaryZone::new(&conf, zones.clone(), KeyRing::default()).unwrap();
+        let (_trigger, shutdown) = shutdown_channel();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (zone, delay) = rt.block_on(zone.refresh()).unwrap();
+        assert_eq!(delay, Duration::from_secs(3600));
+        assert!(zone.last_refresh.is_some());
+        let soa = zones.read().unwrap().get_soa(zone.get_name()).unwrap();
+        assert_eq!(get_serial(&soa), 1);
+
+        let manager = SecondaryManager::new();
+        manager.add_zone(zone);
+        assert!(manager.has_zone(&Name::new("example.org").unwrap()));
+        rt.block_on(manager.run(shutdown)).unwrap();
+        let name = Name::new("example.org").unwrap();
+        assert_eq!(manager.notify(&name, primary.ip(), None), Rcode::NoError);
+        assert_eq!(
+            manager.notify(&name, "10.0.0.1".parse().unwrap(), None),
+            Rcode::Refused
+        );
+        assert_eq!(
+            manager.notify(&Name::new("example.com").unwrap(), primary.ip(), None),
+            Rcode::NotAuth
+        );
+        manager.remove_zone(&Name::new("example.org").unwrap());
+        assert!(!manager.has_zone(&Name::new("example.org").unwrap()));
+    }
+}
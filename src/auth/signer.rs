@@ -0,0 +1,757 @@
+use super::{
+    dnssec::{make_cds_rrsets, make_dnskey_rrset, DnssecKey},
+    journal::{get_zone_soa, now, JournalUpdater},
+    zones::AuthZone,
+};
+use crate::server::ShutdownListener;
+use datasrc::{MemoryZone, ZoneUpdater};
+use failure::Result;
+use futures::{Future, Stream};
+use r53::{Name, RData, RRClass, RRTtl, RRType, RRset};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio_timer::Interval;
+
+//signature inception is set back to tolerate clock skew of validators
+const INCEPTION_OFFSET: u64 = 3600;
+const DEFAULT_DNSKEY_TTL: u32 = 3600;
+const REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+
+struct Signature {
+    typ: RRType,
+    rrsig: RRset,
+    expiration: u64,
+}
+
+//zone is signed online, signatures generated by the signer are tracked so
+//they could be replaced when the covered rrset is changed or expiring
+pub struct ZoneSigner {
+    origin: Name,
+    keys: Vec<DnssecKey>,
+    //keys in the dnskey rrset which don't sign, like the pre-published
+    //and the retired ones during rollover
+    standby_keys: Vec<DnssecKey>,
+    publish_cds: bool,
+    validity: u64,
+    refresh: u64,
+    signatures: HashMap<Name, Vec<Signature>>,
+    //signatures made before the zone is signed, with the covered rrset,
+    //indexed by the owner and the covered type
+    prepared: HashMap<(Name, u16), (RRset, Signature)>,
+    //zone cuts added or removed by updates, the names below them are
+    //signed again outside the write lock
+    changed_cuts: Vec<Name>,
+}
+
+//rrsets below the changed zone cuts, with the signatures of the ones
+//which become authoritative
+pub struct PreparedDelegations {
+    cuts: Vec<Name>,
+    rrsets: Vec<(Name, RRType)>,
+    signatures: HashMap<(Name, u16), (RRset, Signature)>,
+}
+
+impl ZoneSigner {
+    pub fn new(origin: Name, keys: Vec<DnssecKey>, validity: u64, refresh: u64) -> Self {
+        ZoneSigner {
+            origin,
+            keys,
+            standby_keys: Vec::new(),
+            publish_cds: false,
+            validity,
+            refresh,
+            signatures: HashMap::new(),
+            prepared: HashMap::new(),
+            changed_cuts: Vec::new(),
+        }
+    }
+
+    pub fn set_lifetime(&mut self, validity: u64, refresh: u64) {
+        self.validity = validity;
+        self.refresh = refresh;
+    }
+
+    //keys are managed by the server, so the signing ksks are published
+    //for the parent to update its ds, rfc7344
+    pub fn set_managed_keys(&mut self, standby_keys: Vec<DnssecKey>) {
+        self.standby_keys = standby_keys;
+        self.publish_cds = true;
+    }
+
+    //signatures and dnskeys already in zone are replaced, the zone is
+    //proved by nsec chain generated by the signer, nsec3 isn't supported
+    //so the nsec3 chain of the zone is removed
+    pub fn sign_zone(&mut self, updater: &mut JournalUpdater) -> Result<()> {
+        self.signatures.clear();
+        let ttl = zone_ttl(updater.zone());
+        let rrsets = updater.zone().get_all_rrsets();
+        //signatures of each covered type are a separate rrset
+        for rrsig in rrsets.iter().filter(|rrset| rrset.typ == RRType::RRSIG) {
+            updater.delete_rdata(rrsig)?;
+        }
+        for rrset in rrsets {
+            let is_key = rrset.typ == RRType::DNSKEY
+                || (self.publish_cds && (rrset.typ == RRType::CDS || rrset.typ == RRType::CDNSKEY));
+            let is_nsec3 = rrset.typ == RRType::NSEC3 || rrset.typ == RRType::NSEC3PARAM;
+            if (is_key && rrset.name.eq(&self.origin)) || is_nsec3 {
+                updater.delete_rrset(&rrset.name, rrset.typ)?;
+            }
+        }
+        updater.add_rrset(make_dnskey_rrset(
+            &self.origin,
+            ttl,
+            self.keys.iter().chain(self.standby_keys.iter()),
+        )?)?;
+        if self.publish_cds {
+            for rrset in make_cds_rrsets(&self.origin, ttl, &self.keys)? {
+                updater.add_rrset(rrset)?;
+            }
+        }
+        update_nsec_chain(updater)?;
+        let result = self.sign_all(updater);
+        self.prepared.clear();
+        self.changed_cuts.clear();
+        result
+    }
+
+    //signatures are made with the zone only read, so the zone could be
+    //signed later without blocking queries for long, rrsets changed in the
+    //meantime are signed again
+    pub fn prepare(&mut self, zone: &MemoryZone) -> Result<()> {
+        self.prepared.clear();
+        let rrsets = zone
+            .get_all_rrsets()
+            .into_iter()
+            .filter(|rrset| rrset.typ != RRType::RRSIG && rrset.typ != RRType::NSEC)
+            .chain(make_nsec_chain(zone)?);
+        for rrset in rrsets {
+            if !is_authoritative(zone, &rrset.name, rrset.typ) {
+                continue;
+            }
+            if let Some(signature) = self.make_signature(&rrset)? {
+                self.prepared
+                    .insert((rrset.name.clone(), rrset.typ.to_u16()), (rrset, signature));
+            }
+        }
+        Ok(())
+    }
+
+    fn sign_all(&mut self, updater: &mut JournalUpdater) -> Result<()> {
+        for rrset in updater.zone().get_all_rrsets() {
+            if rrset.typ != RRType::RRSIG {
+                self.sign_rrset(updater, &rrset.name, rrset.typ)?;
+            }
+        }
+        Ok(())
+    }
+
+    //only the nsec of the changed names and their predecessors are
+    //updated, names below the changed zone cuts are left to
+    //prepare_delegations
+    pub fn resign(
+        &mut self,
+        updater: &mut JournalUpdater,
+        changes: Vec<(Name, RRType)>,
+    ) -> Result<()> {
+        let mut names: Vec<Name> = Vec::new();
+        for (name, typ) in changes {
+            if typ == RRType::RRSIG {
+                continue;
+            }
+            self.sign_rrset(updater, &name, typ)?;
+            if typ != RRType::NSEC && !names.iter().any(|n| n.eq(&name)) {
+                names.push(name);
+            }
+        }
+        for name in names {
+            self.update_nsec(updater, &name)?;
+        }
+        self.prepared.clear();
+        Ok(())
+    }
+
+    //delegation added or removed changes the authority of the names below
+    //it, they should be signed again by prepare_delegations
+    pub fn add_changed_cuts(&mut self, changes: &[(Name, RRType)]) {
+        for (name, typ) in changes {
+            if *typ == RRType::NS
+                && !name.eq(&self.origin)
+                && !self.changed_cuts.iter().any(|cut| cut.eq(name))
+            {
+                self.changed_cuts.push(name.clone());
+            }
+        }
+    }
+
+    pub fn has_changed_cuts(&self) -> bool {
+        !self.changed_cuts.is_empty()
+    }
+
+    //like prepare, signatures of the names at and below the changed cuts
+    //are made with the zone only read
+    pub fn prepare_delegations(&self, zone: &MemoryZone) -> Result<PreparedDelegations> {
+        let cuts = self.changed_cuts.clone();
+        let mut rrsets = Vec::new();
+        let mut signatures = HashMap::new();
+        for rrset in zone.get_all_rrsets() {
+            if rrset.typ == RRType::RRSIG
+                || rrset.typ == RRType::NSEC
+                || !cuts.iter().any(|cut| rrset.name.is_subdomain(cut))
+            {
+                continue;
+            }
+            rrsets.push((rrset.name.clone(), rrset.typ));
+            if !is_authoritative(zone, &rrset.name, rrset.typ) {
+                continue;
+            }
+            if let Some(signature) = self.make_signature(&rrset)? {
+                signatures.insert((rrset.name.clone(), rrset.typ.to_u16()), (rrset, signature));
+            }
+        }
+        Ok(PreparedDelegations {
+            cuts,
+            rrsets,
+            signatures,
+        })
+    }
+
+    //prepared signatures are used by the following resign, which should be
+    //given the returned rrsets
+    pub fn take_delegations(&mut self, prepared: PreparedDelegations) -> Vec<(Name, RRType)> {
+        let cuts = prepared.cuts;
+        self.changed_cuts
+            .retain(|name| !cuts.iter().any(|cut| cut.eq(name)));
+        self.prepared = prepared.signatures;
+        prepared.rrsets
+    }
+
+    //name with authoritative data is linked into the nsec chain after its
+    //predecessor, and is unlinked when the data is gone
+    fn update_nsec(&mut self, updater: &mut JournalUpdater, name: &Name) -> Result<()> {
+        let rrsets = updater.zone().get_rrsets(name);
+        let old = rrsets
+            .iter()
+            .find(|rrset| rrset.typ == RRType::NSEC)
+            .cloned();
+        let types = nsec_types(&rrsets);
+        let linked = !types.is_empty() && is_authoritative(updater.zone(), name, RRType::DS);
+        match (old, linked) {
+            (None, false) => {}
+            (Some(old), true) => {
+                let nsec = make_nsec(name, &next_name(&old)?, &types, old.ttl)?;
+                if nsec != old {
+                    self.replace_nsec(updater, nsec)?;
+                }
+            }
+            (None, true) => {
+                let ttl = zone_ttl(updater.zone());
+                match updater.zone().get_previous_nsec(name) {
+                    Some(previous) => {
+                        let next = next_name(&previous)?;
+                        self.replace_nsec(updater, make_nsec(name, &next, &types, ttl)?)?;
+                        self.relink_nsec(updater, &previous, name)?;
+                    }
+                    None => self.replace_nsec(updater, make_nsec(name, name, &types, ttl)?)?,
+                }
+            }
+            (Some(old), false) => {
+                let next = next_name(&old)?;
+                updater.delete_rrset(name, RRType::NSEC)?;
+                self.remove_signature(updater, name, RRType::NSEC);
+                if let Some(previous) = updater.zone().get_previous_nsec(name) {
+                    self.relink_nsec(updater, &previous, &next)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn relink_nsec(
+        &mut self,
+        updater: &mut JournalUpdater,
+        previous: &RRset,
+        next: &Name,
+    ) -> Result<()> {
+        let types = nsec_types(&updater.zone().get_rrsets(&previous.name));
+        self.replace_nsec(
+            updater,
+            make_nsec(&previous.name, next, &types, previous.ttl)?,
+        )
+    }
+
+    fn replace_nsec(&mut self, updater: &mut JournalUpdater, nsec: RRset) -> Result<()> {
+        let name = nsec.name.clone();
+        updater.delete_rrset(&name, RRType::NSEC)?;
+        updater.add_rrset(nsec)?;
+        self.sign_rrset(updater, &name, RRType::NSEC)
+    }
+
+    //rrsets whose signatures expire within the refresh time
+    pub fn expiring(&self, now: u64) -> Vec<(Name, RRType)> {
+        self.signatures
+            .iter()
+            .flat_map(|(name, signatures)| {
+                signatures
+                    .iter()
+                    .filter(|signature| signature.expiration <= now + self.refresh)
+                    .map(move |signature| (name.clone(), signature.typ))
+            })
+            .collect()
+    }
+
+    fn sign_rrset(&mut self, updater: &mut JournalUpdater, name: &Name, typ: RRType) -> Result<()> {
+        self.remove_signature(updater, name, typ);
+        let rrset = match updater
+            .zone()
+            .get_rrsets(name)
+            .into_iter()
+            .find(|rrset| rrset.typ == typ)
+        {
+            Some(rrset) => rrset,
+            None => return Ok(()),
+        };
+        if !is_authoritative(updater.zone(), name, typ) {
+            return Ok(());
+        }
+
+        let signature = match self.prepared.remove(&(name.clone(), typ.to_u16())) {
+            Some((covered, signature)) if covered == rrset => Some(signature),
+            _ => self.make_signature(&rrset)?,
+        };
+        if let Some(signature) = signature {
+            updater.add_rrset(signature.rrsig.clone())?;
+            self.signatures
+                .entry(name.clone())
+                .or_insert_with(Vec::new)
+                .push(signature);
+        }
+        Ok(())
+    }
+
+    fn make_signature(&self, rrset: &RRset) -> Result<Option<Signature>> {
+        let now = now();
+        let inception = now.saturating_sub(INCEPTION_OFFSET);
+        let expiration = now + self.validity;
+        let rdatas = self
+            .signing_keys(rrset.typ)
+            .map(|key| key.sign_rrset(rrset, &self.origin, inception, expiration))
+            .collect::<Result<Vec<RData>>>()?;
+        if rdatas.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Signature {
+            typ: rrset.typ,
+            rrsig: RRset {
+                name: rrset.name.clone(),
+                typ: RRType::RRSIG,
+                class: RRClass::IN,
+                ttl: rrset.ttl,
+                rdatas,
+            },
+            expiration,
+        }))
+    }
+
+    //signature may be removed with its name already
+    fn remove_signature(&mut self, updater: &mut JournalUpdater, name: &Name, typ: RRType) {
+        let is_empty = match self.signatures.get_mut(name) {
+            Some(signatures) => {
+                if let Some(pos) = signatures.iter().position(|s| s.typ == typ) {
+                    let _ = updater.delete_rdata(&signatures.remove(pos).rrsig);
+                }
+                signatures.is_empty()
+            }
+            None => return,
+        };
+        if is_empty {
+            self.signatures.remove(name);
+        }
+    }
+
+    //ksk only signs the dnskey rrset and the cds rrsets which should be
+    //validated by the ds in parent, zsk signs the others, key of the only
+    //kind signs all rrsets
+    fn signing_keys<'a>(&'a self, typ: RRType) -> impl Iterator<Item = &'a DnssecKey> + 'a {
+        let use_ksk = if self.keys.iter().all(|key| key.is_ksk()) {
+            true
+        } else if self.keys.iter().all(|key| !key.is_ksk()) {
+            false
+        } else {
+            typ == RRType::DNSKEY || typ == RRType::CDS || typ == RRType::CDNSKEY
+        };
+        self.keys.iter().filter(move |key| key.is_ksk() == use_ksk)
+    }
+}
+
+fn zone_ttl(zone: &MemoryZone) -> RRTtl {
+    get_zone_soa(zone)
+        .map(|soa| soa.ttl)
+        .unwrap_or(RRTtl(DEFAULT_DNSKEY_TTL))
+}
+
+//types in the bitmap of nsec, rrsig and nsec are appended when it's made
+fn nsec_types(rrsets: &[RRset]) -> Vec<RRType> {
+    rrsets
+        .iter()
+        .filter(|rrset| rrset.typ != RRType::NSEC && rrset.typ != RRType::RRSIG)
+        .map(|rrset| rrset.typ)
+        .collect()
+}
+
+fn make_nsec(name: &Name, next: &Name, types: &[RRType], ttl: RRTtl) -> Result<RRset> {
+    let types = types
+        .iter()
+        .map(|typ| typ.to_string())
+        .collect::<Vec<String>>()
+        .join(" ");
+    Ok(RRset {
+        name: name.clone(),
+        typ: RRType::NSEC,
+        class: RRClass::IN,
+        ttl,
+        rdatas: vec![RData::from_str(
+            RRType::NSEC,
+            &format!("{} {} RRSIG NSEC", next, types),
+        )?],
+    })
+}
+
+//next owner name is the first field of nsec
+fn next_name(nsec: &RRset) -> Result<Name> {
+    let rdata = nsec.rdatas[0].to_string();
+    let next = rdata.split_whitespace().next().unwrap_or_default();
+    Ok(Name::new(next)?)
+}
+
+//nsec chain links the authoritative names and the zone cuts in dns order,
+//names below zone cut are skipped, rfc4035 section 2.3
+fn make_nsec_chain(zone: &MemoryZone) -> Result<Vec<RRset>> {
+    let ttl = zone_ttl(zone);
+    let mut owners: Vec<(Name, Vec<RRType>)> = Vec::new();
+    for rrset in zone.get_all_rrsets() {
+        if rrset.typ == RRType::NSEC || rrset.typ == RRType::RRSIG {
+            continue;
+        }
+        match owners.last_mut() {
+            Some((name, types)) if name.eq(&rrset.name) => types.push(rrset.typ),
+            _ => owners.push((rrset.name, vec![rrset.typ])),
+        }
+    }
+    owners.retain(|(name, _)| is_authoritative(zone, name, RRType::DS));
+
+    let mut chain = Vec::with_capacity(owners.len());
+    for (i, (name, types)) in owners.iter().enumerate() {
+        let next = &owners[(i + 1) % owners.len()].0;
+        chain.push(make_nsec(name, next, types, ttl)?);
+    }
+    Ok(chain)
+}
+
+//the whole chain is compared with the one in zone when the zone is
+//signed, nsec which isn't changed is kept
+fn update_nsec_chain(updater: &mut JournalUpdater) -> Result<()> {
+    let mut current = updater
+        .zone()
+        .get_all_rrsets()
+        .into_iter()
+        .filter(|rrset| rrset.typ == RRType::NSEC)
+        .map(|rrset| (rrset.name.clone(), rrset))
+        .collect::<HashMap<Name, RRset>>();
+    for nsec in make_nsec_chain(updater.zone())? {
+        if let Some(old) = current.remove(&nsec.name) {
+            if old == nsec {
+                continue;
+            }
+            updater.delete_rrset(&nsec.name, RRType::NSEC)?;
+        }
+        updater.add_rrset(nsec)?;
+    }
+    for name in current.keys() {
+        updater.delete_rrset(name, RRType::NSEC)?;
+    }
+    Ok(())
+}
+
+//data below zone cut isn't signed, except the ds at the cut
+fn is_authoritative(zone: &MemoryZone, name: &Name, typ: RRType) -> bool {
+    let origin = zone.get_origin();
+    let mut current = name.clone();
+    while !current.eq(origin) && current.is_subdomain(origin) {
+        let is_cut = zone
+            .get_rrsets(&current)
+            .iter()
+            .any(|rrset| rrset.typ == RRType::NS);
+        if is_cut && !(current.eq(name) && typ == RRType::DS) {
+            return false;
+        }
+        current = match current.parent(1) {
+            Ok(parent) => parent,
+            Err(_) => break,
+        };
+    }
+    true
+}
+
+//keys are generated and the zone is signed with them outside the write
+//lock, which is only held to apply the signatures, so queries aren't
+//blocked by the key generation and signing
+pub fn roll_keys(zones: &RwLock<AuthZone>) {
+    let now = now();
+    let managers = zones.write().unwrap().take_key_managers();
+    for (name, mut manager) in managers {
+        let result = manager.rollover(now).and_then(|changed| {
+            if !changed {
+                return Ok(());
+            }
+            let signer = zones
+                .read()
+                .unwrap()
+                .make_managed_signer(&name, &manager, now)?;
+            zones.write().unwrap().sign_zone(&name, signer)?;
+            manager.mark_applied(now);
+            Ok(())
+        });
+        if let Err(e) = result {
+            eprintln!("roll keys of zone {} failed: {}", name, e);
+        }
+        zones.write().unwrap().restore_key_manager(name, manager);
+    }
+}
+
+//signatures of the names below the changed delegations are made with the
+//read lock, only the changed rrsets are signed with the write lock
+pub fn resign_delegations(zones: &RwLock<AuthZone>) {
+    let names = zones.read().unwrap().zones_with_changed_cuts();
+    for name in names {
+        let prepared = zones.read().unwrap().prepare_delegations(&name);
+        let result = prepared
+            .and_then(|prepared| zones.write().unwrap().resign_delegations(&name, prepared));
+        if let Err(e) = result {
+            eprintln!("resign delegations of zone {} failed: {}", name, e);
+        }
+    }
+}
+
+//keys and signatures are checked hourly, so the refresh time and the key
+//propagation time should be much longer than that
+pub fn run_refresher(
+    zones: Arc<RwLock<AuthZone>>,
+    shutdown: ShutdownListener,
+) -> impl Future<Item = (), Error = ()> + Send {
+    Interval::new_interval(REFRESH_INTERVAL)
+        .map_err(|e| eprintln!("timer get err {:?}", e))
+        .for_each(move |_| {
+            roll_keys(&zones);
+            resign_delegations(&zones);
+            zones.write().unwrap().refresh_signatures();
+            Ok(())
+        })
+        .select2(shutdown)
+        .then(|_| Ok(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{
+            dnssec::DnssecAlgorithm,
+            journal::{get_serial, serial_lt},
+            key_manager::KeyManager,
+            notify::Notifier,
+        },
+        config::DnssecPolicyConfig,
+        server::KeyRing,
+    };
+    use datasrc::load_zone_file;
+    use ring::{rand::SystemRandom, signature::Ed25519KeyPair};
+    use std::{env, fs, process, str::FromStr};
+
+    fn generate_key(ksk: bool) -> DnssecKey {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        DnssecKey::from_pkcs8(DnssecAlgorithm::Ed25519, pkcs8.as_ref(), ksk).unwrap()
+    }
+
+    fn get_rrset(zone: &MemoryZone, name: &str, typ: RRType) -> Option<RRset> {
+        zone.get_rrsets(&Name::new(name).unwrap())
+            .into_iter()
+            .find(|rrset| rrset.typ == typ)
+    }
+
+    #[test]
+    fn test_sign_zone() {
+        let name = Name::new("example.org").unwrap();
+        let mut zone = load_zone_file(name.clone(), "testdata/example.org.zone").unwrap();
+        let mut signer = ZoneSigner::new(
+            name.clone(),
+            vec![generate_key(true), generate_key(false)],
+            86400,
+            3600,
+        );
+        let mut updater = JournalUpdater::new(&mut zone);
+        signer.sign_zone(&mut updater).unwrap();
+        assert!(signer.expiring(now()).is_empty());
+        assert!(!signer.expiring(now() + 86400).is_empty());
+
+        let dnskey = get_rrset(updater.zone(), "example.org", RRType::DNSKEY).unwrap();
+        assert_eq!(dnskey.rdatas.len(), 2);
+        //every authoritative rrset is signed by one key
+        let rrsets = updater.zone().get_rrsets(&name);
+        let (rrsigs, covered): (Vec<RRset>, Vec<RRset>) = rrsets
+            .into_iter()
+            .partition(|rrset| rrset.typ == RRType::RRSIG);
+        assert_eq!(rrsigs.len(), covered.len());
+        assert!(rrsigs.iter().all(|rrsig| rrsig.rdatas.len() == 1));
+        assert!(get_rrset(updater.zone(), "cname.example.org", RRType::RRSIG).is_some());
+        //delegation and glue aren't signed, only nsec of the delegation is
+        let child = Name::new("child.example.org").unwrap();
+        let types = signer.signatures[&child]
+            .iter()
+            .map(|signature| signature.typ)
+            .collect::<Vec<RRType>>();
+        assert_eq!(types, vec![RRType::NSEC]);
+        assert!(get_rrset(updater.zone(), "ns.child.example.org", RRType::RRSIG).is_none());
+        assert!(get_rrset(updater.zone(), "ns.child.example.org", RRType::NSEC).is_none());
+        //nsec chain is a loop in dns order
+        let nsec = get_rrset(updater.zone(), "foo.wild.example.org", RRType::NSEC).unwrap();
+        assert!(nsec.rdatas[0].to_string().starts_with("example.org. "));
+
+        let next = |zone: &MemoryZone, name: &str| {
+            let nsec = get_rrset(zone, name, RRType::NSEC).unwrap();
+            next_name(&nsec).unwrap().to_string()
+        };
+        assert_eq!(next(updater.zone(), "ns.example.org"), "txt.example.org.");
+        let rrset = RRset::from_str("ntp.example.org. 300 IN A 192.0.2.10").unwrap();
+        updater.add_rrset(rrset.clone()).unwrap();
+        signer
+            .resign(&mut updater, vec![(rrset.name.clone(), RRType::A)])
+            .unwrap();
+        assert!(get_rrset(updater.zone(), "ntp.example.org", RRType::RRSIG).is_some());
+        //name is linked into the nsec chain after its predecessor
+        assert_eq!(next(updater.zone(), "ns.example.org"), "ntp.example.org.");
+        assert_eq!(next(updater.zone(), "ntp.example.org"), "txt.example.org.");
+        updater.delete_rrset(&rrset.name, RRType::A).unwrap();
+        signer
+            .resign(&mut updater, vec![(rrset.name.clone(), RRType::A)])
+            .unwrap();
+        assert!(get_rrset(updater.zone(), "ntp.example.org", RRType::RRSIG).is_none());
+        assert!(get_rrset(updater.zone(), "ntp.example.org", RRType::NSEC).is_none());
+        assert_eq!(next(updater.zone(), "ns.example.org"), "txt.example.org.");
+    }
+
+    #[test]
+    fn test_prepared_signatures() {
+        let name = Name::new("example.org").unwrap();
+        let mut zone = load_zone_file(name.clone(), "testdata/example.org.zone").unwrap();
+        let mut signer = ZoneSigner::new(name.clone(), vec![generate_key(true)], 86400, 3600);
+        signer.prepare(&zone).unwrap();
+        let prepared = |signer: &ZoneSigner, name: &str, typ: RRType| {
+            let key = (Name::new(name).unwrap(), typ.to_u16());
+            signer.prepared[&key].1.rrsig.clone()
+        };
+        let txt = prepared(&signer, "txt.example.org", RRType::TXT);
+        let ns = prepared(&signer, "ns.example.org", RRType::A);
+
+        //rrset changed after the signatures are prepared is signed again
+        let mut updater = JournalUpdater::new(&mut zone);
+        let rrset = RRset::from_str("ns.example.org. 300 IN A 192.0.2.20").unwrap();
+        updater.add_rrset(rrset).unwrap();
+        signer.sign_zone(&mut updater).unwrap();
+        assert!(signer.prepared.is_empty());
+        let signature = |name: &str, typ: RRType| {
+            signer.signatures[&Name::new(name).unwrap()]
+                .iter()
+                .find(|signature| signature.typ == typ)
+                .map(|signature| signature.rrsig.clone())
+                .unwrap()
+        };
+        assert_eq!(signature("txt.example.org", RRType::TXT), txt);
+        assert_ne!(signature("ns.example.org", RRType::A), ns);
+    }
+
+    #[test]
+    fn test_resign_on_update() {
+        let name = Name::new("example.org").unwrap();
+        let zone = load_zone_file(name.clone(), "testdata/example.org.zone").unwrap();
+        let mut zones = AuthZone::new("", 100, Notifier::new(KeyRing::default()));
+        zones.insert_zone(zone).unwrap();
+        zones
+            .set_zone_signer(&name, vec![generate_key(true)])
+            .unwrap();
+        let soa = zones.get_soa(&name).unwrap();
+        assert_eq!(get_serial(&soa), 101);
+
+        let rrset = RRset::from_str("mail.example.org. 300 IN A 192.0.2.10").unwrap();
+        zones
+            .update_zone(&name, |updater| updater.add_rrset(rrset))
+            .unwrap();
+        let new_soa = zones.get_soa(&name).unwrap();
+        assert!(serial_lt(get_serial(&soa), get_serial(&new_soa)));
+        let zone = zones.get_zone(&name).unwrap();
+        assert!(get_rrset(zone, "mail.example.org", RRType::RRSIG).is_some());
+
+        //glue becomes authoritative after the delegation is removed, it's
+        //signed after the update
+        let child = Name::new("child.example.org").unwrap();
+        zones
+            .update_zone(&name, |updater| updater.delete_rrset(&child, RRType::NS))
+            .unwrap();
+        assert_eq!(zones.zones_with_changed_cuts(), vec![name.clone()]);
+        let zone = zones.get_zone(&name).unwrap();
+        assert!(get_rrset(zone, "ns.child.example.org", RRType::RRSIG).is_none());
+        let zones = RwLock::new(zones);
+        resign_delegations(&zones);
+        let zones = zones.into_inner().unwrap();
+        assert!(zones.zones_with_changed_cuts().is_empty());
+        let zone = zones.get_zone(&name).unwrap();
+        assert!(get_rrset(zone, "ns.child.example.org", RRType::RRSIG).is_some());
+        assert!(get_rrset(zone, "ns.child.example.org", RRType::NSEC).is_some());
+        //the nested delegation is still a cut
+        assert!(get_rrset(zone, "ns.grand.child.example.org", RRType::NSEC).is_none());
+    }
+
+    #[test]
+    fn test_managed_keys() {
+        let dir = env::temp_dir().join(format!("vanguard2_test_managed_keys_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let name = Name::new("example.org").unwrap();
+        let zone = load_zone_file(name.clone(), "testdata/example.org.zone").unwrap();
+        let mut zones = AuthZone::new("", 100, Notifier::new(KeyRing::default()));
+        zones.insert_zone(zone).unwrap();
+        let manager =
+            KeyManager::load(&name, &DnssecPolicyConfig::default(), dir.to_str().unwrap()).unwrap();
+        zones.set_key_manager(&name, manager).unwrap();
+        assert!(dir.join("example.org.keys").exists());
+
+        let zone = zones.get_zone(&name).unwrap();
+        let dnskey = get_rrset(zone, "example.org", RRType::DNSKEY).unwrap();
+        assert_eq!(dnskey.rdatas.len(), 2);
+        //only the ksk is published for the parent
+        assert_eq!(
+            get_rrset(zone, "example.org", RRType::CDS)
+                .unwrap()
+                .rdatas
+                .len(),
+            1
+        );
+        assert_eq!(
+            get_rrset(zone, "example.org", RRType::CDNSKEY)
+                .unwrap()
+                .rdatas
+                .len(),
+            1
+        );
+
+        //nothing changes before the keys retire
+        let soa = zones.get_soa(&name).unwrap();
+        let zones = RwLock::new(zones);
+        roll_keys(&zones);
+        let mut zones = zones.into_inner().unwrap();
+        assert_eq!(get_serial(&soa), get_serial(&zones.get_soa(&name).unwrap()));
+        assert_eq!(zones.take_key_managers().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
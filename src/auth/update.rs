@@ -0,0 +1,422 @@
+use super::{
+    journal::{get_serial, serial_lt, split_rrset, JournalUpdater},
+    zones::AuthZone,
+};
+use datasrc::{ZoneFinder, ZoneUpdater};
+use failure::Result;
+use r53::{message::SectionType, Message, MessageBuilder, Name, RRClass, RRType, RRset, Rcode};
+
+//dns update defined in rfc2136, prerequisites are checked and update
+//section is prescanned before any change is made, and the changes are
+//rolled back if any of them fails, so the update is applied atomically
+pub fn handle_update(zones: &mut AuthZone, request: &mut Message) {
+    let prerequisites = request
+        .take_section(SectionType::Answer)
+        .unwrap_or_default();
+    let updates = request
+        .take_section(SectionType::Authority)
+        .unwrap_or_default();
+    let rcode = match request.question.as_ref() {
+        Some(question) if question.typ == RRType::SOA => {
+            let zone = question.name.clone();
+            update_zone(zones, &zone, &prerequisites, updates).unwrap_or_else(|e| {
+                eprintln!("update zone {} failed: {}", zone.to_string(), e);
+                Rcode::ServFail
+            })
+        }
+        _ => Rcode::FormErr,
+    };
+    make_update_response(request, rcode);
+}
+
+//prerequisite and update sections aren't echoed back
+pub fn make_update_response(request: &mut Message, rcode: Rcode) {
+    for &section in &[
+        SectionType::Answer,
+        SectionType::Authority,
+        SectionType::Additional,
+    ] {
+        request.take_section(section);
+    }
+    MessageBuilder::new(request)
+        .make_response()
+        .rcode(rcode)
+        .done();
+}
+
+fn update_zone(
+    zones: &mut AuthZone,
+    zone: &Name,
+    prerequisites: &[RRset],
+    updates: Vec<RRset>,
+) -> Result<Rcode> {
+    if !zones.has_zone(zone) {
+        return Ok(Rcode::NotAuth);
+    }
+
+    zones.update_zone(zone, |updater| {
+        if let Some(rcode) = check_prerequisites(updater, prerequisites) {
+            return Ok(rcode);
+        }
+        if let Some(rcode) = prescan(zone, &updates) {
+            return Ok(rcode);
+        }
+        for rrset in updates {
+            if let Err(e) = apply_update(updater, rrset) {
+                updater.rollback()?;
+                return Err(e);
+            }
+        }
+        Ok(Rcode::NoError)
+    })
+}
+
+fn check_prerequisites(updater: &JournalUpdater, prerequisites: &[RRset]) -> Option<Rcode> {
+    let zone = updater.zone();
+    //value dependent prerequisites are compared with the whole rrset
+    let mut expected: Vec<RRset> = Vec::new();
+    for prerequisite in prerequisites {
+        if prerequisite.ttl.0 != 0 {
+            return Some(Rcode::FormErr);
+        }
+        if !prerequisite.name.is_subdomain(zone.get_origin()) {
+            return Some(Rcode::NotZone);
+        }
+
+        let rrsets = zone.get_rrsets(&prerequisite.name);
+        let rrset_exists = rrsets.iter().any(|rrset| rrset.typ == prerequisite.typ);
+        match prerequisite.class {
+            RRClass::ANY | RRClass::NONE if !prerequisite.rdatas.is_empty() => {
+                return Some(Rcode::FormErr);
+            }
+            RRClass::ANY if prerequisite.typ == RRType::ANY => {
+                if rrsets.is_empty() {
+                    return Some(Rcode::NXDomain);
+                }
+            }
+            RRClass::ANY => {
+                if !rrset_exists {
+                    return Some(Rcode::NXRRset);
+                }
+            }
+            RRClass::NONE if prerequisite.typ == RRType::ANY => {
+                if !rrsets.is_empty() {
+                    return Some(Rcode::YXDomain);
+                }
+            }
+            RRClass::NONE => {
+                if rrset_exists {
+                    return Some(Rcode::YXRRset);
+                }
+            }
+            RRClass::IN if !is_meta_type(prerequisite.typ) => {
+                match expected.iter_mut().find(|rrset| {
+                    rrset.typ == prerequisite.typ && rrset.name.eq(&prerequisite.name)
+                }) {
+                    Some(rrset) => rrset.rdatas.extend(prerequisite.rdatas.iter().cloned()),
+                    None => expected.push(prerequisite.clone()),
+                }
+            }
+            _ => return Some(Rcode::FormErr),
+        }
+    }
+
+    for rrset in expected {
+        let matched = zone
+            .get_rrsets(&rrset.name)
+            .into_iter()
+            .find(|current| current.typ == rrset.typ)
+            .map(|current| has_same_rdatas(&current, &rrset))
+            .unwrap_or(false);
+        if !matched {
+            return Some(Rcode::NXRRset);
+        }
+    }
+    None
+}
+
+fn prescan(zone: &Name, updates: &[RRset]) -> Option<Rcode> {
+    for rrset in updates {
+        if !rrset.name.is_subdomain(zone) {
+            return Some(Rcode::NotZone);
+        }
+        let valid = match rrset.class {
+            RRClass::IN => !is_meta_type(rrset.typ) && !rrset.rdatas.is_empty(),
+            RRClass::ANY => {
+                rrset.ttl.0 == 0
+                    && rrset.rdatas.is_empty()
+                    && (rrset.typ == RRType::ANY || !is_meta_type(rrset.typ))
+            }
+            RRClass::NONE => {
+                rrset.ttl.0 == 0 && !is_meta_type(rrset.typ) && !rrset.rdatas.is_empty()
+            }
+            _ => false,
+        };
+        if !valid {
+            return Some(Rcode::FormErr);
+        }
+    }
+    None
+}
+
+fn apply_update(updater: &mut JournalUpdater, rrset: RRset) -> Result<()> {
+    match rrset.class {
+        RRClass::ANY => delete_rrsets(updater, &rrset.name, rrset.typ),
+        RRClass::NONE => {
+            for record in split_rrset(rrset) {
+                delete_record(updater, record)?;
+            }
+            Ok(())
+        }
+        _ => {
+            for record in split_rrset(rrset) {
+                add_record(updater, record)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+//soa and ns of the zone apex can't be deleted as a whole
+fn delete_rrsets(updater: &mut JournalUpdater, name: &Name, typ: RRType) -> Result<()> {
+    let is_apex = name.eq(updater.zone().get_origin());
+    let types = updater
+        .zone()
+        .get_rrsets(name)
+        .into_iter()
+        .map(|rrset| rrset.typ)
+        .filter(|&current| typ == RRType::ANY || current == typ)
+        .filter(|&current| !is_apex || (current != RRType::SOA && current != RRType::NS))
+        .collect::<Vec<RRType>>();
+    for typ in types {
+        updater.delete_rrset(name, typ)?;
+    }
+    Ok(())
+}
+
+//record which doesn't exist is ignored, and the last ns of the zone
+//apex is kept
+fn delete_record(updater: &mut JournalUpdater, record: RRset) -> Result<()> {
+    if record.typ == RRType::SOA {
+        return Ok(());
+    }
+    let is_apex = record.name.eq(updater.zone().get_origin());
+    let current = updater
+        .zone()
+        .get_rrsets(&record.name)
+        .into_iter()
+        .find(|rrset| rrset.typ == record.typ);
+    match current {
+        Some(ref rrset) if rrset.rdatas.contains(&record.rdatas[0]) => {
+            if is_apex && record.typ == RRType::NS && rrset.rdatas.len() == 1 {
+                return Ok(());
+            }
+            updater.delete_rdata(&record)
+        }
+        _ => Ok(()),
+    }
+}
+
+//cname can't coexist with other rrsets, so the conflicting record is
+//ignored, soa is only replaced by the one with bigger serial
+fn add_record(updater: &mut JournalUpdater, record: RRset) -> Result<()> {
+    let rrsets = updater.zone().get_rrsets(&record.name);
+    if record.typ == RRType::SOA {
+        let replace = record.name.eq(updater.zone().get_origin())
+            && rrsets
+                .iter()
+                .find(|rrset| rrset.typ == RRType::SOA)
+                .map(|soa| serial_lt(get_serial(soa), get_serial(&record)))
+                .unwrap_or(false);
+        return if replace {
+            updater.add_rrset(record)
+        } else {
+            Ok(())
+        };
+    }
+
+    let conflict = rrsets
+        .iter()
+        .any(|rrset| (record.typ == RRType::CNAME) != (rrset.typ == RRType::CNAME));
+    if conflict {
+        return Ok(());
+    }
+    let current = rrsets.into_iter().find(|rrset| rrset.typ == record.typ);
+    match current {
+        //duplicate record replaces the existing one, only ttl may change
+        Some(ref rrset) if rrset.rdatas.contains(&record.rdatas[0]) => {
+            if rrset.ttl.0 != record.ttl.0 {
+                updater.delete_rdata(&record)?;
+                updater.add_rrset(record)?;
+            }
+            Ok(())
+        }
+        _ => updater.add_rrset(record),
+    }
+}
+
+fn has_same_rdatas(a: &RRset, b: &RRset) -> bool {
+    a.rdatas.iter().all(|rdata| b.rdatas.contains(rdata))
+        && b.rdatas.iter().all(|rdata| a.rdatas.contains(rdata))
+}
+
+fn is_meta_type(typ: RRType) -> bool {
+    match typ {
+        RRType::ANY | RRType::AXFR | RRType::IXFR | RRType::OPT => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auth::notify::Notifier, server::KeyRing};
+    use r53::{Opcode, RRTtl};
+    use std::str::FromStr;
+
+    const ZONE: &str =
+        "example.org. 300 IN SOA ns.example.org. root.example.org. 100 1800 900 604800 86400
+example.org. 300 IN NS ns.example.org.
+ns.example.org. 300 IN A 192.0.2.1
+www.example.org. 300 IN A 192.0.2.2";
+
+    fn empty_rrset(name: &str, typ: RRType, class: RRClass) -> RRset {
+        RRset {
+            name: Name::new(name).unwrap(),
+            typ,
+            class,
+            ttl: RRTtl(0),
+            rdatas: Vec::new(),
+        }
+    }
+
+    fn delete_record(s: &str) -> RRset {
+        let mut rrset = RRset::from_str(s).unwrap();
+        rrset.class = RRClass::NONE;
+        rrset.ttl = RRTtl(0);
+        rrset
+    }
+
+    fn update(zones: &mut AuthZone, prerequisites: Vec<RRset>, updates: Vec<RRset>) -> Rcode {
+        let mut request = Message::with_query(Name::new("example.org").unwrap(), RRType::SOA);
+        let mut builder = MessageBuilder::new(&mut request);
+        builder.opcode(Opcode::Update);
+        for rrset in prerequisites {
+            builder.add_answer(rrset);
+        }
+        for rrset in updates {
+            builder.add_auth(rrset);
+        }
+        builder.done();
+        handle_update(zones, &mut request);
+        assert_eq!(request.header.an_count, 0);
+        assert_eq!(request.header.ns_count, 0);
+        request.header.rcode
+    }
+
+    fn get_a(zones: &AuthZone, name: &str) -> Option<RRset> {
+        let mut query = Message::with_query(Name::new(name).unwrap(), RRType::A);
+        zones.handle_query(&mut query, false);
+        query
+            .take_section(SectionType::Answer)
+            .and_then(|mut answers| answers.pop())
+    }
+
+    fn get_serial_of(zones: &AuthZone) -> u32 {
+        get_serial(&zones.get_soa(&Name::new("example.org").unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_check_prerequisites() {
+        let mut zones = AuthZone::new("", 100, Notifier::new(KeyRing::default()));
+        zones
+            .add_zone(Name::new("example.org").unwrap(), ZONE)
+            .unwrap();
+        let add = RRset::from_str("mail.example.org. 300 IN A 192.0.2.3").unwrap();
+
+        for (prerequisite, rcode) in vec![
+            (
+                empty_rrset("mail.example.org", RRType::ANY, RRClass::ANY),
+                Rcode::NXDomain,
+            ),
+            (
+                empty_rrset("www.example.org", RRType::AAAA, RRClass::ANY),
+                Rcode::NXRRset,
+            ),
+            (
+                empty_rrset("www.example.org", RRType::ANY, RRClass::NONE),
+                Rcode::YXDomain,
+            ),
+            (
+                empty_rrset("www.example.org", RRType::A, RRClass::NONE),
+                Rcode::YXRRset,
+            ),
+            (
+                RRset::from_str("www.example.org. 0 IN A 192.0.2.3").unwrap(),
+                Rcode::NXRRset,
+            ),
+            (
+                RRset::from_str("www.example.org. 300 IN A 192.0.2.2").unwrap(),
+                Rcode::FormErr,
+            ),
+            (
+                empty_rrset("www.example.com", RRType::A, RRClass::ANY),
+                Rcode::NotZone,
+            ),
+        ] {
+            assert_eq!(
+                update(&mut zones, vec![prerequisite], vec![add.clone()]),
+                rcode
+            );
+        }
+        assert!(get_a(&zones, "mail.example.org").is_none());
+        assert_eq!(get_serial_of(&zones), 100);
+
+        let prerequisites = vec![
+            empty_rrset("www.example.org", RRType::A, RRClass::ANY),
+            empty_rrset("mail.example.org", RRType::ANY, RRClass::NONE),
+            RRset::from_str("www.example.org. 0 IN A 192.0.2.2").unwrap(),
+        ];
+        assert_eq!(
+            update(&mut zones, prerequisites, vec![add.clone()]),
+            Rcode::NoError
+        );
+        assert!(get_a(&zones, "mail.example.org").is_some());
+        assert_eq!(get_serial_of(&zones), 101);
+    }
+
+    #[test]
+    fn test_apply_update() {
+        let mut zones = AuthZone::new("", 100, Notifier::new(KeyRing::default()));
+        zones
+            .add_zone(Name::new("example.org").unwrap(), ZONE)
+            .unwrap();
+
+        let updates = vec![
+            RRset::from_str("www.example.org. 300 IN A 192.0.2.3").unwrap(),
+            delete_record("www.example.org. 300 IN A 192.0.2.2"),
+            RRset::from_str("www.example.org. 300 IN CNAME ns.example.org.").unwrap(),
+            empty_rrset("example.org", RRType::NS, RRClass::ANY),
+        ];
+        assert_eq!(update(&mut zones, Vec::new(), updates), Rcode::NoError);
+        let www = get_a(&zones, "www.example.org").unwrap();
+        assert_eq!(www.rdatas.len(), 1);
+        assert_eq!(www.rdatas[0].to_string(), "192.0.2.3");
+        assert_eq!(get_serial_of(&zones), 101);
+
+        //update section is prescanned before any change is made
+        let mut invalid = delete_record("www.example.org. 300 IN A 192.0.2.3");
+        invalid.ttl = RRTtl(300);
+        let updates = vec![
+            empty_rrset("www.example.org", RRType::ANY, RRClass::ANY),
+            invalid,
+        ];
+        assert_eq!(update(&mut zones, Vec::new(), updates), Rcode::FormErr);
+        assert!(get_a(&zones, "www.example.org").is_some());
+
+        let updates = vec![empty_rrset("www.example.org", RRType::ANY, RRClass::ANY)];
+        assert_eq!(update(&mut zones, Vec::new(), updates), Rcode::NoError);
+        assert!(get_a(&zones, "www.example.org").is_none());
+        assert_eq!(get_serial_of(&zones), 102);
+    }
+}
@@ -0,0 +1,281 @@
+use super::journal::{get_zone_soa, Diff};
+use crate::server::XfrResponse;
+use datasrc::MemoryZone;
+use futures::stream;
+use r53::{
+    header_flag::HeaderFlag, message::SectionType, Message, MessageBuilder, MessageRender, RRType,
+    RRset, Rcode,
+};
+use std::{iter, sync::Arc};
+
+//message over tcp is limited to 64k, leave room for the tsig record
+//which is appended after the message is rendered
+const MAX_XFR_MESSAGE_LEN: usize = 65535 - 1024;
+
+//zone is a snapshot shared with the zone tree, its rrsets are copied out
+//when the first message is polled, soa is the first and the last record
+pub fn make_axfr_response(query: &Message, zone: Arc<MemoryZone>) -> XfrResponse {
+    let soa = match get_zone_soa(&zone) {
+        Some(soa) => soa,
+        None => return make_error_xfr_response(query, Rcode::ServFail),
+    };
+
+    let rrsets = iter::once(zone)
+        .flat_map(|zone| zone.get_all_rrsets())
+        .filter(|rrset| rrset.typ != RRType::SOA);
+    let records = iter::once(soa.clone()).chain(rrsets).chain(iter::once(soa));
+    make_xfr_response(query, records)
+}
+
+//each diff starts with the old soa and the deleted records, followed by
+//the new soa and the added records, current soa is the first and the last
+pub fn make_ixfr_response(query: &Message, soa: RRset, diffs: Vec<Diff>) -> XfrResponse {
+    let changes = diffs.into_iter().flat_map(|diff| {
+        iter::once(diff.old_soa)
+            .chain(diff.deleted)
+            .chain(iter::once(diff.new_soa))
+            .chain(diff.added)
+    });
+    let records = iter::once(soa.clone())
+        .chain(changes)
+        .chain(iter::once(soa));
+    make_xfr_response(query, records)
+}
+
+pub fn make_xfr_response<I>(query: &Message, records: I) -> XfrResponse
+where
+    I: Iterator<Item = RRset> + Send + 'static,
+{
+    Box::new(stream::iter_ok(XfrMessages::new(query, records)))
+}
+
+pub fn make_error_xfr_response(query: &Message, rcode: Rcode) -> XfrResponse {
+    Box::new(stream::once(Ok(make_error_response(query, rcode))))
+}
+
+//records are added until the rendered message reaches the limit, big
+//rrset is split to fill up the messages
+struct XfrMessages<I> {
+    query: Message,
+    records: I,
+    //rest of the rrset which doesn't fit into the last message
+    pending: Option<RRset>,
+    probe: Message,
+    render: MessageRender,
+    empty_len: usize,
+    finished: bool,
+}
+
+impl<I: Iterator<Item = RRset>> XfrMessages<I> {
+    fn new(query: &Message, records: I) -> Self {
+        let mut render = MessageRender::new();
+        let probe = new_response(query);
+        probe.rend(&mut render);
+        let empty_len = render.data().len();
+        XfrMessages {
+            query: query.clone(),
+            records,
+            pending: None,
+            probe,
+            render,
+            empty_len,
+            finished: false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = RRset>> Iterator for XfrMessages<I> {
+    type Item = Message;
+
+    fn next(&mut self) -> Option<Message> {
+        if self.finished {
+            return None;
+        }
+
+        let mut response = new_response(&self.query);
+        let mut len = self.empty_len;
+        loop {
+            let rrset = match self.pending.take().or_else(|| self.records.next()) {
+                Some(rrset) => rrset,
+                None => {
+                    self.finished = true;
+                    return Some(response);
+                }
+            };
+            let RRset {
+                name,
+                typ,
+                class,
+                ttl,
+                rdatas,
+            } = rrset;
+            let mut fitted = RRset {
+                name,
+                typ,
+                class,
+                ttl,
+                rdatas: Vec::new(),
+            };
+            let mut rdatas = rdatas.into_iter();
+            while let Some(rdata) = rdatas.next() {
+                let record = RRset {
+                    name: fitted.name.clone(),
+                    typ,
+                    class,
+                    ttl,
+                    rdatas: vec![rdata],
+                };
+                let (record_len, mut record) =
+                    measure(&mut self.probe, &mut self.render, record, self.empty_len);
+                if len + record_len > MAX_XFR_MESSAGE_LEN && len > self.empty_len {
+                    record.rdatas.extend(rdatas);
+                    self.pending = Some(record);
+                    if !fitted.rdatas.is_empty() {
+                        MessageBuilder::new(&mut response).add_answer(fitted).done();
+                    }
+                    return Some(response);
+                }
+                len += record_len;
+                fitted.rdatas.append(&mut record.rdatas);
+            }
+            if !fitted.rdatas.is_empty() {
+                MessageBuilder::new(&mut response).add_answer(fitted).done();
+            }
+        }
+    }
+}
+
+//length of the record in the response, which is the upper bound since
+//the name could only be compressed with the question
+fn measure(
+    probe: &mut Message,
+    render: &mut MessageRender,
+    record: RRset,
+    empty_len: usize,
+) -> (usize, RRset) {
+    MessageBuilder::new(probe).add_answer(record).done();
+    render.clear();
+    probe.rend(render);
+    let record = probe
+        .take_section(SectionType::Answer)
+        .and_then(|mut answers| answers.pop())
+        .expect("record isn't added to answer");
+    (render.data().len() - empty_len, record)
+}
+
+pub fn make_error_response(query: &Message, rcode: Rcode) -> Message {
+    let mut response = query.clone();
+    MessageBuilder::new(&mut response)
+        .make_response()
+        .rcode(rcode)
+        .done();
+    response
+}
+
+fn new_response(query: &Message) -> Message {
+    let mut response = query.clone();
+    MessageBuilder::new(&mut response)
+        .make_response()
+        .set_flag(HeaderFlag::AuthAnswer)
+        .done();
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::journal::get_serial;
+    use datasrc::ZoneUpdater;
+    use futures::{Future, Stream};
+    use r53::Name;
+    use std::str::FromStr;
+
+    fn collect(response: XfrResponse) -> Vec<Message> {
+        response.collect().wait().unwrap()
+    }
+
+    #[test]
+    fn test_make_axfr_response() {
+        let name = Name::new("example.org").unwrap();
+        let query = Message::with_query(name.clone(), RRType::AXFR);
+        let mut rrsets = vec![
+            RRset::from_str("example.org. 300 IN NS ns.example.org.").unwrap(),
+            RRset::from_str("example.org. 300 IN SOA ns.example.org. root.example.org. 1 2 3 4 5")
+                .unwrap(),
+        ];
+        let txt = |i: usize| {
+            RRset::from_str(&format!(
+                "www.example.org. 300 IN TXT {}{}",
+                i,
+                "x".repeat(250)
+            ))
+            .unwrap()
+        };
+        let mut texts = txt(0);
+        for i in 1..300 {
+            texts.rdatas.append(&mut txt(i).rdatas);
+        }
+        rrsets.push(texts);
+
+        let mut zone = MemoryZone::new(name.clone());
+        for rrset in rrsets {
+            zone.add_rrset(rrset).unwrap();
+        }
+        let mut responses = collect(make_axfr_response(&query, Arc::new(zone)));
+        assert_eq!(responses.len(), 2);
+        let mut render = MessageRender::new();
+        for response in &responses {
+            render.clear();
+            response.rend(&mut render);
+            assert!(render.data().len() <= MAX_XFR_MESSAGE_LEN);
+        }
+        let first = responses[0].take_section(SectionType::Answer).unwrap();
+        let last = responses[1].take_section(SectionType::Answer).unwrap();
+        assert_eq!(first[0].typ, RRType::SOA);
+        assert_eq!(first[1].typ, RRType::NS);
+        assert_eq!(first[2].rdatas.len() + last[0].rdatas.len(), 300);
+        assert_eq!(last[1].typ, RRType::SOA);
+        assert!(responses[0].header.is_flag_set(HeaderFlag::AuthAnswer));
+
+        let responses = collect(make_axfr_response(&query, Arc::new(MemoryZone::new(name))));
+        assert_eq!(responses[0].header.rcode, Rcode::ServFail);
+    }
+
+    #[test]
+    fn test_make_ixfr_response() {
+        let query = Message::with_query(Name::new("example.org").unwrap(), RRType::IXFR);
+        let soa = |serial: u32| {
+            RRset::from_str(&format!(
+                "example.org. 300 IN SOA ns.example.org. root.example.org. {} 2 3 4 5",
+                serial
+            ))
+            .unwrap()
+        };
+        let a = |ip: &str| RRset::from_str(&format!("www.example.org. 300 IN A {}", ip)).unwrap();
+        let diffs = vec![
+            Diff {
+                old_soa: soa(1),
+                deleted: vec![a("192.0.2.1")],
+                new_soa: soa(2),
+                added: vec![a("192.0.2.2")],
+            },
+            Diff {
+                old_soa: soa(2),
+                deleted: Vec::new(),
+                new_soa: soa(3),
+                added: vec![a("192.0.2.3")],
+            },
+        ];
+
+        let mut responses = collect(make_ixfr_response(&query, soa(3), diffs));
+        assert_eq!(responses.len(), 1);
+        let answers = responses[0].take_section(SectionType::Answer).unwrap();
+        let serials = answers
+            .iter()
+            .filter(|rrset| rrset.typ == RRType::SOA)
+            .map(get_serial)
+            .collect::<Vec<u32>>();
+        assert_eq!(serials, vec![3, 1, 2, 2, 3, 3]);
+        assert_eq!(answers.len(), 9);
+    }
+}
@@ -1,43 +1,510 @@
-use super::error::AuthError;
+use super::{
+    dnssec::DnssecKey,
+    error::AuthError,
+    journal::{get_serial, get_zone_soa, now, serial_lt, Diff, Journal, JournalUpdater},
+    key_manager::KeyManager,
+    notify::Notifier,
+    signer::{PreparedDelegations, ZoneSigner},
+    xfr::{make_axfr_response, make_error_xfr_response, make_ixfr_response, make_xfr_response},
+};
+use crate::{
+    config::{AnyQueryMode, SerialPolicy},
+    server::XfrResponse,
+};
 use datasrc::{
     load_zone, zone::FindResult, zone::ZoneFinder, FindOption, FindResultFlag, FindResultType,
-    MemoryZone, RBTree,
+    MemoryZone, MemoryZoneFindResult, RBTree,
 };
 use failure::Result;
-use r53::{HeaderFlag, Message, MessageBuilder, Name, RRType, Rcode};
+use r53::{
+    message::SectionType, HeaderFlag, Message, MessageBuilder, Name, RData, RRClass, RRType, RRset,
+    Rcode,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs, iter, mem,
+    path::PathBuf,
+    sync::Arc,
+};
 
 pub struct AuthZone {
-    zones: RBTree<MemoryZone>,
+    //zones are shared with the running zone transfers, and are copied on
+    //write while they are shared
+    zones: RBTree<Arc<MemoryZone>>,
+    journals: HashMap<Name, Journal>,
+    journal_dir: Option<PathBuf>,
+    max_journal_records: usize,
+    notifier: Notifier,
+    serial_policies: HashMap<Name, SerialPolicy>,
+    //zones added through grpc are persisted into the zone dir
+    zone_dir: Option<PathBuf>,
+    zone_files: HashMap<Name, PathBuf>,
+    //zones created at runtime, whose files are owned by the server
+    managed_zones: HashSet<Name>,
+    //zones changed since last flush
+    dirty_zones: HashSet<Name>,
+    any_query_mode: AnyQueryMode,
+    //zones signed online
+    signers: HashMap<Name, ZoneSigner>,
+    signature_validity: u64,
+    signature_refresh: u64,
+    //zones whose keys are rolled over automatically
+    key_managers: HashMap<Name, KeyManager>,
 }
 
 impl AuthZone {
-    pub fn new() -> Self {
+    pub fn new(journal_dir: &str, max_journal_records: usize, notifier: Notifier) -> Self {
         AuthZone {
             zones: RBTree::new(),
+            journals: HashMap::new(),
+            journal_dir: if journal_dir.is_empty() {
+                None
+            } else {
+                Some(PathBuf::from(journal_dir))
+            },
+            max_journal_records,
+            notifier,
+            serial_policies: HashMap::new(),
+            zone_dir: None,
+            zone_files: HashMap::new(),
+            managed_zones: HashSet::new(),
+            dirty_zones: HashSet::new(),
+            any_query_mode: AnyQueryMode::default(),
+            signers: HashMap::new(),
+            signature_validity: 30 * 86400,
+            signature_refresh: 7 * 86400,
+            key_managers: HashMap::new(),
+        }
+    }
+
+    pub fn set_signature_lifetime(&mut self, validity: u64, refresh: u64) {
+        self.signature_validity = validity;
+        self.signature_refresh = refresh;
+        for signer in self.signers.values_mut() {
+            signer.set_lifetime(validity, refresh);
+        }
+    }
+
+    //zone is signed as a whole, the signatures are recorded into journal
+    //like other changes
+    pub fn set_zone_signer(&mut self, name: &Name, keys: Vec<DnssecKey>) -> Result<()> {
+        let signer = ZoneSigner::new(
+            name.clone(),
+            keys,
+            self.signature_validity,
+            self.signature_refresh,
+        );
+        self.sign_zone(name, signer)
+    }
+
+    //keys of the zone are generated by the manager, and the zone is signed
+    //again whenever they change
+    pub fn set_key_manager(&mut self, name: &Name, mut manager: KeyManager) -> Result<()> {
+        let now = now();
+        manager.rollover(now)?;
+        let signer = self.make_managed_signer(name, &manager, now)?;
+        self.sign_zone(name, signer)?;
+        manager.mark_applied(now);
+        self.key_managers.insert(name.clone(), manager);
+        Ok(())
+    }
+
+    //managers are taken out while keys are rolled without lock
+    pub fn take_key_managers(&mut self) -> HashMap<Name, KeyManager> {
+        mem::replace(&mut self.key_managers, HashMap::new())
+    }
+
+    //manager of the zone which is deleted or set again in the meantime is
+    //dropped
+    pub fn restore_key_manager(&mut self, name: Name, manager: KeyManager) {
+        if self.has_zone(&name) && !self.key_managers.contains_key(&name) {
+            self.key_managers.insert(name, manager);
+        }
+    }
+
+    //signer with the managed keys, signatures of the zone are prepared
+    pub fn make_managed_signer(
+        &self,
+        name: &Name,
+        manager: &KeyManager,
+        now: u64,
+    ) -> Result<ZoneSigner> {
+        let zone = self
+            .get_zone(name)
+            .filter(|zone| zone.get_origin().eq(name))
+            .ok_or_else(|| AuthError::UnknownZone(name.to_string()))?;
+        let (keys, standby_keys) = manager.zone_keys(now)?;
+        let mut signer = ZoneSigner::new(
+            name.clone(),
+            keys,
+            self.signature_validity,
+            self.signature_refresh,
+        );
+        signer.set_managed_keys(standby_keys);
+        signer.prepare(zone)?;
+        Ok(signer)
+    }
+
+    pub fn sign_zone(&mut self, name: &Name, mut signer: ZoneSigner) -> Result<()> {
+        let find_result = self.zones.find(name);
+        let zone = if find_result.flag == FindResultFlag::ExacatMatch {
+            find_result.get_value_mut().map(Arc::make_mut)
+        } else {
+            None
+        };
+        let zone = zone.ok_or_else(|| AuthError::UnknownZone(name.to_string()))?;
+
+        let policy = self.serial_policies.get(name).cloned().unwrap_or_default();
+        let mut updater = JournalUpdater::new(zone);
+        let result = signer
+            .sign_zone(&mut updater)
+            .and_then(|_| updater.bump_serial(policy))
+            .and_then(|_| signer.resign(&mut updater, vec![(name.clone(), RRType::SOA)]));
+        if let Err(e) = result {
+            updater.rollback()?;
+            return Err(e);
+        }
+        let diff = updater.commit(policy);
+        self.record_diff(name, diff);
+        self.signers.insert(name.clone(), signer);
+        Ok(())
+    }
+
+    //signatures expiring soon are regenerated, which changes the serial
+    //like normal updates
+    pub fn refresh_signatures(&mut self) {
+        let now = now();
+        let expiring = self
+            .signers
+            .iter()
+            .map(|(name, signer)| (name.clone(), signer.expiring(now)))
+            .filter(|(_, expiring)| !expiring.is_empty())
+            .collect::<Vec<_>>();
+        for (name, expiring) in expiring {
+            if let Err(e) = self.change_zone(&name, |_| Ok(()), expiring) {
+                eprintln!("refresh signatures of zone {} failed: {}", name, e);
+            }
+        }
+    }
+
+    //signed zones whose delegations are changed by updates
+    pub fn zones_with_changed_cuts(&self) -> Vec<Name> {
+        self.signers
+            .iter()
+            .filter(|(_, signer)| signer.has_changed_cuts())
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    pub fn prepare_delegations(&self, name: &Name) -> Result<PreparedDelegations> {
+        let zone = self
+            .get_zone(name)
+            .filter(|zone| zone.get_origin().eq(name))
+            .ok_or_else(|| AuthError::UnknownZone(name.to_string()))?;
+        let signer = self
+            .signers
+            .get(name)
+            .ok_or_else(|| AuthError::UnknownZone(name.to_string()))?;
+        signer.prepare_delegations(zone)
+    }
+
+    //rrsets at and below the changed cuts are signed with the prepared
+    //signatures, and the nsec chain is updated around them
+    pub fn resign_delegations(&mut self, name: &Name, prepared: PreparedDelegations) -> Result<()> {
+        let changes = match self.signers.get_mut(name) {
+            Some(signer) => signer.take_delegations(prepared),
+            None => return Err(AuthError::UnknownZone(name.to_string()).into()),
+        };
+        self.change_zone(name, |_| Ok(()), changes)
+    }
+
+    pub fn set_any_query_mode(&mut self, mode: AnyQueryMode) {
+        self.any_query_mode = mode;
+    }
+
+    pub fn set_zone_dir(&mut self, zone_dir: &str) {
+        self.zone_dir = if zone_dir.is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(zone_dir))
+        };
+    }
+
+    //file of the zone in zone dir, None if zone dir isn't set
+    pub fn managed_zone_file(&self, name: &Name) -> Option<PathBuf> {
+        let name = name.to_string();
+        self.zone_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.zone", name.trim_end_matches('.'))))
+    }
+
+    //changed zone is written back to the file when it's flushed
+    pub fn set_zone_file(&mut self, name: &Name, path: PathBuf) {
+        self.zone_files.insert(name.clone(), path);
+    }
+
+    pub fn set_managed_zone(&mut self, name: &Name) {
+        self.managed_zones.insert(name.clone());
+    }
+
+    pub fn mark_dirty(&mut self, name: &Name) {
+        if self.zone_files.contains_key(name) {
+            self.dirty_zones.insert(name.clone());
         }
     }
 
+    pub fn take_dirty_zones(&mut self) -> Vec<(Name, PathBuf)> {
+        let zone_files = &self.zone_files;
+        self.dirty_zones
+            .drain()
+            .filter_map(|name| zone_files.get(&name).map(|path| (name, path.clone())))
+            .collect()
+    }
+
+    //zone without policy uses the default one
+    pub fn set_serial_policies(&mut self, policies: HashMap<Name, SerialPolicy>) {
+        self.serial_policies = policies;
+    }
+
     pub fn add_zone(&mut self, name: Name, zone_content: &str) -> Result<()> {
-        if self.get_exact_zone(&name).is_some() {
+        if self.has_zone(&name) {
             return Err(AuthError::DuplicateZone(name.to_string()).into());
         }
 
         let zone = load_zone(name.clone(), zone_content)?;
-        self.zones.insert(name, Some(zone));
+        self.insert_zone(zone)?;
+        if let Some(path) = self.managed_zone_file(&name) {
+            self.set_zone_file(&name, path);
+            self.set_managed_zone(&name);
+            self.mark_dirty(&name);
+        }
+        Ok(())
+    }
+
+    pub fn insert_zone(&mut self, mut zone: MemoryZone) -> Result<()> {
+        let name = zone.get_origin().clone();
+        if self.has_zone(&name) {
+            return Err(AuthError::DuplicateZone(name.to_string()).into());
+        }
+
+        let journal = Journal::open(
+            self.journal_path(&name),
+            self.max_journal_records,
+            &mut zone,
+        );
+        self.journals.insert(name.clone(), journal);
+        self.zones.insert(name, Some(Arc::new(zone)));
+        Ok(())
+    }
+
+    //zone transferred from primary is replaced as a whole, the journal
+    //is discarded since the serial isn't continuous
+    pub fn replace_zone(&mut self, zone: MemoryZone) -> Result<()> {
+        let name = zone.get_origin().clone();
+        if self.has_zone(&name) {
+            self.delete_zone(&name)?;
+        }
+        self.insert_zone(zone)?;
+        self.notifier.notify(&name);
         Ok(())
     }
 
+    //incremental changes transferred from primary are recorded into
+    //journal too, so they could be transferred to other secondaries, diff
+    //failed to apply is rolled back like update
+    pub fn apply_diffs(&mut self, name: &Name, diffs: Vec<Diff>) -> Result<()> {
+        let find_result = self.zones.find(name);
+        let zone = if find_result.flag == FindResultFlag::ExacatMatch {
+            find_result.get_value_mut().map(Arc::make_mut)
+        } else {
+            None
+        };
+        let zone = zone.ok_or_else(|| AuthError::UnknownZone(name.to_string()))?;
+
+        for diff in diffs {
+            let serial = get_zone_soa(zone).map(|soa| get_serial(&soa));
+            if serial != Some(diff.from_serial()) {
+                return Err(AuthError::InvalidDiff(name.to_string(), diff.from_serial()).into());
+            }
+            let mut updater = JournalUpdater::new(zone);
+            if let Err(e) = diff.apply(&mut updater) {
+                updater.rollback()?;
+                return Err(e);
+            }
+            if let Some(journal) = self.journals.get_mut(name) {
+                if let Err(e) = journal.append(diff) {
+                    eprintln!("{}", e);
+                }
+            }
+            self.mark_dirty(name);
+            self.notifier.notify(name);
+        }
+        Ok(())
+    }
+
+    pub fn get_soa(&self, name: &Name) -> Option<RRset> {
+        let result = self.zones.find(name);
+        if result.flag == FindResultFlag::ExacatMatch {
+            result
+                .get_value()
+                .and_then(|zone| get_zone_soa(zone.as_ref()))
+        } else {
+            None
+        }
+    }
+
+    fn journal_path(&self, name: &Name) -> Option<PathBuf> {
+        let name = name.to_string();
+        self.journal_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.jnl", name.trim_end_matches('.'))))
+    }
+
+    //changes made through the updater are recorded into the journal of
+    //the zone, including the ones before the failed update
+    pub fn update_zone<F, T>(&mut self, name: &Name, update: F) -> Result<T>
+    where
+        F: FnOnce(&mut JournalUpdater) -> Result<T>,
+    {
+        self.change_zone(name, update, Vec::new())
+    }
+
+    //changed rrsets of signed zone and the rrsets need resign are signed
+    //before the changes are committed, all the changes are rolled back if
+    //the zone can't be signed
+    fn change_zone<F, T>(
+        &mut self,
+        name: &Name,
+        update: F,
+        resign: Vec<(Name, RRType)>,
+    ) -> Result<T>
+    where
+        F: FnOnce(&mut JournalUpdater) -> Result<T>,
+    {
+        let find_result = self.zones.find(name);
+        let zone = if find_result.flag == FindResultFlag::ExacatMatch {
+            find_result.get_value_mut().map(Arc::make_mut)
+        } else {
+            None
+        };
+        let zone = zone.ok_or_else(|| AuthError::UnknownZone(name.to_string()))?;
+
+        let policy = self.serial_policies.get(name).cloned().unwrap_or_default();
+        let mut updater = JournalUpdater::new(zone);
+        let result = update(&mut updater);
+        if let Some(signer) = self.signers.get_mut(name) {
+            let mut changes = updater.changed_rrsets();
+            signer.add_changed_cuts(&changes);
+            changes.extend(resign);
+            let signed = signer
+                .resign(&mut updater, changes)
+                .and_then(|_| updater.bump_serial(policy))
+                .and_then(|changed| {
+                    if changed {
+                        signer.resign(&mut updater, vec![(name.clone(), RRType::SOA)])
+                    } else {
+                        Ok(())
+                    }
+                });
+            if let Err(e) = signed {
+                updater.rollback()?;
+                return Err(e);
+            }
+        }
+        let diff = updater.commit(policy);
+        self.record_diff(name, diff);
+        result
+    }
+
+    fn record_diff(&mut self, name: &Name, diff: Option<Diff>) {
+        if let Some(diff) = diff {
+            if let Some(journal) = self.journals.get_mut(name) {
+                if let Err(e) = journal.append(diff) {
+                    eprintln!("{}", e);
+                }
+            }
+            self.mark_dirty(name);
+            self.notifier.notify(name);
+        }
+    }
+
+    pub fn has_zone(&self, name: &Name) -> bool {
+        let result = self.zones.find(name);
+        result.flag == FindResultFlag::ExacatMatch && result.get_value().is_some()
+    }
+
+    //zone is deleted with its journal, and its file if it's created at
+    //runtime, the files of zone from config are kept
     pub fn delete_zone(&mut self, name: &Name) -> Result<()> {
+        let zone_file = if self.managed_zones.contains(name) {
+            self.zone_files.get(name).cloned()
+        } else {
+            None
+        };
+        if let Some(journal) = self.remove_zone(name)? {
+            journal.remove_file();
+        }
+        if let Some(path) = zone_file {
+            let _ = fs::remove_file(path);
+        }
+        Ok(())
+    }
+
+    //zone is only dropped from memory, its files are kept on disk
+    pub fn remove_zone(&mut self, name: &Name) -> Result<Option<Journal>> {
         let result = self.zones.find(name);
         if result.flag != FindResultFlag::ExacatMatch {
             return Err(AuthError::UnknownZone(name.to_string()).into());
         }
         let target = result.node;
         self.zones.remove_node(target);
-        Ok(())
+        self.signers.remove(name);
+        self.key_managers.remove(name);
+        self.dirty_zones.remove(name);
+        self.zone_files.remove(name);
+        self.managed_zones.remove(name);
+        Ok(self.journals.remove(name))
+    }
+
+    //fall back to full transfer if the changes since the serial in
+    //query are discarded from the journal, only the snapshot of the zone
+    //or the diffs are taken with the lock, messages are built later
+    pub fn handle_xfr(&self, query: &Message) -> XfrResponse {
+        let question = query.question.as_ref().unwrap();
+        let result = self.zones.find(&question.name);
+        let zone = match result.get_value() {
+            Some(zone) if result.flag == FindResultFlag::ExacatMatch => zone,
+            _ => return make_error_xfr_response(query, Rcode::NotAuth),
+        };
+
+        if question.typ == RRType::IXFR {
+            let serial = query
+                .clone()
+                .take_section(SectionType::Authority)
+                .and_then(|auth| auth.into_iter().find(|rrset| rrset.typ == RRType::SOA))
+                .map(|soa| get_serial(&soa));
+            let serial = match serial {
+                Some(serial) => serial,
+                None => return make_error_xfr_response(query, Rcode::FormErr),
+            };
+            if let Some(soa) = get_zone_soa(zone) {
+                if !serial_lt(serial, get_serial(&soa)) {
+                    return make_xfr_response(query, iter::once(soa));
+                }
+                let diffs = self
+                    .journals
+                    .get(zone.get_origin())
+                    .and_then(|journal| journal.get_diffs(serial));
+                if let Some(diffs) = diffs {
+                    return make_ixfr_response(query, soa, diffs.to_vec());
+                }
+            }
+        }
+        make_axfr_response(query, Arc::clone(zone))
     }
 
-    pub fn handle_query(&self, req: &mut Message) -> bool {
+    pub fn handle_query(&self, req: &mut Message, over_udp: bool) -> bool {
+        let dnssec_ok = req.edns.as_ref().map_or(false, |edns| edns.dnssec_aware);
         let question = req.question.as_ref().unwrap();
         let zone = self.get_zone(&question.name);
         if zone.is_none() {
@@ -50,20 +517,56 @@ impl AuthZone {
         let mut result = zone.find(&question.name, question.typ, FindOption::FollowZoneCut);
 
         let query_type = question.typ;
+        let denial_proof = if dnssec_ok {
+            result.get_denial_proof(&question.name)
+        } else {
+            Vec::new()
+        };
         let mut builder = MessageBuilder::new(req);
         builder.make_response().set_flag(HeaderFlag::AuthAnswer);
         match result.typ {
             FindResultType::CName => {
-                builder.add_answer(result.rrset.take().unwrap());
+                let cname = result.rrset.take().unwrap();
+                for rrset in with_signatures(&result, cname, dnssec_ok) {
+                    builder.add_answer(rrset);
+                }
+            }
+            FindResultType::Success if query_type == RRType::ANY => {
+                match self.make_any_answer(&mut result, over_udp, dnssec_ok) {
+                    Ok(answer) => {
+                        for rrset in answer {
+                            builder.add_answer(rrset);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("make answer of any query failed: {}", e);
+                        builder.rcode(Rcode::ServFail);
+                    }
+                }
+            }
+            //signatures of each covered type are a separate rrsig rrset
+            FindResultType::Success if query_type == RRType::RRSIG => {
+                for rrset in result
+                    .get_all_rrsets()
+                    .into_iter()
+                    .filter(|rrset| rrset.typ == RRType::RRSIG)
+                {
+                    builder.add_answer(rrset);
+                }
             }
             FindResultType::Success => {
                 for rrset in result.get_additional() {
                     builder.add_additional(rrset);
                 }
-                builder.add_answer(result.rrset.take().unwrap());
+                let answer = result.rrset.take().unwrap();
+                for rrset in with_signatures(&result, answer, dnssec_ok) {
+                    builder.add_answer(rrset);
+                }
                 if query_type != RRType::NS {
                     let (auth, additional) = result.get_apex_ns_and_glue();
-                    builder.add_auth(auth);
+                    for rrset in with_signatures(&result, auth, dnssec_ok) {
+                        builder.add_auth(rrset);
+                    }
                     for rrset in additional {
                         builder.add_additional(rrset);
                     }
@@ -73,36 +576,90 @@ impl AuthZone {
                 for rrset in result.get_additional() {
                     builder.add_additional(rrset);
                 }
+                //ds proves the child zone is signed
+                let ds = result.get_delegation_ds();
                 builder
                     .clear_flag(HeaderFlag::AuthAnswer)
                     .add_auth(result.rrset.take().unwrap());
+                if let Some(ds) = ds.filter(|_| dnssec_ok) {
+                    for rrset in with_signatures(&result, ds, dnssec_ok) {
+                        builder.add_auth(rrset);
+                    }
+                }
             }
             FindResultType::NXDomain => {
-                builder
-                    .rcode(Rcode::NXDomain)
-                    .add_auth(result.get_apex_soa());
+                builder.rcode(Rcode::NXDomain);
+                for rrset in with_signatures(&result, result.get_apex_soa(), dnssec_ok) {
+                    builder.add_auth(rrset);
+                }
             }
+            //nodata is answered with noerror, rcode nxrrset is only for update
             FindResultType::NXRRset => {
-                builder
-                    .rcode(Rcode::NXRRset)
-                    .add_auth(result.get_apex_soa());
+                for rrset in with_signatures(&result, result.get_apex_soa(), dnssec_ok) {
+                    builder.add_auth(rrset);
+                }
+            }
+        }
+        for nsec in denial_proof {
+            for rrset in with_signatures(&result, nsec, dnssec_ok) {
+                builder.add_auth(rrset);
             }
         }
         builder.done();
         true
     }
 
+    //rfc8482, only one rrset or the synthesized hinfo is returned over udp
+    //to avoid amplification
+    fn make_any_answer(
+        &self,
+        result: &mut MemoryZoneFindResult,
+        over_udp: bool,
+        dnssec_ok: bool,
+    ) -> Result<Vec<RRset>> {
+        if !over_udp || self.any_query_mode == AnyQueryMode::Full {
+            return Ok(result
+                .get_all_rrsets()
+                .into_iter()
+                .filter(|rrset| dnssec_ok || rrset.typ != RRType::RRSIG)
+                .collect());
+        }
+        let rrset = result.rrset.take().unwrap();
+        if self.any_query_mode == AnyQueryMode::Hinfo {
+            return Ok(vec![RRset {
+                name: rrset.name,
+                typ: RRType::HINFO,
+                class: RRClass::IN,
+                ttl: rrset.ttl,
+                rdatas: vec![RData::from_str(RRType::HINFO, "\"RFC8482\" \"\"")?],
+            }]);
+        }
+        Ok(with_signatures(result, rrset, dnssec_ok))
+    }
+
     pub fn get_zone<'a>(&'a self, name: &Name) -> Option<&'a MemoryZone> {
         let result = self.zones.find(&name);
-        result.get_value()
+        result.get_value().map(|zone| zone.as_ref())
     }
 
     pub fn get_exact_zone<'a>(&'a mut self, name: &Name) -> Option<&'a mut MemoryZone> {
         let result = self.zones.find(&name);
         if result.flag == FindResultFlag::ExacatMatch {
-            result.get_value_mut()
+            result.get_value_mut().map(Arc::make_mut)
         } else {
             None
         }
     }
 }
+
+//signatures are only returned when the client sets the do bit, rfc4035
+fn with_signatures(result: &MemoryZoneFindResult, rrset: RRset, dnssec_ok: bool) -> Vec<RRset> {
+    let signatures = if dnssec_ok {
+        result.get_signatures(&rrset)
+    } else {
+        None
+    };
+    let mut rrsets = vec![rrset];
+    rrsets.extend(signatures);
+    rrsets
+}
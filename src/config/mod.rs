@@ -10,6 +10,8 @@ pub struct VanguardConfig {
     pub forwarder: ForwarderConfig,
     pub vg_ctrl: VgCtrlConfig,
     pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub tsig_keys: Vec<TsigKeyConfig>,
 }
 
 impl VanguardConfig {
@@ -26,15 +28,102 @@ impl VanguardConfig {
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(default)]
 pub struct ServerConfig {
-    pub address: String,
-    pub enable_tcp: bool,
+    pub listeners: Vec<ListenerConfig>,
+    //max udp payload size advertised in edns
+    pub max_udp_payload: u16,
+    //max concurrent handled queries per tcp connection
+    pub max_tcp_pipelined_queries: usize,
+    //certificate used by dns over tls and dns over https
+    pub tls_cert_file: String,
+    pub tls_key_file: String,
+    pub doh_path: String,
+    pub query_log: QueryLogConfig,
 }
 
 impl Default for ServerConfig {
     fn default() -> Self {
         ServerConfig {
+            listeners: vec![ListenerConfig::default()],
+            max_udp_payload: 1232,
+            max_tcp_pipelined_queries: 16,
+            tls_cert_file: String::new(),
+            tls_key_file: String::new(),
+            doh_path: "/dns-query".to_string(),
+            query_log: QueryLogConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryLogOutput {
+    File,
+    //unix domain socket, like the one opened by dnstap collector
+    Unix,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct QueryLogConfig {
+    pub enable: bool,
+    pub output: QueryLogOutput,
+    pub path: String,
+    //identity field of dnstap frame, hostname is a common choice
+    pub identity: String,
+    //log one out of every sample_rate queries
+    pub sample_rate: u32,
+    //logs exceed the buffer are dropped instead of blocking the query
+    pub buffer_size: usize,
+}
+
+impl Default for QueryLogConfig {
+    fn default() -> Self {
+        QueryLogConfig {
+            enable: false,
+            output: QueryLogOutput::File,
+            path: "/var/log/vanguard2/query.dnstap".to_string(),
+            identity: String::new(),
+            sample_rate: 1,
+            buffer_size: 10000,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerRole {
+    //only answer queries for authoritative zones
+    Auth,
+    //answer authoritative zones and do recursion for the others
+    Recursor,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ListenerConfig {
+    //ip and port, ipv6 address should be in brackets like [::1]:53
+    pub address: String,
+    pub enable_udp: bool,
+    pub enable_tcp: bool,
+    //dns over tls and dns over https listen on the same ip with address
+    pub enable_tls: bool,
+    pub tls_port: u16,
+    pub enable_doh: bool,
+    pub doh_port: u16,
+    pub role: ListenerRole,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        ListenerConfig {
             address: "0.0.0.0:53".to_string(),
+            enable_udp: true,
             enable_tcp: false,
+            enable_tls: false,
+            tls_port: 853,
+            enable_doh: false,
+            doh_port: 443,
+            role: ListenerRole::Recursor,
         }
     }
 }
@@ -43,18 +132,181 @@ impl Default for ServerConfig {
 #[serde(default)]
 pub struct AuthorityConfig {
     pub zones: Vec<AuthZoneConfig>,
+    //skip the zone which failed to load instead of abort startup
+    pub skip_invalid_zone: bool,
+    //ip of the secondary servers allowed to transfer zones
+    pub allow_transfer: Vec<String>,
+    //ip of the clients allowed to send dns update
+    pub allow_update: Vec<String>,
+    //directory to persist the journal of dynamic updates, journal is
+    //only kept in memory if it's empty
+    pub journal_dir: String,
+    //records kept in the journal of each zone, older changes are discarded
+    pub max_journal_records: usize,
+    //directory to persist the zones added through grpc, which are loaded
+    //on startup, they are only kept in memory if it's empty
+    pub zone_dir: String,
+    //seconds between writing the changed zones back to their files
+    pub flush_interval: u64,
+    //response to any query over udp, it's always full over other transports
+    pub any_query_mode: AnyQueryMode,
+    //seconds the signatures of signed zones are valid
+    pub signature_validity: u64,
+    //signatures are refreshed if they expire within the seconds
+    pub signature_refresh: u64,
+    //directory to keep the keys generated for zones with dnssec policy
+    pub key_dir: String,
 }
 
 impl Default for AuthorityConfig {
     fn default() -> Self {
-        AuthorityConfig { zones: Vec::new() }
+        AuthorityConfig {
+            zones: Vec::new(),
+            skip_invalid_zone: false,
+            allow_transfer: Vec::new(),
+            allow_update: Vec::new(),
+            journal_dir: String::new(),
+            max_journal_records: 100000,
+            zone_dir: String::new(),
+            flush_interval: 60,
+            any_query_mode: AnyQueryMode::Hinfo,
+            signature_validity: 30 * 86400,
+            signature_refresh: 7 * 86400,
+            key_dir: String::new(),
+        }
+    }
+}
+
+//minimal response to any query defined in rfc8482
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AnyQueryMode {
+    //synthesized hinfo record
+    Hinfo,
+    //one of the rrsets owned by the name
+    Single,
+    //all the rrsets owned by the name
+    Full,
+}
+
+impl Default for AnyQueryMode {
+    fn default() -> Self {
+        AnyQueryMode::Hinfo
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ZoneType {
+    //zone loaded from file
+    Primary,
+    //zone transferred from primary servers
+    Secondary,
+}
+
+impl Default for ZoneType {
+    fn default() -> Self {
+        ZoneType::Primary
+    }
+}
+
+//how the soa serial is changed when zone is updated
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SerialPolicy {
+    Increment,
+    //seconds since unix epoch
+    Unixtime,
+    //YYYYMMDDnn
+    Date,
+}
+
+impl Default for SerialPolicy {
+    fn default() -> Self {
+        SerialPolicy::Increment
     }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AuthZoneConfig {
     pub name: String,
+    #[serde(default)]
+    pub zone_type: ZoneType,
+    #[serde(default)]
     pub file_path: String,
+    //ip or ip:port of the primary servers, only used by secondary zone
+    #[serde(default)]
+    pub primaries: Vec<String>,
+    //ip or ip:port of the secondary servers notified when zone changes
+    #[serde(default)]
+    pub notify: Vec<String>,
+    //keys which could sign the transfer and update requests of the zone
+    #[serde(default)]
+    pub allow_transfer_keys: Vec<String>,
+    #[serde(default)]
+    pub allow_update_keys: Vec<String>,
+    //key to sign the transfer requests to primaries and the notifies to
+    //secondaries, notifies from primaries have to be signed by it too
+    #[serde(default)]
+    pub tsig_key: String,
+    #[serde(default)]
+    pub serial_policy: SerialPolicy,
+    //zone is signed online if it has keys, only used by primary zone
+    #[serde(default)]
+    pub dnssec_keys: Vec<DnssecKeyConfig>,
+    //keys are generated and rolled over automatically, exclusive with
+    //dnssec keys
+    #[serde(default)]
+    pub dnssec_policy: Option<DnssecPolicyConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct DnssecKeyConfig {
+    //private key in pkcs8 format, pem or der encoded
+    pub file: String,
+    //ecdsap256sha256 or ed25519
+    pub algorithm: String,
+    //key signing key only signs the dnskey rrset
+    #[serde(default)]
+    pub ksk: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct DnssecPolicyConfig {
+    //ecdsap256sha256 or ed25519
+    pub algorithm: String,
+    //seconds each key signs the zone before it's replaced
+    pub zsk_lifetime: u64,
+    pub ksk_lifetime: u64,
+    //seconds for the dnskey change to reach the caches, new zsk is
+    //published for it before signing and new ksk signs along with the old
+    //one for it
+    pub propagation: u64,
+    //seconds for the parent to replace the ds with the one of the new ksk
+    //and the old ds to expire in caches, old ksk keeps signing until then
+    pub ds_propagation: u64,
+}
+
+impl Default for DnssecPolicyConfig {
+    fn default() -> Self {
+        DnssecPolicyConfig {
+            algorithm: "ecdsap256sha256".to_string(),
+            zsk_lifetime: 90 * 86400,
+            ksk_lifetime: 365 * 86400,
+            propagation: 2 * 86400,
+            ds_propagation: 7 * 86400,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TsigKeyConfig {
+    pub name: String,
+    //hmac-sha256 or hmac-sha512
+    pub algorithm: String,
+    //base64 encoded
+    pub secret: String,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -21,6 +21,24 @@ pub enum VgError {
 
     #[fail(display = "query get loop")]
     LoopedQuery,
+
+    #[fail(display = "invalid tls config: {}", _0)]
+    InvalidTlsConfig(String),
+
+    #[fail(display = "invalid listen address: {}", _0)]
+    InvalidListenAddress(String),
+
+    #[fail(display = "listen {} on {} failed: {}", _0, _1, _2)]
+    ListenFailed(String, String, String),
+
+    #[fail(display = "open query log {} failed: {}", _0, _1)]
+    QueryLogFailed(String, String),
+
+    #[fail(display = "tsig key {} is invalid: {}", _0, _1)]
+    InvalidTsigKey(String, String),
+
+    #[fail(display = "invalid tsig: {}", _0)]
+    InvalidTsig(String),
 }
 
 impl From<io::Error> for VgError {
@@ -3,14 +3,22 @@ extern crate tokio;
 mod resolver;
 
 use clap::{App, Arg};
+use futures::Future;
+use futures::Stream;
 use metrics::start_metric_server;
 use std::net::SocketAddr;
+use std::process;
+use std::sync::mpsc;
 use std::thread;
-use tokio::runtime::current_thread;
+use std::time::Duration;
+use tokio::runtime::{current_thread, Runtime};
+use tokio_signal::unix::{Signal, SIGHUP, SIGINT, SIGTERM};
 
 use vanguard2::auth::{AuthServer, DynamicUpdateHandler};
 use vanguard2::config::VanguardConfig;
-use vanguard2::server::{start_qps_calculate, Server};
+use vanguard2::server::{shutdown_channel, start_qps_calculate, KeyRing, Server, ShutdownListener};
+
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
 
 fn main() {
     let matches = App::new("auth")
@@ -31,13 +39,35 @@ fn main() {
             return;
         }
         Ok(config) => {
-            let auth_server = AuthServer::new(&config.auth);
+            let keyring = match KeyRing::new(&config.tsig_keys) {
+                Ok(keyring) => keyring,
+                Err(e) => {
+                    eprintln!("load tsig keys failed: {}", e);
+                    return;
+                }
+            };
+            let auth_server = match AuthServer::new(&config.auth, keyring.clone()) {
+                Ok(auth_server) => auth_server,
+                Err(e) => {
+                    eprintln!("load auth zones failed: {}", e);
+                    return;
+                }
+            };
             let dynamic_server = DynamicUpdateHandler::new(auth_server.zones());
+            let secondary_zones = auth_server.secondary_zones();
+            let notifier = auth_server.notifier();
+            let persister = auth_server.clone();
             let resolver = resolver::Resolver::new(auth_server, &config);
-            let server = Server::new(&config.server, resolver);
+            let server = match Server::new(&config.server, resolver.clone(), keyring) {
+                Ok(server) => server,
+                Err(e) => {
+                    eprintln!("create server failed: {}", e);
+                    return;
+                }
+            };
 
             let addr_and_port = config.vg_ctrl.address.split(":").collect::<Vec<&str>>();
-            let _handler = dynamic_server.run(
+            let grpc_handler = dynamic_server.run(
                 addr_and_port[0].to_string(),
                 addr_and_port[1].parse().unwrap(),
             );
@@ -45,11 +75,83 @@ fn main() {
             let addr = config.metrics.address.parse::<SocketAddr>().unwrap();
             start_metrics(addr);
 
-            tokio::run(server.into_future());
+            let (shutdown_trigger, shutdown_listener) = shutdown_channel();
+            let mut rt = Runtime::new().unwrap();
+            rt.spawn(server.into_future(shutdown_listener.clone()).map_err(|e| {
+                eprintln!("start server failed: {}", e);
+                process::exit(1);
+            }));
+            rt.spawn(secondary_zones.run(shutdown_listener.clone()));
+            rt.spawn(notifier.run(shutdown_listener.clone()));
+            rt.spawn(persister.run_flusher(shutdown_listener.clone()));
+            rt.spawn(persister.run_signer(shutdown_listener.clone()));
+            rt.spawn(reload_on_sighup(
+                config_file.to_string(),
+                config,
+                resolver,
+                shutdown_listener,
+            ));
+
+            let _ = rt.block_on(wait_for_shutdown_signal());
+            println!("start shutdown, wait for in-flight queries");
+            shutdown_trigger.shutdown();
+            drop(grpc_handler);
+            drain(rt);
+            //flusher may not finish before the drain deadline
+            persister.flush_zones();
         }
     }
 }
 
+fn wait_for_shutdown_signal() -> impl Future<Item = (), Error = ()> + Send {
+    let sigterm = Signal::new(SIGTERM).flatten_stream();
+    let sigint = Signal::new(SIGINT).flatten_stream();
+    sigterm
+        .select(sigint)
+        .into_future()
+        .map(|_| ())
+        .map_err(|(e, _)| eprintln!("wait for signal get error {:?}", e))
+}
+
+fn reload_on_sighup(
+    config_file: String,
+    mut config: VanguardConfig,
+    resolver: resolver::Resolver,
+    shutdown: ShutdownListener,
+) -> impl Future<Item = (), Error = ()> + Send {
+    Signal::new(SIGHUP)
+        .flatten_stream()
+        .for_each(move |_| {
+            match VanguardConfig::load_config(&config_file) {
+                Ok(new_config) => match resolver.reload(&config, &new_config) {
+                    Ok(_) => {
+                        println!("reload configure file succeed");
+                        config = new_config;
+                    }
+                    Err(e) => eprintln!("reload configure file failed: {}", e),
+                },
+                Err(e) => eprintln!("load configure file failed: {:?}", e),
+            }
+            Ok(())
+        })
+        .map_err(|e| eprintln!("wait for sighup get error {:?}", e))
+        .select(shutdown)
+        .map(|_| ())
+        .map_err(|_| ())
+}
+
+//wait all the in-flight queries to be handled, but no longer than the deadline
+fn drain(rt: Runtime) {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = rt.shutdown_on_idle().wait();
+        let _ = sender.send(());
+    });
+    if receiver.recv_timeout(SHUTDOWN_DRAIN_TIMEOUT).is_err() {
+        eprintln!("in-flight queries aren't finished before deadline, force exit");
+    }
+}
+
 fn start_metrics(addr: SocketAddr) {
     thread::Builder::new()
         .name("metrics".into())
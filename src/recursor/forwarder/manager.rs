@@ -17,7 +17,7 @@ use std::{
 
 #[derive(Clone)]
 pub struct ForwarderManager {
-    forwarders: Arc<RBTree<ForwarderGroup>>,
+    forwarders: Arc<RwLock<RBTree<ForwarderGroup>>>,
     pool: Arc<RwLock<ForwarderPool>>,
 }
 
@@ -27,11 +27,24 @@ impl ForwarderManager {
         let mut groups = RBTree::new();
         pool.init_groups(&mut groups, conf);
         ForwarderManager {
-            forwarders: Arc::new(groups),
+            forwarders: Arc::new(RwLock::new(groups)),
             pool: Arc::new(RwLock::new(pool)),
         }
     }
 
+    //group keeps the index of forwarder in pool, so both of them
+    //are replaced together
+    pub fn reload(&self, conf: &ForwarderConfig) {
+        let new_pool = ForwarderPool::new(conf);
+        let mut new_groups = RBTree::new();
+        new_pool.init_groups(&mut new_groups, conf);
+
+        let mut forwarders = self.forwarders.write().unwrap();
+        let mut pool = self.pool.write().unwrap();
+        *forwarders = new_groups;
+        *pool = new_pool;
+    }
+
     pub fn handle_query(
         &self,
         name: &Name,
@@ -49,7 +62,8 @@ impl ForwarderManager {
     }
 
     fn get_forwarder(&self, name: &Name) -> Option<Forwarder> {
-        let result = self.forwarders.find(name);
+        let forwarders = self.forwarders.read().unwrap();
+        let result = forwarders.find(name);
         if let Some(selecotr) = result.get_value() {
             let pool = self.pool.read().unwrap();
             return Some(selecotr.select_forwarder(&pool));
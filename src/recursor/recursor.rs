@@ -39,6 +39,11 @@ impl Recursor {
     pub fn handle_query(&self, query: Query) -> RecursorFuture {
         RecursorFuture::new(self.clone(), query)
     }
+
+    //only forwarder is reloaded, cache is kept
+    pub fn reload(&self, forwarder_cfg: &ForwarderConfig) {
+        self.forwarder.reload(forwarder_cfg);
+    }
 }
 
 impl Resolver for Recursor {
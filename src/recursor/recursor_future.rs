@@ -15,6 +15,7 @@ const DEFAULT_RECURSOR_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct RecursorFuture {
     client: SocketAddr,
+    allow_recursion: bool,
     inner: RunningQuery,
     delay: Delay,
 }
@@ -23,6 +24,7 @@ impl RecursorFuture {
     pub fn new(recursor: Recursor, query: Query) -> Self {
         RecursorFuture {
             client: query.client,
+            allow_recursion: query.allow_recursion,
             inner: RunningQuery::new(query.message, recursor, 0),
             delay: Delay::new(
                 Instant::now()
@@ -59,6 +61,8 @@ impl Future for RecursorFuture {
                         client: self.client,
                         message: resp,
                         done: true,
+                        allow_recursion: self.allow_recursion,
+                        answer_source: Some(self.inner.answer_source()),
                     }));
                 }
             }
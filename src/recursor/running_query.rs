@@ -5,7 +5,7 @@ use super::{
     recursor::{Recursor, Resolver},
     util::Sender,
 };
-use crate::error::VgError;
+use crate::{error::VgError, server::AnswerSource};
 use failure;
 use futures::{future, prelude::*, Future};
 use r53::{message::SectionType, name, Message, MessageBuilder, Name, RData, RRType, Rcode};
@@ -30,6 +30,9 @@ pub struct RunningQuery {
     recursor: Recursor,
     state: State,
     depth: usize,
+    //source of the last response, for cname chain it's the source of
+    //the final target
+    answer_source: AnswerSource,
 }
 
 impl RunningQuery {
@@ -47,9 +50,14 @@ impl RunningQuery {
             recursor,
             state: State::Init,
             depth,
+            answer_source: AnswerSource::Cache,
         }
     }
 
+    pub fn answer_source(&self) -> AnswerSource {
+        self.answer_source
+    }
+
     pub fn reset(&mut self) {
         let query = self.response.as_mut().unwrap();
         query.take_section(SectionType::Answer);
@@ -62,6 +70,7 @@ impl RunningQuery {
         self.cname_depth = 0;
         self.state = State::Init;
         self.depth = 0;
+        self.answer_source = AnswerSource::Cache;
     }
 
     fn lookup_in_cache(&mut self) -> Option<Message> {
@@ -228,6 +237,7 @@ impl Future for RunningQuery {
                         }
                     }
                     Some(resp) => {
+                        self.answer_source = AnswerSource::Cache;
                         return Ok(Async::Ready(resp));
                     }
                 },
@@ -244,6 +254,7 @@ impl Future for RunningQuery {
                             return Err(e);
                         }
                         Ok(Some(resp)) => {
+                            self.answer_source = AnswerSource::Forwarder;
                             return Ok(Async::Ready(resp));
                         }
                         Ok(none) => {
@@ -280,6 +291,7 @@ impl Future for RunningQuery {
                             return Err(e);
                         }
                         Ok(Some(resp)) => {
+                            self.answer_source = AnswerSource::Iteration;
                             return Ok(Async::Ready(resp));
                         }
                         Ok(none) => {
@@ -2,11 +2,12 @@ use failure;
 use futures::{prelude::*, Future};
 use std::mem;
 
+use r53::{MessageBuilder, Rcode};
 use vanguard2::{
     auth::{AuthFuture, AuthServer},
     config::VanguardConfig,
     recursor::{Recursor, RecursorFuture},
-    server::{Query, QueryHandler},
+    server::{Query, QueryHandler, XfrResponse},
 };
 
 #[derive(Clone)]
@@ -22,6 +23,22 @@ impl Resolver {
             recursor: Recursor::new(&conf.recursor, &conf.forwarder),
         }
     }
+
+    pub fn reload(
+        &self,
+        old_conf: &VanguardConfig,
+        new_conf: &VanguardConfig,
+    ) -> failure::Result<()> {
+        //keys are restored if zones failed to reload, since zones refer them
+        let keyring = self.auth.keyring();
+        keyring.reload(&new_conf.tsig_keys)?;
+        if let Err(e) = self.auth.reload(&old_conf.auth, &new_conf.auth) {
+            let _ = keyring.reload(&old_conf.tsig_keys);
+            return Err(e);
+        }
+        self.recursor.reload(&new_conf.forwarder);
+        Ok(())
+    }
 }
 
 impl QueryHandler for Resolver {
@@ -29,6 +46,10 @@ impl QueryHandler for Resolver {
     fn handle_query(&self, query: Query) -> Self::Response {
         ResolverFuture::new(self.clone(), query)
     }
+
+    fn handle_xfr(&self, query: &Query) -> Option<XfrResponse> {
+        self.auth.handle_xfr(query)
+    }
 }
 
 enum State {
@@ -63,9 +84,16 @@ impl Future for ResolverFuture {
                     Err(_) | Ok(Async::NotReady) => {
                         unreachable!();
                     }
-                    Ok(Async::Ready(query)) => {
+                    Ok(Async::Ready(mut query)) => {
                         if query.done {
                             return Ok(Async::Ready(query));
+                        } else if !query.allow_recursion {
+                            MessageBuilder::new(&mut query.message)
+                                .make_response()
+                                .rcode(Rcode::Refused)
+                                .done();
+                            query.done = true;
+                            return Ok(Async::Ready(query));
                         } else {
                             self.state =
                                 State::Recursor(self.resolver.recursor.handle_query(query));
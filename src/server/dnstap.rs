@@ -0,0 +1,261 @@
+use super::handler::AnswerSource;
+use r53::{Message, MessageRender};
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+//dnstap is a protobuf message carried by frame streams, only the few
+//fields used by client response are encoded here by hand, which is
+//much lighter than generating the whole schema
+pub const CONTENT_TYPE: &[u8] = b"protobuf:dnstap.Dnstap";
+
+const CONTROL_ACCEPT: u32 = 0x01;
+const CONTROL_START: u32 = 0x02;
+const CONTROL_STOP: u32 = 0x03;
+const CONTROL_READY: u32 = 0x04;
+const CONTROL_FIELD_CONTENT_TYPE: u32 = 0x01;
+const MAX_CONTROL_FRAME_LEN: usize = 512;
+
+const WIRE_VARINT: u32 = 0;
+const WIRE_LEN_DELIMITED: u32 = 2;
+const WIRE_FIXED32: u32 = 5;
+
+const DNSTAP_TYPE_MESSAGE: u64 = 1;
+const MESSAGE_TYPE_CLIENT_RESPONSE: u64 = 6;
+const SOCKET_FAMILY_INET: u64 = 1;
+const SOCKET_FAMILY_INET6: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketProtocol {
+    Udp = 1,
+    Tcp = 2,
+    Dot = 3,
+    Doh = 4,
+}
+
+pub struct ClientResponse {
+    pub client: SocketAddr,
+    pub protocol: SocketProtocol,
+    //time since unix epoch
+    pub query_time: Duration,
+    pub response_time: Duration,
+    pub response: Message,
+    pub answer_source: Option<AnswerSource>,
+}
+
+impl ClientResponse {
+    pub fn to_dnstap(&self, identity: &[u8], render: &mut MessageRender) -> Vec<u8> {
+        self.response.rend(render);
+        let wire = render.take_data();
+
+        let mut message = Vec::with_capacity(wire.len() + 64);
+        put_varint_field(&mut message, 1, MESSAGE_TYPE_CLIENT_RESPONSE);
+        let (family, address) = match self.client.ip() {
+            IpAddr::V4(ip) => (SOCKET_FAMILY_INET, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (SOCKET_FAMILY_INET6, ip.octets().to_vec()),
+        };
+        put_varint_field(&mut message, 2, family);
+        put_varint_field(&mut message, 3, self.protocol as u64);
+        put_bytes_field(&mut message, 4, &address);
+        put_varint_field(&mut message, 6, self.client.port() as u64);
+        put_varint_field(&mut message, 8, self.query_time.as_secs());
+        put_fixed32_field(&mut message, 9, self.query_time.subsec_nanos());
+        put_varint_field(&mut message, 12, self.response_time.as_secs());
+        put_fixed32_field(&mut message, 13, self.response_time.subsec_nanos());
+        put_bytes_field(&mut message, 14, &wire);
+
+        let mut dnstap = Vec::with_capacity(message.len() + identity.len() + 64);
+        if !identity.is_empty() {
+            put_bytes_field(&mut dnstap, 1, identity);
+        }
+        put_bytes_field(
+            &mut dnstap,
+            2,
+            concat!("vanguard2 ", env!("CARGO_PKG_VERSION")).as_bytes(),
+        );
+        if let Some(source) = self.answer_source {
+            put_bytes_field(
+                &mut dnstap,
+                3,
+                format!("source={}", source.to_str()).as_bytes(),
+            );
+        }
+        put_bytes_field(&mut dnstap, 14, &message);
+        put_varint_field(&mut dnstap, 15, DNSTAP_TYPE_MESSAGE);
+        dnstap
+    }
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn put_key(buf: &mut Vec<u8>, field: u32, wire_type: u32) {
+    put_varint(buf, ((field << 3) | wire_type) as u64);
+}
+
+fn put_varint_field(buf: &mut Vec<u8>, field: u32, v: u64) {
+    put_key(buf, field, WIRE_VARINT);
+    put_varint(buf, v);
+}
+
+fn put_fixed32_field(buf: &mut Vec<u8>, field: u32, v: u32) {
+    put_key(buf, field, WIRE_FIXED32);
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_bytes_field(buf: &mut Vec<u8>, field: u32, data: &[u8]) {
+    put_key(buf, field, WIRE_LEN_DELIMITED);
+    put_varint(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+//frame streams writer, every data frame is prefixed with its length,
+//control frames are escaped with a zero length
+pub struct FrameWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> FrameWriter<W> {
+    //unidirectional stream, used for file
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        write_control(&mut writer, CONTROL_START, true)?;
+        Ok(FrameWriter { writer })
+    }
+
+    pub fn write_frame(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(&(data.len() as u32).to_be_bytes())?;
+        self.writer.write_all(data)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        write_control(&mut self.writer, CONTROL_STOP, false)?;
+        self.writer.flush()
+    }
+}
+
+//bidirectional stream like socket, receiver has to accept the content
+//type before frame writer is created on it
+pub fn handshake<S: Read + Write>(stream: &mut S) -> io::Result<()> {
+    write_control(stream, CONTROL_READY, true)?;
+    stream.flush()?;
+    if read_control(stream)? != CONTROL_ACCEPT {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "receiver doesn't accept dnstap",
+        ));
+    }
+    Ok(())
+}
+
+fn write_control<W: Write>(writer: &mut W, typ: u32, with_content_type: bool) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(12 + CONTENT_TYPE.len());
+    frame.extend_from_slice(&typ.to_be_bytes());
+    if with_content_type {
+        frame.extend_from_slice(&CONTROL_FIELD_CONTENT_TYPE.to_be_bytes());
+        frame.extend_from_slice(&(CONTENT_TYPE.len() as u32).to_be_bytes());
+        frame.extend_from_slice(CONTENT_TYPE);
+    }
+    writer.write_all(&0u32.to_be_bytes())?;
+    writer.write_all(&(frame.len() as u32).to_be_bytes())?;
+    writer.write_all(&frame)
+}
+
+fn read_control<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    if u32::from_be_bytes(buf) != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "expect control frame",
+        ));
+    }
+    reader.read_exact(&mut buf)?;
+    let len = u32::from_be_bytes(buf) as usize;
+    if len < 4 || len > MAX_CONTROL_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid control frame length",
+        ));
+    }
+    let mut frame = vec![0; len];
+    reader.read_exact(&mut frame)?;
+    Ok(u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r53::{Name, RRType};
+
+    #[test]
+    fn test_put_varint() {
+        let mut buf = Vec::new();
+        put_varint(&mut buf, 1);
+        assert_eq!(buf, vec![1]);
+        buf.clear();
+        put_varint(&mut buf, 300);
+        assert_eq!(buf, vec![0xac, 0x02]);
+        buf.clear();
+        put_varint_field(&mut buf, 15, 1);
+        assert_eq!(buf, vec![0x78, 0x01]);
+    }
+
+    #[test]
+    fn test_frame_writer() {
+        let mut buf = Vec::new();
+        let mut writer = FrameWriter::new(&mut buf).unwrap();
+        writer.write_frame(&[1, 2, 3]).unwrap();
+        writer.finish().unwrap();
+
+        let start_len = 8 + 12 + CONTENT_TYPE.len();
+        assert_eq!(&buf[..4], &[0, 0, 0, 0]);
+        assert_eq!(&buf[8..12], &CONTROL_START.to_be_bytes());
+        assert_eq!(&buf[20..start_len], CONTENT_TYPE);
+        assert_eq!(&buf[start_len..start_len + 7], &[0, 0, 0, 3, 1, 2, 3]);
+        assert_eq!(&buf[start_len + 7..], &[0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn test_handshake() {
+        let mut accept = Vec::new();
+        write_control(&mut accept, CONTROL_ACCEPT, true).unwrap();
+        assert_eq!(
+            read_control(&mut accept.as_slice()).unwrap(),
+            CONTROL_ACCEPT
+        );
+
+        let mut stop = Vec::new();
+        write_control(&mut stop, CONTROL_STOP, false).unwrap();
+        assert_eq!(read_control(&mut stop.as_slice()).unwrap(), CONTROL_STOP);
+        assert!(read_control(&mut [0, 0, 0, 3, 1, 2, 3].as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_client_response_to_dnstap() {
+        let response = ClientResponse {
+            client: "127.0.0.1:5353".parse().unwrap(),
+            protocol: SocketProtocol::Udp,
+            query_time: Duration::new(1, 0),
+            response_time: Duration::new(1, 500),
+            response: Message::with_query(Name::new("example.org").unwrap(), RRType::A),
+            answer_source: Some(AnswerSource::Cache),
+        };
+        let mut render = MessageRender::new();
+        let dnstap = response.to_dnstap(b"vg", &mut render);
+        assert_eq!(&dnstap[..4], &[0x0a, 2, b'v', b'g']);
+        let extra = b"source=cache";
+        assert!(dnstap.windows(extra.len()).any(|w| w == extra));
+        assert_eq!(&dnstap[dnstap.len() - 2..], &[0x78, 0x01]);
+    }
+}
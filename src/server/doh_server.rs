@@ -0,0 +1,235 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use super::{
+    handler::{Query, QueryHandler},
+    socket::bind_tcp,
+};
+use futures::{future, stream::Stream, Future};
+use hyper::{
+    header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE},
+    server::conn::Http,
+    service::service_fn,
+    Body, Method, Request, Response, StatusCode,
+};
+use r53::{message::SectionType, Message, MessageRender};
+use tokio::{executor::spawn, net::TcpListener};
+use tokio_rustls::TlsAcceptor;
+
+const DNS_MESSAGE_CONTENT_TYPE: &str = "application/dns-message";
+const DNS_QUERY_PARAM: &str = "dns=";
+const MAX_MESSAGE_LEN: usize = 65535;
+
+type ResponseFuture = Box<dyn Future<Item = Response<Body>, Error = hyper::Error> + Send>;
+
+//dns over https (rfc8484), support both GET and POST
+pub struct DohServer<S: QueryHandler> {
+    listener: TcpListener,
+    path: String,
+    handler: Arc<S>,
+    acceptor: TlsAcceptor,
+}
+
+impl<S: QueryHandler + 'static> DohServer<S> {
+    pub fn new(
+        addr: SocketAddr,
+        path: String,
+        handler: Arc<S>,
+        acceptor: TlsAcceptor,
+    ) -> io::Result<Self> {
+        Ok(DohServer {
+            listener: bind_tcp(&addr)?,
+            path,
+            handler,
+            acceptor,
+        })
+    }
+
+    pub fn into_future(self) -> impl Future<Item = (), Error = ()> + Send + 'static {
+        let listener = self.listener;
+        let path = Arc::new(self.path);
+        let handler = self.handler;
+        let acceptor = self.acceptor;
+        listener
+            .incoming()
+            .for_each(move |tcp_stream| {
+                let peer = tcp_stream.peer_addr().unwrap();
+                let handler = handler.clone();
+                let path = path.clone();
+                spawn(
+                    acceptor
+                        .accept(tcp_stream)
+                        .map_err(move |e| println!("tls handshake with {} failed {:?}", peer, e))
+                        .and_then(move |tls_stream| {
+                            let service = service_fn(move |req| {
+                                handle_request(&handler, path.as_str(), peer, req)
+                            });
+                            Http::new()
+                                .serve_connection(tls_stream, service)
+                                .map_err(|e| println!("doh connection get error {:?}", e))
+                        }),
+                );
+                Ok(())
+            })
+            .map_err(|e| panic!("error in inbound doh stream: {}", e))
+    }
+}
+
+fn handle_request<S: QueryHandler + 'static>(
+    handler: &Arc<S>,
+    path: &str,
+    client: SocketAddr,
+    req: Request<Body>,
+) -> ResponseFuture {
+    if req.uri().path() != path {
+        return error_response(StatusCode::NOT_FOUND);
+    }
+
+    match *req.method() {
+        Method::GET => {
+            let query = req
+                .uri()
+                .query()
+                .and_then(get_dns_param)
+                .and_then(|param| base64::decode_config(param, base64::URL_SAFE_NO_PAD).ok())
+                .and_then(|wire| Message::from_wire(wire.as_ref()).ok());
+            match query {
+                Some(query) => resolve(handler, query, client),
+                None => error_response(StatusCode::BAD_REQUEST),
+            }
+        }
+        Method::POST => {
+            let is_dns_message = req
+                .headers()
+                .get(CONTENT_TYPE)
+                .map_or(false, |typ| typ == DNS_MESSAGE_CONTENT_TYPE);
+            if !is_dns_message {
+                return error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            }
+
+            let handler = handler.clone();
+            Box::new(read_body(req.into_body()).and_then(move |body| match body {
+                Some(body) => match Message::from_wire(body.as_ref()) {
+                    Ok(query) => resolve(&handler, query, client),
+                    Err(_) => error_response(StatusCode::BAD_REQUEST),
+                },
+                None => error_response(StatusCode::PAYLOAD_TOO_LARGE),
+            }))
+        }
+        _ => error_response(StatusCode::METHOD_NOT_ALLOWED),
+    }
+}
+
+//reading stops once the body is longer than the max dns message, and
+//None is returned
+fn read_body(body: Body) -> impl Future<Item = Option<Vec<u8>>, Error = hyper::Error> {
+    body.map_err(Some)
+        .fold(Vec::new(), |mut buf, chunk| {
+            if buf.len() + chunk.len() > MAX_MESSAGE_LEN {
+                return Err(None);
+            }
+            buf.extend_from_slice(&chunk);
+            Ok(buf)
+        })
+        .then(|result| match result {
+            Ok(buf) => Ok(Some(buf)),
+            Err(None) => Ok(None),
+            Err(Some(e)) => Err(e),
+        })
+}
+
+fn resolve<S: QueryHandler>(
+    handler: &Arc<S>,
+    query: Message,
+    client: SocketAddr,
+) -> ResponseFuture {
+    Box::new(
+        handler
+            .handle_query(Query::new(query, client))
+            .then(|result| match result {
+                Ok(ref query) if query.done => Ok(dns_response(&query.message)),
+                _ => Ok(build_error_response(StatusCode::INTERNAL_SERVER_ERROR)),
+            }),
+    )
+}
+
+fn get_dns_param(query_string: &str) -> Option<&str> {
+    query_string
+        .split('&')
+        .find(|param| param.starts_with(DNS_QUERY_PARAM))
+        .map(|param| &param[DNS_QUERY_PARAM.len()..])
+}
+
+//http cache freshness lifetime shouldn't be longer than the
+//min ttl of the rrsets in the response
+fn get_min_ttl(message: &Message) -> Option<u32> {
+    [SectionType::Answer, SectionType::Authority]
+        .iter()
+        .filter_map(|&section| message.section(section))
+        .flat_map(|rrsets| rrsets.iter())
+        .map(|rrset| rrset.ttl.0)
+        .min()
+}
+
+fn dns_response(message: &Message) -> Response<Body> {
+    let mut render = MessageRender::new();
+    message.rend(&mut render);
+    let data = render.take_data();
+    let mut builder = Response::builder();
+    builder
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, DNS_MESSAGE_CONTENT_TYPE)
+        .header(CONTENT_LENGTH, data.len());
+    if let Some(ttl) = get_min_ttl(message) {
+        builder.header(CACHE_CONTROL, format!("max-age={}", ttl).as_str());
+    }
+    builder.body(Body::from(data)).unwrap()
+}
+
+fn build_error_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn error_response(status: StatusCode) -> ResponseFuture {
+    Box::new(future::ok(build_error_response(status)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r53::{MessageBuilder, Name, RRType, RRset};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_get_dns_param() {
+        assert_eq!(get_dns_param("dns=AAABAAAB"), Some("AAABAAAB"));
+        assert_eq!(get_dns_param("ct=x&dns=AAABAAAB"), Some("AAABAAAB"));
+        assert_eq!(get_dns_param("ct=x"), None);
+    }
+
+    #[test]
+    fn test_read_body() {
+        let body = read_body(Body::from(vec![0u8; MAX_MESSAGE_LEN])).wait();
+        assert_eq!(body.unwrap().map(|body| body.len()), Some(MAX_MESSAGE_LEN));
+        let body = read_body(Body::from(vec![0u8; MAX_MESSAGE_LEN + 1])).wait();
+        assert_eq!(body.unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_min_ttl() {
+        let mut msg = Message::with_query(Name::new("example.org.").unwrap(), RRType::A);
+        assert_eq!(get_min_ttl(&msg), None);
+        {
+            let mut builder = MessageBuilder::new(&mut msg);
+            builder
+                .make_response()
+                .add_answer(RRset::from_str("example.org. 300 IN A 192.0.2.1").unwrap())
+                .add_auth(RRset::from_str("example.org. 100 IN NS ns.example.org.").unwrap())
+                .add_additional(RRset::from_str("ns.example.org. 10 IN A 192.0.2.2").unwrap())
+                .done();
+        }
+        assert_eq!(get_min_ttl(&msg), Some(100));
+    }
+}
@@ -0,0 +1,132 @@
+use r53::{edns::Edns, Message, MessageBuilder, Rcode};
+
+//plain dns message over udp is limited to 512 bytes
+pub const MIN_UDP_PAYLOAD: u16 = 512;
+const SUPPORTED_EDNS_VERSION: u8 = 0;
+//BADVERS is 16, the upper 8 bits of the 12 bits rcode is kept in opt
+const BADVERS_EXTENDED_RCODE: u8 = 1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClientEdns {
+    pub udp_size: u16,
+    pub dnssec_aware: bool,
+}
+
+pub enum EdnsCheckResult {
+    NoEdns,
+    Edns(ClientEdns),
+    BadVersion,
+}
+
+pub fn check_query_edns(query: &Message) -> EdnsCheckResult {
+    match query.edns.as_ref() {
+        None => EdnsCheckResult::NoEdns,
+        Some(edns) if edns.versoin > SUPPORTED_EDNS_VERSION => EdnsCheckResult::BadVersion,
+        Some(edns) => EdnsCheckResult::Edns(ClientEdns {
+            //udp size less than 512 should be treated as 512
+            udp_size: edns.udp_size.max(MIN_UDP_PAYLOAD),
+            dnssec_aware: edns.dnssec_aware,
+        }),
+    }
+}
+
+//the max length of the response which could be sent to client over udp
+pub fn max_response_len(client: Option<&ClientEdns>, server_max_payload: u16) -> usize {
+    let size = match client {
+        None => MIN_UDP_PAYLOAD,
+        Some(edns) => edns.udp_size.min(server_max_payload),
+    };
+    size.max(MIN_UDP_PAYLOAD) as usize
+}
+
+//response only carries opt when query has it, the udp size in it
+//tells client how much data the server could receive
+pub fn set_response_edns(
+    response: &mut Message,
+    client: Option<&ClientEdns>,
+    server_max_payload: u16,
+) {
+    response.edns = client.map(|edns| build_edns(server_max_payload, edns.dnssec_aware, 0));
+}
+
+pub fn make_badvers_response(query: &mut Message, server_max_payload: u16) {
+    let dnssec_aware = query.edns.as_ref().map_or(false, |edns| edns.dnssec_aware);
+    let mut builder = MessageBuilder::new(query);
+    builder
+        .make_response()
+        .rcode(Rcode::NoError)
+        .edns(build_edns(
+            server_max_payload,
+            dnssec_aware,
+            BADVERS_EXTENDED_RCODE,
+        ))
+        .done();
+}
+
+fn build_edns(server_max_payload: u16, dnssec_aware: bool, extened_rcode: u8) -> Edns {
+    Edns {
+        versoin: SUPPORTED_EDNS_VERSION,
+        extened_rcode,
+        udp_size: server_max_payload,
+        dnssec_aware,
+        options: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r53::{Name, RRType};
+
+    fn query_with_edns(versoin: u8, udp_size: u16) -> Message {
+        let mut query = Message::with_query(Name::new("example.org.").unwrap(), RRType::A);
+        query.edns = Some(Edns {
+            versoin,
+            extened_rcode: 0,
+            udp_size,
+            dnssec_aware: true,
+            options: None,
+        });
+        query
+    }
+
+    #[test]
+    fn test_check_query_edns() {
+        let query = Message::with_query(Name::new("example.org.").unwrap(), RRType::A);
+        match check_query_edns(&query) {
+            EdnsCheckResult::NoEdns => {}
+            _ => panic!("query without opt should have no edns"),
+        }
+        assert_eq!(max_response_len(None, 4096), 512);
+
+        match check_query_edns(&query_with_edns(0, 4096)) {
+            EdnsCheckResult::Edns(edns) => {
+                assert!(edns.dnssec_aware);
+                assert_eq!(max_response_len(Some(&edns), 1232), 1232);
+                assert_eq!(max_response_len(Some(&edns), 8192), 4096);
+            }
+            _ => panic!("edns version 0 should be accepted"),
+        }
+
+        match check_query_edns(&query_with_edns(0, 100)) {
+            EdnsCheckResult::Edns(edns) => assert_eq!(max_response_len(Some(&edns), 1232), 512),
+            _ => panic!("edns version 0 should be accepted"),
+        }
+
+        match check_query_edns(&query_with_edns(1, 4096)) {
+            EdnsCheckResult::BadVersion => {}
+            _ => panic!("edns version 1 isn't supported"),
+        }
+    }
+
+    #[test]
+    fn test_badvers_response() {
+        let mut query = query_with_edns(1, 4096);
+        make_badvers_response(&mut query, 1232);
+        let edns = query.edns.as_ref().unwrap();
+        assert_eq!(edns.versoin, 0);
+        assert_eq!(edns.extened_rcode, BADVERS_EXTENDED_RCODE);
+        assert_eq!(edns.udp_size, 1232);
+        assert_eq!(query.header.rcode, Rcode::NoError);
+    }
+}
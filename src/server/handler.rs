@@ -1,13 +1,41 @@
-use failure;
-use futures::Future;
+use super::tsig::{KeyRing, TsigContext};
+use failure::{self, Result};
+use futures::{Future, Stream};
 use r53::Message;
 use std::net::SocketAddr;
 
+//where the answer of the query comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnswerSource {
+    Auth,
+    Cache,
+    Forwarder,
+    Iteration,
+}
+
+impl AnswerSource {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            AnswerSource::Auth => "auth",
+            AnswerSource::Cache => "cache",
+            AnswerSource::Forwarder => "forwarder",
+            AnswerSource::Iteration => "iteration",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Query {
     pub client: SocketAddr,
     pub message: Message,
     pub done: bool,
+    //query from auth only listener won't be recursively resolved
+    pub allow_recursion: bool,
+    pub answer_source: Option<AnswerSource>,
+    //responses of signed query are signed with the same key
+    pub tsig: Option<TsigContext>,
+    //response over udp is size limited, so minimal answer is preferred
+    pub over_udp: bool,
 }
 
 impl Query {
@@ -16,11 +44,37 @@ impl Query {
             client,
             message,
             done: false,
+            allow_recursion: true,
+            answer_source: None,
+            tsig: None,
+            over_udp: false,
+        }
+    }
+
+    //tsig of the query is verified and stripped before message is parsed
+    pub fn from_wire(wire: &[u8], client: SocketAddr, keyring: &KeyRing) -> Result<Self> {
+        match keyring.verify_request(wire)? {
+            Some((wire, tsig)) => {
+                let mut query = Query::new(Message::from_wire(wire.as_ref())?, client);
+                query.tsig = Some(tsig);
+                Ok(query)
+            }
+            None => Ok(Query::new(Message::from_wire(wire)?, client)),
         }
     }
 }
 
+//messages of zone transfer are built when they are polled, so they are
+//only generated as fast as the client reads them
+pub type XfrResponse = Box<dyn Stream<Item = Message, Error = failure::Error> + Send>;
+
 pub trait QueryHandler: Send + Sync {
     type Response: Future<Item = Query, Error = failure::Error> + Send + 'static;
     fn handle_query(&self, query: Query) -> Self::Response;
+
+    //zone transfer answers with a sequence of messages, only available
+    //on stream based transport
+    fn handle_xfr(&self, _query: &Query) -> Option<XfrResponse> {
+        None
+    }
 }
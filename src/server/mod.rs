@@ -1,9 +1,21 @@
+mod dnstap;
+mod doh_server;
+mod edns;
 #[macro_use]
 mod handler;
+mod query_log;
 mod server;
+mod shutdown;
+mod socket;
 mod tcp_server;
+mod tls_server;
+mod tsig;
 mod udp_server;
+mod wire;
 
-pub use self::handler::{Query, QueryHandler};
+pub use self::handler::{AnswerSource, Query, QueryHandler, XfrResponse};
 pub use self::server::Server;
+pub use self::shutdown::{shutdown_channel, ShutdownListener, ShutdownTrigger};
+pub use self::tsig::{normalize_key_name, KeyRing, TsigContext, TsigKey, TsigSigner};
 pub use self::udp_server::start_qps_calculate;
+pub use self::wire::{name_to_wire, read_name, wire_to_string};
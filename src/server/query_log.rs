@@ -0,0 +1,189 @@
+use super::{
+    dnstap::{handshake, ClientResponse, FrameWriter, SocketProtocol},
+    handler::Query,
+};
+use crate::{
+    config::{QueryLogConfig, QueryLogOutput},
+    error::VgError,
+};
+use failure::{self, Result};
+use futures::{Async, Future, Poll};
+use prometheus::IntCounter;
+use r53::MessageRender;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    net::SocketAddr,
+    os::unix::net::UnixStream,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref DROPPED_QUERY_LOG_COUNT: IntCounter = register_int_counter!(
+        "dropped_query_log",
+        "query log dropped since buffer is full"
+    )
+    .unwrap();
+}
+
+//logs are encoded and written by a dedicated thread, query path only
+//does a non-blocking send, logs are dropped when the buffer is full
+pub struct QueryLogger {
+    sender: SyncSender<ClientResponse>,
+    sample_rate: usize,
+    counter: AtomicUsize,
+}
+
+impl QueryLogger {
+    pub fn new(conf: &QueryLogConfig) -> Result<Self> {
+        let open_failed =
+            |e: std::io::Error| VgError::QueryLogFailed(conf.path.clone(), e.to_string());
+        let output: Box<dyn Write + Send> = match conf.output {
+            //file is truncated, since frame stream can't be appended
+            QueryLogOutput::File => {
+                let file = File::create(&conf.path).map_err(open_failed)?;
+                Box::new(BufWriter::new(file))
+            }
+            QueryLogOutput::Unix => {
+                let mut stream = UnixStream::connect(&conf.path).map_err(open_failed)?;
+                handshake(&mut stream).map_err(open_failed)?;
+                Box::new(BufWriter::new(stream))
+            }
+        };
+        let writer = FrameWriter::new(output).map_err(open_failed)?;
+
+        let (sender, receiver) = sync_channel(conf.buffer_size);
+        let identity = conf.identity.clone().into_bytes();
+        thread::spawn(move || write_logs(writer, receiver, identity));
+        Ok(QueryLogger {
+            sender,
+            sample_rate: conf.sample_rate.max(1) as usize,
+            counter: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn should_log(&self) -> bool {
+        self.sample_rate == 1
+            || self.counter.fetch_add(1, Ordering::Relaxed) % self.sample_rate == 0
+    }
+
+    pub fn log(&self, response: ClientResponse) {
+        if self.sender.try_send(response).is_err() {
+            DROPPED_QUERY_LOG_COUNT.inc();
+        }
+    }
+}
+
+fn write_logs(
+    mut writer: FrameWriter<Box<dyn Write + Send>>,
+    receiver: Receiver<ClientResponse>,
+    identity: Vec<u8>,
+) {
+    let mut render = MessageRender::new();
+    loop {
+        let result = match receiver.recv_timeout(FLUSH_INTERVAL) {
+            Ok(response) => writer.write_frame(&response.to_dnstap(&identity, &mut render)),
+            Err(RecvTimeoutError::Timeout) => writer.flush(),
+            Err(RecvTimeoutError::Disconnected) => {
+                if let Err(e) = writer.finish() {
+                    eprintln!("close query log failed: {}", e);
+                }
+                return;
+            }
+        };
+        //once the thread exits, the later logs are counted as dropped
+        if let Err(e) = result {
+            eprintln!("write query log failed: {}, stop logging", e);
+            return;
+        }
+    }
+}
+
+pub struct LogContext {
+    logger: Arc<QueryLogger>,
+    client: SocketAddr,
+    protocol: SocketProtocol,
+    query_time: Duration,
+    start: Instant,
+}
+
+impl LogContext {
+    pub fn new(logger: Arc<QueryLogger>, client: SocketAddr, protocol: SocketProtocol) -> Self {
+        LogContext {
+            logger,
+            client,
+            protocol,
+            query_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+            start: Instant::now(),
+        }
+    }
+}
+
+//log the response once the query is answered, queries which
+//fail or time out don't have response, so they aren't logged
+pub struct LoggedQuery<F> {
+    inner: F,
+    context: Option<LogContext>,
+}
+
+impl<F> LoggedQuery<F> {
+    pub fn new(inner: F, context: Option<LogContext>) -> Self {
+        LoggedQuery { inner, context }
+    }
+}
+
+impl<F: Future<Item = Query, Error = failure::Error>> Future for LoggedQuery<F> {
+    type Item = Query;
+    type Error = failure::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let query = try_ready!(self.inner.poll());
+        if let Some(context) = self.context.take() {
+            context.logger.log(ClientResponse {
+                client: context.client,
+                protocol: context.protocol,
+                query_time: context.query_time,
+                response_time: context.query_time + context.start.elapsed(),
+                response: query.message.clone(),
+                answer_source: query.answer_source,
+            });
+        }
+        Ok(Async::Ready(query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    #[test]
+    fn test_sample_query() {
+        let path =
+            env::temp_dir().join(format!("vanguard2_test_query_log_{}.dnstap", process::id()));
+        let mut conf = QueryLogConfig::default();
+        conf.enable = true;
+        conf.path = path.to_str().unwrap().to_string();
+        conf.sample_rate = 3;
+        let logger = QueryLogger::new(&conf).unwrap();
+        let logged = (0..9).filter(|_| logger.should_log()).count();
+        assert_eq!(logged, 3);
+
+        conf.sample_rate = 0;
+        let logger = QueryLogger::new(&conf).unwrap();
+        assert!((0..9).all(|_| logger.should_log()));
+
+        conf.output = QueryLogOutput::Unix;
+        assert!(QueryLogger::new(&conf).is_err());
+    }
+}
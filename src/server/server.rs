@@ -1,27 +1,217 @@
-use std::{net::SocketAddr, str::FromStr, sync::Arc};
+use std::{io, net::SocketAddr, sync::Arc};
 
-use super::{handler::QueryHandler, tcp_server::TcpServer, udp_server::UdpServer};
-use crate::config::ServerConfig;
+use super::{
+    dnstap::SocketProtocol,
+    doh_server::DohServer,
+    handler::{Query, QueryHandler, XfrResponse},
+    query_log::{LogContext, LoggedQuery, QueryLogger},
+    shutdown::ShutdownListener,
+    tcp_server::TcpServer,
+    tls_server::{load_tls_acceptor, TlsServer},
+    tsig::KeyRing,
+    udp_server::UdpServer,
+};
+use crate::{
+    config::{ListenerConfig, ListenerRole, ServerConfig},
+    error::VgError,
+};
+use failure::{self, Result};
 use futures::{future, Future};
 use tokio::executor::spawn;
+use tokio_rustls::TlsAcceptor;
 
-pub struct Server<S: QueryHandler> {
+type ServerFuture = Box<dyn Future<Item = (), Error = ()> + Send>;
+
+struct Listener {
     addr: SocketAddr,
+    enable_udp: bool,
+    enable_tcp: bool,
+    tls_addr: Option<SocketAddr>,
+    doh_addr: Option<SocketAddr>,
+    allow_recursion: bool,
+}
+
+impl Listener {
+    fn new(conf: &ListenerConfig) -> Result<Self> {
+        let addr: SocketAddr = conf
+            .address
+            .parse()
+            .map_err(|_| VgError::InvalidListenAddress(conf.address.clone()))?;
+        Ok(Listener {
+            addr,
+            enable_udp: conf.enable_udp,
+            enable_tcp: conf.enable_tcp,
+            tls_addr: if conf.enable_tls {
+                Some(SocketAddr::new(addr.ip(), conf.tls_port))
+            } else {
+                None
+            },
+            doh_addr: if conf.enable_doh {
+                Some(SocketAddr::new(addr.ip(), conf.doh_port))
+            } else {
+                None
+            },
+            allow_recursion: conf.role == ListenerRole::Recursor,
+        })
+    }
+}
+
+//mark the queries with the role of the listener they come from,
+//and log the sampled queries with the protocol they use
+pub struct ListenerHandler<S: QueryHandler> {
+    handler: Arc<S>,
+    allow_recursion: bool,
+    protocol: SocketProtocol,
+    query_logger: Option<Arc<QueryLogger>>,
+}
+
+impl<S: QueryHandler> QueryHandler for ListenerHandler<S> {
+    type Response = LoggedQuery<S::Response>;
+    fn handle_query(&self, mut query: Query) -> Self::Response {
+        query.allow_recursion = self.allow_recursion;
+        let context = self
+            .query_logger
+            .as_ref()
+            .filter(|logger| logger.should_log())
+            .map(|logger| LogContext::new(logger.clone(), query.client, self.protocol));
+        LoggedQuery::new(self.handler.handle_query(query), context)
+    }
+
+    fn handle_xfr(&self, query: &Query) -> Option<XfrResponse> {
+        self.handler.handle_xfr(query)
+    }
+}
+
+pub struct Server<S: QueryHandler> {
+    listeners: Vec<Listener>,
+    max_udp_payload: u16,
+    max_tcp_pipelined_queries: usize,
+    tls_acceptor: Option<TlsAcceptor>,
+    doh_path: String,
+    query_logger: Option<Arc<QueryLogger>>,
+    keyring: KeyRing,
     handler: S,
 }
 
 impl<S: QueryHandler + 'static> Server<S> {
-    pub fn new(conf: &ServerConfig, handler: S) -> Self {
-        let addr = conf.address.parse().unwrap();
-        Server { addr, handler }
+    pub fn new(conf: &ServerConfig, handler: S, keyring: KeyRing) -> Result<Self> {
+        let listeners = conf
+            .listeners
+            .iter()
+            .map(Listener::new)
+            .collect::<Result<Vec<Listener>>>()?;
+        let need_tls = listeners
+            .iter()
+            .any(|listener| listener.tls_addr.is_some() || listener.doh_addr.is_some());
+        let tls_acceptor = if need_tls {
+            Some(load_tls_acceptor(&conf.tls_cert_file, &conf.tls_key_file)?)
+        } else {
+            None
+        };
+
+        let query_logger = if conf.query_log.enable {
+            Some(Arc::new(QueryLogger::new(&conf.query_log)?))
+        } else {
+            None
+        };
+
+        Ok(Server {
+            listeners,
+            max_udp_payload: conf.max_udp_payload,
+            max_tcp_pipelined_queries: conf.max_tcp_pipelined_queries,
+            tls_acceptor,
+            doh_path: conf.doh_path.clone(),
+            query_logger,
+            keyring,
+            handler,
+        })
     }
 
-    pub fn into_future(self) -> impl Future<Item = (), Error = ()> + Send + 'static {
-        let handler = Arc::new(self.handler);
-        let addr = self.addr;
+    //all the listeners are bound before any of them is started, so
+    //the process either serves on every configured address or none.
+    //after shutdown, listeners stop accepting new queries, and the
+    //future returned by runtime shutdown_on_idle resolves after all
+    //the in-flight queries are handled
+    pub fn into_future(
+        self,
+        shutdown: ShutdownListener,
+    ) -> impl Future<Item = (), Error = failure::Error> + Send + 'static {
         future::lazy(move || {
-            spawn(UdpServer::new(addr, handler.clone()).map_err(|e| println!("udp errr {:?}", e)));
-            TcpServer::new(addr, handler.clone()).into_future()
+            for server in self.bind(shutdown)? {
+                spawn(server);
+            }
+            Ok(())
         })
     }
+
+    fn bind(self, shutdown: ShutdownListener) -> Result<Vec<ServerFuture>> {
+        let handler = Arc::new(self.handler);
+        let mut servers: Vec<ServerFuture> = Vec::new();
+        for listener in &self.listeners {
+            let listener_handler = |protocol| {
+                Arc::new(ListenerHandler {
+                    handler: handler.clone(),
+                    allow_recursion: listener.allow_recursion,
+                    protocol,
+                    query_logger: self.query_logger.clone(),
+                })
+            };
+            if listener.enable_udp {
+                let server = UdpServer::new(
+                    listener.addr,
+                    listener_handler(SocketProtocol::Udp),
+                    self.max_udp_payload,
+                    self.keyring.clone(),
+                    shutdown.clone(),
+                )
+                .map_err(|e| listen_failed("udp", listener.addr, e))?;
+                servers.push(Box::new(server.map_err(|e| println!("udp errr {:?}", e))));
+            }
+            if listener.enable_tcp {
+                let server = TcpServer::new(
+                    listener.addr,
+                    listener_handler(SocketProtocol::Tcp),
+                    self.max_tcp_pipelined_queries,
+                    self.keyring.clone(),
+                )
+                .map_err(|e| listen_failed("tcp", listener.addr, e))?;
+                servers.push(stop_on_shutdown(server.into_future(), &shutdown));
+            }
+            if let Some(tls_addr) = listener.tls_addr {
+                let server = TlsServer::new(
+                    tls_addr,
+                    listener_handler(SocketProtocol::Dot),
+                    self.tls_acceptor.clone().unwrap(),
+                    self.max_tcp_pipelined_queries,
+                    self.keyring.clone(),
+                )
+                .map_err(|e| listen_failed("tls", tls_addr, e))?;
+                servers.push(stop_on_shutdown(server.into_future(), &shutdown));
+            }
+            if let Some(doh_addr) = listener.doh_addr {
+                let server = DohServer::new(
+                    doh_addr,
+                    self.doh_path.clone(),
+                    listener_handler(SocketProtocol::Doh),
+                    self.tls_acceptor.clone().unwrap(),
+                )
+                .map_err(|e| listen_failed("https", doh_addr, e))?;
+                servers.push(stop_on_shutdown(server.into_future(), &shutdown));
+            }
+        }
+        Ok(servers)
+    }
+}
+
+//stream based server stops accepting new connection after shutdown,
+//the connections already accepted are closed by idle timeout
+fn stop_on_shutdown<F>(server: F, shutdown: &ShutdownListener) -> ServerFuture
+where
+    F: Future<Item = (), Error = ()> + Send + 'static,
+{
+    Box::new(server.select(shutdown.clone()).map(|_| ()).map_err(|_| ()))
+}
+
+fn listen_failed(protocol: &str, addr: SocketAddr, e: io::Error) -> failure::Error {
+    VgError::ListenFailed(protocol.to_string(), addr.to_string(), e.to_string()).into()
 }
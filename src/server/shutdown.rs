@@ -0,0 +1,67 @@
+use futures::{
+    future::Shared,
+    sync::oneshot::{channel, Receiver, Sender},
+    Async, Future, Poll,
+};
+
+pub struct ShutdownTrigger(Sender<()>);
+
+impl ShutdownTrigger {
+    pub fn shutdown(self) {
+        //receiver may be dropped if no server is running
+        let _ = self.0.send(());
+    }
+}
+
+//shutdown listener could be cloned and passed to every running server,
+//all of them will be notified once shutdown is triggered
+#[derive(Clone)]
+pub struct ShutdownListener(Shared<Receiver<()>>);
+
+impl ShutdownListener {
+    //the current task will be notified when shutdown is triggered
+    pub fn is_shutdown(&mut self) -> bool {
+        match self.0.poll() {
+            Ok(Async::NotReady) => false,
+            //trigger is dropped is also treated as shutdown
+            Ok(Async::Ready(_)) | Err(_) => true,
+        }
+    }
+}
+
+impl Future for ShutdownListener {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        if self.is_shutdown() {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+pub fn shutdown_channel() -> (ShutdownTrigger, ShutdownListener) {
+    let (sender, receiver) = channel();
+    (ShutdownTrigger(sender), ShutdownListener(receiver.shared()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shutdown() {
+        let (trigger, listener) = shutdown_channel();
+        let mut listeners = vec![listener.clone(), listener];
+        futures::future::lazy(move || {
+            assert!(listeners.iter_mut().all(|l| !l.is_shutdown()));
+            trigger.shutdown();
+            assert!(listeners.iter_mut().all(|l| l.is_shutdown()));
+            Ok::<(), ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+}
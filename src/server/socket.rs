@@ -0,0 +1,35 @@
+use net2::{TcpBuilder, UdpBuilder};
+use std::{io, net::SocketAddr};
+use tokio::{
+    net::{TcpListener, UdpSocket},
+    reactor::Handle,
+};
+
+const TCP_LISTEN_BACKLOG: i32 = 1024;
+
+//ipv6 socket is bound with v6only, so the same port could be used
+//by an ipv4 listener at the same time
+pub fn bind_udp(addr: &SocketAddr) -> io::Result<UdpSocket> {
+    let builder = if addr.is_ipv4() {
+        UdpBuilder::new_v4()?
+    } else {
+        let builder = UdpBuilder::new_v6()?;
+        builder.only_v6(true)?;
+        builder
+    };
+    let socket = builder.bind(addr)?;
+    UdpSocket::from_std(socket, &Handle::default())
+}
+
+pub fn bind_tcp(addr: &SocketAddr) -> io::Result<TcpListener> {
+    let builder = if addr.is_ipv4() {
+        TcpBuilder::new_v4()?
+    } else {
+        let builder = TcpBuilder::new_v6()?;
+        builder.only_v6(true)?;
+        builder
+    };
+    builder.reuse_address(true)?;
+    let listener = builder.bind(addr)?.listen(TCP_LISTEN_BACKLOG)?;
+    TcpListener::from_std(listener, &Handle::default())
+}
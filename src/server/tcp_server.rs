@@ -1,64 +1,92 @@
 use std::{
+    collections::VecDeque,
     io::{self, Read, Write},
-    mem,
     net::SocketAddr,
     time::Duration,
 };
 
-use super::handler::{Query, QueryHandler};
-use failure;
-use futures::{future, stream::Stream, Async, Future, Poll};
+use super::{
+    handler::{Query, QueryHandler, XfrResponse},
+    socket::bind_tcp,
+    tsig::{KeyRing, TsigSigner},
+};
+use futures::{
+    future,
+    stream::{FuturesUnordered, Stream},
+    Async, Future, Poll,
+};
 use r53::{Message, MessageRender};
 use std::sync::Arc;
-use tokio::{
-    executor::spawn,
-    net::{TcpListener, TcpStream},
-};
+use tokio::{executor::spawn, net::TcpListener};
 use tokio_io::try_nb;
 use tokio_timer::Timeout;
 
 const DEFAULT_RECV_TIMEOUT: Duration = Duration::from_secs(3); //3 secs
 
 pub struct TcpServer<S: QueryHandler> {
-    addr: SocketAddr,
+    listener: TcpListener,
     handler: Arc<S>,
+    max_pending_queries: usize,
+    keyring: KeyRing,
 }
 
 impl<S: QueryHandler + 'static> TcpServer<S> {
-    pub fn new(addr: SocketAddr, handler: Arc<S>) -> Self {
-        TcpServer { addr, handler }
+    pub fn new(
+        addr: SocketAddr,
+        handler: Arc<S>,
+        max_pending_queries: usize,
+        keyring: KeyRing,
+    ) -> io::Result<Self> {
+        Ok(TcpServer {
+            listener: bind_tcp(&addr)?,
+            handler,
+            max_pending_queries,
+            keyring,
+        })
     }
 
     pub fn into_future(self) -> impl Future<Item = (), Error = ()> + Send + 'static {
-        let listener = TcpListener::bind(&self.addr).unwrap();
-        listener
+        let handler = self.handler;
+        let max_pending_queries = self.max_pending_queries;
+        let keyring = self.keyring;
+        self.listener
             .incoming()
             .for_each(move |tcp_stream| {
                 let peer = tcp_stream.peer_addr().unwrap();
-                let stream = TcpStreamWrapper::from_stream(tcp_stream, peer, self.handler.clone());
-                let stream = Timeout::new(stream, DEFAULT_RECV_TIMEOUT);
-                spawn(
-                    stream
-                        .for_each(|_| future::ok(()))
-                        .map_err(|e| println!("get error {:?}", e)),
-                );
-
+                spawn(serve_stream(
+                    tcp_stream,
+                    peer,
+                    handler.clone(),
+                    max_pending_queries,
+                    keyring.clone(),
+                ));
                 Ok(())
             })
             .map_err(|e| panic!("error in inbound tcp_stream: {}", e))
     }
 }
 
+//serve queries from a connected stream which uses two bytes length
+//prefixed framing, it's shared by plain tcp and tls
+pub fn serve_stream<S, T>(
+    stream: T,
+    peer: SocketAddr,
+    handler: Arc<S>,
+    max_pending_queries: usize,
+    keyring: KeyRing,
+) -> impl Future<Item = (), Error = ()> + Send + 'static
+where
+    S: QueryHandler + 'static,
+    T: Read + Write + Send + 'static,
+{
+    let stream = TcpStreamWrapper::from_stream(stream, peer, handler, max_pending_queries, keyring);
+    Timeout::new(stream, DEFAULT_RECV_TIMEOUT)
+        .for_each(|_| future::ok(()))
+        .map_err(|e| println!("get error {:?}", e))
+}
+
 enum WriteTcpState {
-    LenBytes {
-        pos: usize,
-        length: [u8; 2],
-        bytes: Vec<u8>,
-    },
-    Bytes {
-        pos: usize,
-        bytes: Vec<u8>,
-    },
+    Bytes { pos: usize, bytes: Vec<u8> },
     Flushing,
 }
 
@@ -67,18 +95,32 @@ pub enum ReadTcpState {
     Bytes { pos: usize, bytes: Vec<u8> },
 }
 
-pub struct TcpStreamWrapper<S: QueryHandler> {
-    socket: TcpStream,
+//queries on one connection are handled concurrently, responses are
+//sent back in the order they are finished which is allowed by rfc7766
+pub struct TcpStreamWrapper<S: QueryHandler, T> {
+    socket: T,
     send_state: Option<WriteTcpState>,
     read_state: Option<ReadTcpState>,
-    wait_for_handling: Option<S::Response>,
+    pending_queries: FuturesUnordered<S::Response>,
+    max_pending_queries: usize,
+    pending_responses: VecDeque<Vec<u8>>,
+    //zone transfers are sent one after another, messages of them are
+    //chained by tsig
+    pending_xfrs: VecDeque<(XfrResponse, Option<TsigSigner>)>,
     peer_addr: SocketAddr,
     handler: Arc<S>,
     render: MessageRender,
+    keyring: KeyRing,
 }
 
-impl<S: QueryHandler> TcpStreamWrapper<S> {
-    pub fn from_stream(stream: TcpStream, peer_addr: SocketAddr, handler: Arc<S>) -> Self {
+impl<S: QueryHandler, T: Read + Write> TcpStreamWrapper<S, T> {
+    pub fn from_stream(
+        stream: T,
+        peer_addr: SocketAddr,
+        handler: Arc<S>,
+        max_pending_queries: usize,
+        keyring: KeyRing,
+    ) -> Self {
         TcpStreamWrapper {
             socket: stream,
             send_state: None,
@@ -86,14 +128,20 @@ impl<S: QueryHandler> TcpStreamWrapper<S> {
                 pos: 0,
                 bytes: [0u8; 2],
             }),
-            wait_for_handling: None,
+            pending_queries: FuturesUnordered::new(),
+            max_pending_queries,
+            pending_responses: VecDeque::new(),
+            pending_xfrs: VecDeque::new(),
             peer_addr,
             handler,
             render: MessageRender::new(),
+            keyring,
         }
     }
 
-    fn try_read(&mut self) -> Poll<Option<()>, io::Error> {
+    //read state is set to None when client closes the connection
+    //or sends a malformed message
+    fn try_read(&mut self) -> Poll<Option<Query>, io::Error> {
         loop {
             match self.read_state.as_mut().unwrap() {
                 ReadTcpState::LenBytes {
@@ -116,8 +164,7 @@ impl<S: QueryHandler> TcpStreamWrapper<S> {
                     if *pos == bytes.len() {
                         let length =
                             u16::from(bytes[0]) << 8 & 0xFF00 | u16::from(bytes[1]) & 0x00FF;
-                        let mut bytes = vec![0; length as usize];
-                        bytes.resize(length as usize, 0);
+                        let bytes = vec![0; length as usize];
                         self.read_state = Some(ReadTcpState::Bytes { pos: 0, bytes });
                     }
                 }
@@ -135,14 +182,17 @@ impl<S: QueryHandler> TcpStreamWrapper<S> {
 
                     *pos += read;
                     if *pos == bytes.len() {
-                        let query = Message::from_wire(bytes.as_ref());
-                        if query.is_ok() {
-                            self.read_state = None;
-                            let query = Query::new(query.unwrap(), self.peer_addr);
-                            self.wait_for_handling = Some(self.handler.handle_query(query));
-                            return Ok(Async::Ready(Some(())));
-                        } else {
-                            return Ok(Async::Ready(None));
+                        match Query::from_wire(bytes.as_ref(), self.peer_addr, &self.keyring) {
+                            Ok(query) => {
+                                self.read_state = Some(ReadTcpState::LenBytes {
+                                    pos: 0,
+                                    bytes: [0u8; 2],
+                                });
+                                return Ok(Async::Ready(Some(query)));
+                            }
+                            Err(_) => {
+                                return Ok(Async::Ready(None));
+                            }
                         }
                     }
                 }
@@ -150,118 +200,160 @@ impl<S: QueryHandler> TcpStreamWrapper<S> {
         }
     }
 
-    fn try_send(&mut self) -> Poll<(), io::Error> {
-        loop {
-            match self.send_state {
-                Some(WriteTcpState::LenBytes {
-                    ref mut pos,
-                    ref length,
-                    ..
-                }) => {
-                    let wrote = try_nb!(self.socket.write(&length[*pos..]));
-                    *pos += wrote;
+    //return true if any query is read
+    fn read_queries(&mut self) -> Result<bool, io::Error> {
+        let mut has_new_query = false;
+        while self.read_state.is_some() && self.pending_queries.len() < self.max_pending_queries {
+            match self.try_read()? {
+                Async::Ready(Some(query)) => {
+                    if let Some(responses) = self.handler.handle_xfr(&query) {
+                        let signer = query.tsig.as_ref().map(|tsig| tsig.signer());
+                        self.pending_xfrs.push_back((responses, signer));
+                    } else {
+                        self.pending_queries.push(self.handler.handle_query(query));
+                    }
+                    has_new_query = true;
                 }
-                Some(WriteTcpState::Bytes {
-                    ref mut pos,
-                    ref bytes,
-                }) => {
-                    let wrote = try_nb!(self.socket.write(&bytes[*pos..]));
-                    *pos += wrote;
+                Async::Ready(None) => {
+                    self.read_state = None;
                 }
-                Some(WriteTcpState::Flushing) => {
-                    try_nb!(self.socket.flush());
+                Async::NotReady => break,
+            }
+        }
+        Ok(has_new_query)
+    }
+
+    //return true if any query is finished
+    fn collect_responses(&mut self) -> bool {
+        let mut has_new_response = false;
+        loop {
+            match self.pending_queries.poll() {
+                Ok(Async::Ready(Some(query))) => {
+                    has_new_response = true;
+                    if query.done {
+                        let mut signer = query.tsig.as_ref().map(|tsig| tsig.signer());
+                        self.push_response(&query.message, signer.as_mut());
+                    }
                 }
-                None => {
-                    self.read_state = Some(ReadTcpState::LenBytes {
-                        pos: 0,
-                        bytes: [0u8; 2],
-                    });
-                    return Ok(Async::Ready(()));
+                //failed query is removed from pending queries, no response
+                //is sent back and client will timeout
+                Err(_) => {
+                    has_new_response = true;
                 }
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => return has_new_response,
             }
+        }
+    }
 
-            let current_state = mem::replace(&mut self.send_state, None);
-            match current_state {
-                Some(WriteTcpState::LenBytes { pos, length, bytes }) => {
-                    if pos < length.len() {
-                        self.send_state = Some(WriteTcpState::LenBytes { pos, length, bytes });
-                    } else {
-                        self.send_state = Some(WriteTcpState::Bytes { pos: 0, bytes });
-                    }
+    //next message of zone transfer is only built when the previous
+    //responses are all written, so a slow client doesn't pile up the
+    //messages in memory, return true if any message is generated
+    fn collect_xfr_response(&mut self) -> bool {
+        while self.send_state.is_none() && self.pending_responses.is_empty() {
+            let (mut responses, mut signer) = match self.pending_xfrs.pop_front() {
+                Some(xfr) => xfr,
+                None => return false,
+            };
+            match responses.poll() {
+                Ok(Async::Ready(Some(message))) => {
+                    self.push_response(&message, signer.as_mut());
+                    self.pending_xfrs.push_front((responses, signer));
+                    return true;
+                }
+                Ok(Async::Ready(None)) => {}
+                Ok(Async::NotReady) => {
+                    self.pending_xfrs.push_front((responses, signer));
+                    return false;
                 }
-                Some(WriteTcpState::Bytes { pos, bytes }) => {
-                    if pos < bytes.len() {
-                        self.send_state = Some(WriteTcpState::Bytes { pos, bytes });
+                //rest of the failed zone transfer is dropped
+                Err(e) => {
+                    eprintln!("zone transfer failed: {}", e);
+                }
+            }
+        }
+        false
+    }
+
+    fn push_response(&mut self, message: &Message, signer: Option<&mut TsigSigner>) {
+        message.rend(&mut self.render);
+        let mut buffer = self.render.take_data();
+        if let Some(signer) = signer {
+            signer.sign(&mut buffer);
+        }
+        //length prefix is two bytes, longer message can't be sent
+        if buffer.len() > u16::max_value() as usize {
+            eprintln!("response with {} bytes is too long", buffer.len());
+            return;
+        }
+        let mut bytes = Vec::with_capacity(buffer.len() + 2);
+        bytes.push((buffer.len() >> 8 & 0xFF) as u8);
+        bytes.push((buffer.len() & 0xFF) as u8);
+        bytes.extend_from_slice(buffer.as_ref());
+        self.pending_responses.push_back(bytes);
+    }
+
+    fn try_send(&mut self) -> Poll<(), io::Error> {
+        loop {
+            match self.send_state {
+                Some(WriteTcpState::Bytes {
+                    ref mut pos,
+                    ref bytes,
+                }) => {
+                    if *pos < bytes.len() {
+                        let wrote = try_nb!(self.socket.write(&bytes[*pos..]));
+                        *pos += wrote;
                     } else {
                         self.send_state = Some(WriteTcpState::Flushing);
                     }
                 }
                 Some(WriteTcpState::Flushing) => {
+                    try_nb!(self.socket.flush());
                     self.send_state = None;
+                    return Ok(Async::Ready(()));
                 }
-                None => {
-                    panic!("this shouldn't happend");
-                }
-            };
+                None => match self.pending_responses.pop_front() {
+                    Some(bytes) => {
+                        self.send_state = Some(WriteTcpState::Bytes { pos: 0, bytes });
+                    }
+                    None => return Ok(Async::Ready(())),
+                },
+            }
         }
     }
 
-    fn handle_query(&mut self) -> Poll<(), io::Error> {
-        let mut handler_fut = mem::replace(&mut self.wait_for_handling, None).unwrap();
-        match handler_fut.poll() {
-            Ok(Async::Ready(query)) => {
-                if query.done {
-                    query.message.rend(&mut self.render);
-                    let buffer = self.render.take_data();
-                    let len: [u8; 2] = [
-                        (buffer.len() >> 8 & 0xFF) as u8,
-                        (buffer.len() & 0xFF) as u8,
-                    ];
-                    self.send_state = Some(WriteTcpState::LenBytes {
-                        pos: 0,
-                        length: len,
-                        bytes: buffer,
-                    });
-                }
-                return Ok(Async::Ready(()));
-            }
-            Ok(Async::NotReady) => {
-                self.wait_for_handling = Some(handler_fut);
-                return Ok(Async::NotReady);
-            }
-            Err(_) => {
-                self.read_state = Some(ReadTcpState::LenBytes {
-                    pos: 0,
-                    bytes: [0u8; 2],
-                });
-                return Ok(Async::Ready(()));
-            }
-        }
+    #[inline]
+    fn is_finished(&self) -> bool {
+        self.read_state.is_none()
+            && self.pending_queries.is_empty()
+            && self.pending_responses.is_empty()
+            && self.pending_xfrs.is_empty()
+            && self.send_state.is_none()
     }
 }
 
-impl<S: QueryHandler> Stream for TcpStreamWrapper<S> {
+impl<S: QueryHandler, T: Read + Write> Stream for TcpStreamWrapper<S, T> {
     type Item = ();
     type Error = io::Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         loop {
-            if self.read_state.is_some() {
-                match try_ready!(self.try_read()) {
-                    None => {
-                        return Ok(Async::Ready(None));
-                    }
-                    _ => (),
-                }
+            let has_new_query = self.read_queries()?;
+            let has_new_response = self.collect_responses();
+            let has_new_xfr_response = self.collect_xfr_response();
+
+            let has_response_to_send =
+                self.send_state.is_some() || !self.pending_responses.is_empty();
+            if has_response_to_send {
+                try_ready!(self.try_send());
+                return Ok(Async::Ready(Some(())));
             }
 
-            if self.wait_for_handling.is_some() {
-                try_ready!(self.handle_query());
+            if self.is_finished() {
+                return Ok(Async::Ready(None));
             }
 
-            if self.send_state.is_some() {
-                try_ready!(self.try_send());
-                return Ok(Async::Ready(Some(())));
+            if !has_new_query && !has_new_response && !has_new_xfr_response {
+                return Ok(Async::NotReady);
             }
         }
     }
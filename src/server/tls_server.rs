@@ -0,0 +1,97 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use super::{handler::QueryHandler, socket::bind_tcp, tcp_server::serve_stream, tsig::KeyRing};
+use crate::error::VgError;
+use failure::Result;
+use futures::{stream::Stream, Future};
+use tokio::{executor::spawn, net::TcpListener};
+use tokio_rustls::{
+    rustls::{
+        internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys},
+        NoClientAuth, ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+//dns over tls (rfc7858), after handshake, the framing is same with tcp
+pub struct TlsServer<S: QueryHandler> {
+    listener: TcpListener,
+    handler: Arc<S>,
+    acceptor: TlsAcceptor,
+    max_pending_queries: usize,
+    keyring: KeyRing,
+}
+
+impl<S: QueryHandler + 'static> TlsServer<S> {
+    pub fn new(
+        addr: SocketAddr,
+        handler: Arc<S>,
+        acceptor: TlsAcceptor,
+        max_pending_queries: usize,
+        keyring: KeyRing,
+    ) -> io::Result<Self> {
+        Ok(TlsServer {
+            listener: bind_tcp(&addr)?,
+            handler,
+            acceptor,
+            max_pending_queries,
+            keyring,
+        })
+    }
+
+    pub fn into_future(self) -> impl Future<Item = (), Error = ()> + Send + 'static {
+        let handler = self.handler;
+        let acceptor = self.acceptor;
+        let max_pending_queries = self.max_pending_queries;
+        let keyring = self.keyring;
+        self.listener
+            .incoming()
+            .for_each(move |tcp_stream| {
+                let peer = tcp_stream.peer_addr().unwrap();
+                let handler = handler.clone();
+                let keyring = keyring.clone();
+                spawn(
+                    acceptor
+                        .accept(tcp_stream)
+                        .map_err(move |e| println!("tls handshake with {} failed {:?}", peer, e))
+                        .and_then(move |tls_stream| {
+                            serve_stream(tls_stream, peer, handler, max_pending_queries, keyring)
+                        }),
+                );
+                Ok(())
+            })
+            .map_err(|e| panic!("error in inbound tls stream: {}", e))
+    }
+}
+
+pub fn load_tls_acceptor(cert_file: &str, key_file: &str) -> Result<TlsAcceptor> {
+    let invalid_config =
+        |reason: String| -> failure::Error { VgError::InvalidTlsConfig(reason).into() };
+
+    let cert_chain = certs(&mut BufReader::new(File::open(cert_file)?))
+        .map_err(|_| invalid_config(format!("{} has invalid certificate", cert_file)))?;
+    if cert_chain.is_empty() {
+        return Err(invalid_config(format!("{} has no certificate", cert_file)));
+    }
+
+    let mut keys = pkcs8_private_keys(&mut BufReader::new(File::open(key_file)?))
+        .map_err(|_| invalid_config(format!("{} has invalid private key", key_file)))?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut BufReader::new(File::open(key_file)?))
+            .map_err(|_| invalid_config(format!("{} has invalid private key", key_file)))?;
+    }
+    let key = keys
+        .pop()
+        .ok_or_else(|| invalid_config(format!("{} has no private key", key_file)))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(cert_chain, key)
+        .map_err(|e| invalid_config(e.to_string()))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -0,0 +1,610 @@
+use super::wire::{read_name, wire_to_string, write_name};
+use crate::{config::TsigKeyConfig, error::VgError};
+use failure::Result;
+use ring::{constant_time::verify_slices_are_equal, hmac};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+//transaction signature defined in rfc8945, tsig rr isn't parsed into
+//message, it's stripped from the wire before the message is parsed and
+//appended to the wire after the message is rendered
+const TSIG_TYPE: u16 = 250;
+const CLASS_ANY: u16 = 255;
+const HEADER_LEN: usize = 12;
+const ARCOUNT_OFFSET: usize = 10;
+const DEFAULT_FUDGE: u16 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TsigError {
+    NoError = 0,
+    BadSig = 16,
+    BadKey = 17,
+    BadTime = 18,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TsigAlgorithm {
+    HmacSha256,
+    HmacSha512,
+}
+
+impl TsigAlgorithm {
+    fn from_name(name: &str) -> Option<Self> {
+        match normalize_key_name(name).as_ref() {
+            "hmac-sha256." => Some(TsigAlgorithm::HmacSha256),
+            "hmac-sha512." => Some(TsigAlgorithm::HmacSha512),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TsigAlgorithm::HmacSha256 => "hmac-sha256.",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512.",
+        }
+    }
+
+    fn mac_len(self) -> usize {
+        match self {
+            TsigAlgorithm::HmacSha256 => 32,
+            TsigAlgorithm::HmacSha512 => 64,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TsigKey {
+    name: String,
+    algorithm: TsigAlgorithm,
+    secret: Vec<u8>,
+}
+
+//secret isn't printed
+impl fmt::Debug for TsigKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TsigKey({} {})", self.name, self.algorithm.name())
+    }
+}
+
+impl TsigKey {
+    pub fn new(conf: &TsigKeyConfig) -> Result<Self> {
+        let invalid_key =
+            |reason: &str| VgError::InvalidTsigKey(conf.name.clone(), reason.to_string());
+        let algorithm = TsigAlgorithm::from_name(&conf.algorithm)
+            .ok_or_else(|| invalid_key("unsupported algorithm"))?;
+        let secret = base64::decode(&conf.secret).map_err(|_| invalid_key("invalid secret"))?;
+        if secret.is_empty() {
+            return Err(invalid_key("empty secret").into());
+        }
+        Ok(TsigKey {
+            name: normalize_key_name(&conf.name),
+            algorithm,
+            secret,
+        })
+    }
+
+    pub fn get_name(&self) -> &str {
+        self.name.as_ref()
+    }
+}
+
+//key names are compared case insensitively with trailing dot
+pub fn normalize_key_name(name: &str) -> String {
+    let mut name = name.to_lowercase();
+    if !name.ends_with('.') {
+        name.push('.');
+    }
+    name
+}
+
+//keys could be replaced by reload, users always get the current ones
+#[derive(Clone, Default)]
+pub struct KeyRing {
+    keys: Arc<RwLock<HashMap<String, TsigKey>>>,
+}
+
+impl KeyRing {
+    pub fn new(conf: &[TsigKeyConfig]) -> Result<Self> {
+        let keyring = KeyRing::default();
+        keyring.reload(conf)?;
+        Ok(keyring)
+    }
+
+    pub fn reload(&self, conf: &[TsigKeyConfig]) -> Result<()> {
+        let mut keys = HashMap::new();
+        for key_conf in conf {
+            let key = TsigKey::new(key_conf)?;
+            keys.insert(key.name.clone(), key);
+        }
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    pub fn get_key(&self, name: &str) -> Option<TsigKey> {
+        self.keys
+            .read()
+            .unwrap()
+            .get(&normalize_key_name(name))
+            .cloned()
+    }
+
+    pub fn has_key(&self, name: &str) -> bool {
+        self.keys
+            .read()
+            .unwrap()
+            .contains_key(&normalize_key_name(name))
+    }
+
+    //return None if the request isn't signed, otherwise the wire without
+    //tsig rr and the verify result, which is used to sign the response
+    pub fn verify_request(&self, wire: &[u8]) -> Result<Option<(Vec<u8>, TsigContext)>> {
+        let (message, tsig) = match split_tsig(wire)? {
+            Some(result) => result,
+            None => return Ok(None),
+        };
+
+        let key = self
+            .get_key(&tsig.key_name)
+            .filter(|key| key.algorithm.name() == tsig.algorithm);
+        let error = match key {
+            None => TsigError::BadKey,
+            Some(ref key) => {
+                let mac = compute_mac(key, None, &message, &tsig, true);
+                if verify_slices_are_equal(&mac, &tsig.mac).is_err() {
+                    TsigError::BadSig
+                } else if !is_in_time(&tsig) {
+                    TsigError::BadTime
+                } else {
+                    TsigError::NoError
+                }
+            }
+        };
+        let context = TsigContext {
+            key,
+            key_name: tsig.key_name,
+            algorithm: tsig.algorithm,
+            error,
+            request_mac: tsig.mac,
+            request_time: tsig.time_signed,
+        };
+        Ok(Some((message, context)))
+    }
+}
+
+//state of the signed request, responses are signed with the same key
+#[derive(Debug, Clone)]
+pub struct TsigContext {
+    key: Option<TsigKey>,
+    key_name: String,
+    algorithm: String,
+    error: TsigError,
+    request_mac: Vec<u8>,
+    request_time: u64,
+}
+
+impl TsigContext {
+    pub fn get_key_name(&self) -> &str {
+        self.key_name.as_ref()
+    }
+
+    pub fn get_error(&self) -> TsigError {
+        self.error
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.error == TsigError::NoError
+    }
+
+    //space reserved for tsig rr when the response is truncated
+    pub fn rr_len(&self) -> usize {
+        let mac_len = self.key.as_ref().map_or(0, |key| key.algorithm.mac_len());
+        name_wire_len(&self.key_name) + 10 + name_wire_len(&self.algorithm) + 22 + mac_len
+    }
+
+    pub fn signer(&self) -> TsigSigner {
+        //response of bad signature or unknown key isn't signed
+        let key = match self.error {
+            TsigError::BadSig | TsigError::BadKey => None,
+            _ => self.key.clone(),
+        };
+        TsigSigner {
+            key,
+            key_name: self.key_name.clone(),
+            algorithm: self.algorithm.clone(),
+            error: self.error,
+            request_time: self.request_time,
+            prior_mac: Some(self.request_mac.clone()),
+            message_count: 1,
+            unsigned: Vec::new(),
+        }
+    }
+}
+
+//sign the request and verify its responses on client side, or sign the
+//responses on server side. Besides the first request and response, the
+//following messages in one stream only cover the timers and are chained
+//by the mac of the previous one
+pub struct TsigSigner {
+    key: Option<TsigKey>,
+    key_name: String,
+    algorithm: String,
+    error: TsigError,
+    request_time: u64,
+    prior_mac: Option<Vec<u8>>,
+    message_count: usize,
+    //messages which aren't signed are covered by the next signed one
+    unsigned: Vec<u8>,
+}
+
+impl TsigSigner {
+    pub fn new(key: TsigKey) -> Self {
+        TsigSigner {
+            key_name: key.name.clone(),
+            algorithm: key.algorithm.name().to_string(),
+            key: Some(key),
+            error: TsigError::NoError,
+            request_time: 0,
+            prior_mac: None,
+            message_count: 0,
+            unsigned: Vec::new(),
+        }
+    }
+
+    //append tsig rr to the rendered message
+    pub fn sign(&mut self, wire: &mut Vec<u8>) {
+        if wire.len() < HEADER_LEN {
+            return;
+        }
+        let now = now();
+        let mut tsig = TsigRecord {
+            key_name: self.key_name.clone(),
+            algorithm: self.algorithm.clone(),
+            time_signed: now,
+            fudge: DEFAULT_FUDGE,
+            mac: Vec::new(),
+            original_id: u16::from_be_bytes([wire[0], wire[1]]),
+            error: self.error as u16,
+            other: Vec::new(),
+        };
+        if self.error == TsigError::BadTime {
+            tsig.other = now.to_be_bytes()[2..].to_vec();
+        }
+        match self.key {
+            Some(ref key) => {
+                tsig.mac = compute_mac(
+                    key,
+                    self.prior_mac.as_ref(),
+                    wire,
+                    &tsig,
+                    self.message_count <= 1,
+                );
+                self.prior_mac = Some(tsig.mac.clone());
+            }
+            None => tsig.time_signed = self.request_time,
+        }
+        self.message_count += 1;
+        tsig.to_wire(wire);
+        let arcount = u16::from_be_bytes([wire[ARCOUNT_OFFSET], wire[ARCOUNT_OFFSET + 1]]) + 1;
+        wire[ARCOUNT_OFFSET..ARCOUNT_OFFSET + 2].copy_from_slice(&arcount.to_be_bytes());
+    }
+
+    //return the wire without tsig rr, the first response has to be signed
+    pub fn verify(&mut self, wire: &[u8]) -> Result<Vec<u8>> {
+        let (message, tsig) = match split_tsig(wire)? {
+            Some(result) => result,
+            None if self.message_count > 1 => {
+                self.unsigned.extend_from_slice(wire);
+                return Ok(wire.to_vec());
+            }
+            None => return Err(invalid_tsig("response isn't signed")),
+        };
+        let key = self
+            .key
+            .as_ref()
+            .ok_or_else(|| invalid_tsig("no key to verify"))?;
+        if tsig.key_name != key.name || tsig.algorithm != key.algorithm.name() {
+            return Err(invalid_tsig("response is signed by another key"));
+        }
+        if tsig.error != TsigError::NoError as u16 {
+            return Err(invalid_tsig(&format!("get tsig error {}", tsig.error)));
+        }
+
+        let mut signed = std::mem::replace(&mut self.unsigned, Vec::new());
+        signed.extend_from_slice(&message);
+        let mac = compute_mac(
+            key,
+            self.prior_mac.as_ref(),
+            &signed,
+            &tsig,
+            self.message_count <= 1,
+        );
+        if verify_slices_are_equal(&mac, &tsig.mac).is_err() {
+            return Err(invalid_tsig("bad signature"));
+        }
+        if !is_in_time(&tsig) {
+            return Err(invalid_tsig("bad time"));
+        }
+        self.prior_mac = Some(tsig.mac);
+        self.message_count += 1;
+        Ok(message)
+    }
+
+    //the last message in the stream has to be signed
+    pub fn is_verified(&self) -> bool {
+        self.message_count > 1 && self.unsigned.is_empty()
+    }
+}
+
+struct TsigRecord {
+    key_name: String,
+    algorithm: String,
+    time_signed: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+}
+
+impl TsigRecord {
+    fn from_wire(wire: &[u8], key_name: String, pos: usize, end: usize) -> Result<Self> {
+        let (algorithm, mut pos) = read_name_text(wire, pos)?;
+        let time_signed = read_bytes(wire, &mut pos, end, 6)?
+            .iter()
+            .fold(0u64, |time, &b| time << 8 | u64::from(b));
+        let fudge = read_u16(read_bytes(wire, &mut pos, end, 2)?);
+        let mac_len = read_u16(read_bytes(wire, &mut pos, end, 2)?) as usize;
+        let mac = read_bytes(wire, &mut pos, end, mac_len)?.to_vec();
+        let original_id = read_u16(read_bytes(wire, &mut pos, end, 2)?);
+        let error = read_u16(read_bytes(wire, &mut pos, end, 2)?);
+        let other_len = read_u16(read_bytes(wire, &mut pos, end, 2)?) as usize;
+        let other = read_bytes(wire, &mut pos, end, other_len)?.to_vec();
+        Ok(TsigRecord {
+            key_name,
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        })
+    }
+
+    fn to_wire(&self, buf: &mut Vec<u8>) {
+        write_name(buf, &self.key_name);
+        buf.extend_from_slice(&TSIG_TYPE.to_be_bytes());
+        buf.extend_from_slice(&CLASS_ANY.to_be_bytes());
+        buf.extend_from_slice(&0u32.to_be_bytes());
+        let rdlen_pos = buf.len();
+        buf.extend_from_slice(&[0, 0]);
+        write_name(buf, &self.algorithm);
+        buf.extend_from_slice(&self.time_signed.to_be_bytes()[2..]);
+        buf.extend_from_slice(&self.fudge.to_be_bytes());
+        buf.extend_from_slice(&(self.mac.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&self.mac);
+        buf.extend_from_slice(&self.original_id.to_be_bytes());
+        buf.extend_from_slice(&self.error.to_be_bytes());
+        buf.extend_from_slice(&(self.other.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&self.other);
+        let rdlen = (buf.len() - rdlen_pos - 2) as u16;
+        buf[rdlen_pos..rdlen_pos + 2].copy_from_slice(&rdlen.to_be_bytes());
+    }
+}
+
+//only the first request and response cover the whole tsig variables
+fn compute_mac(
+    key: &TsigKey,
+    prior_mac: Option<&Vec<u8>>,
+    message: &[u8],
+    tsig: &TsigRecord,
+    all_variables: bool,
+) -> Vec<u8> {
+    let algorithm = match key.algorithm {
+        TsigAlgorithm::HmacSha256 => hmac::HMAC_SHA256,
+        TsigAlgorithm::HmacSha512 => hmac::HMAC_SHA512,
+    };
+    let mut context = hmac::Context::with_key(&hmac::Key::new(algorithm, &key.secret));
+    if let Some(mac) = prior_mac {
+        context.update(&(mac.len() as u16).to_be_bytes());
+        context.update(mac);
+    }
+    context.update(message);
+    let mut variables = Vec::new();
+    if all_variables {
+        write_name(&mut variables, &tsig.key_name);
+        variables.extend_from_slice(&CLASS_ANY.to_be_bytes());
+        variables.extend_from_slice(&0u32.to_be_bytes());
+        write_name(&mut variables, &tsig.algorithm);
+    }
+    variables.extend_from_slice(&tsig.time_signed.to_be_bytes()[2..]);
+    variables.extend_from_slice(&tsig.fudge.to_be_bytes());
+    if all_variables {
+        variables.extend_from_slice(&tsig.error.to_be_bytes());
+        variables.extend_from_slice(&(tsig.other.len() as u16).to_be_bytes());
+        variables.extend_from_slice(&tsig.other);
+    }
+    context.update(&variables);
+    context.sign().as_ref().to_vec()
+}
+
+//tsig has to be the last rr in additional section, the returned wire
+//has the original id and doesn't count the tsig rr
+fn split_tsig(wire: &[u8]) -> Result<Option<(Vec<u8>, TsigRecord)>> {
+    if wire.len() < HEADER_LEN {
+        return Err(invalid_tsig("message is too short"));
+    }
+    let count = |offset: usize| read_u16(&wire[offset..offset + 2]) as usize;
+    let arcount = count(ARCOUNT_OFFSET);
+    if arcount == 0 {
+        return Ok(None);
+    }
+
+    let mut pos = HEADER_LEN;
+    for _ in 0..count(4) {
+        pos = read_name_text(wire, pos)?.1 + 4;
+    }
+    let rr_count = count(6) + count(8) + arcount;
+    for i in 0..rr_count {
+        let start = pos;
+        let (owner, rdata_pos) = read_name_text(wire, pos)?;
+        if rdata_pos + 10 > wire.len() {
+            return Err(invalid_tsig("rr is truncated"));
+        }
+        let typ = read_u16(&wire[rdata_pos..rdata_pos + 2]);
+        let rdlen = read_u16(&wire[rdata_pos + 8..rdata_pos + 10]) as usize;
+        pos = rdata_pos + 10 + rdlen;
+        if pos > wire.len() {
+            return Err(invalid_tsig("rr is truncated"));
+        }
+        if i + 1 == rr_count && typ == TSIG_TYPE {
+            let tsig = TsigRecord::from_wire(wire, owner, rdata_pos + 10, pos)?;
+            let mut message = wire[..start].to_vec();
+            message[..2].copy_from_slice(&tsig.original_id.to_be_bytes());
+            message[ARCOUNT_OFFSET..ARCOUNT_OFFSET + 2]
+                .copy_from_slice(&((arcount - 1) as u16).to_be_bytes());
+            return Ok(Some((message, tsig)));
+        }
+    }
+    Ok(None)
+}
+
+//return the lower case name in text and the position after it
+fn read_name_text(wire: &[u8], pos: usize) -> Result<(String, usize)> {
+    read_name(wire, pos)
+        .map(|(name, end)| (wire_to_string(&name), end))
+        .ok_or_else(|| invalid_tsig("name is invalid"))
+}
+
+fn name_wire_len(name: &str) -> usize {
+    let mut buf = Vec::new();
+    write_name(&mut buf, name);
+    buf.len()
+}
+
+fn read_bytes<'a>(wire: &'a [u8], pos: &mut usize, end: usize, len: usize) -> Result<&'a [u8]> {
+    if *pos + len > end {
+        return Err(invalid_tsig("tsig rdata is too short"));
+    }
+    *pos += len;
+    Ok(&wire[*pos - len..*pos])
+}
+
+fn read_u16(buf: &[u8]) -> u16 {
+    u16::from_be_bytes([buf[0], buf[1]])
+}
+
+fn is_in_time(tsig: &TsigRecord) -> bool {
+    let now = now();
+    let diff = if now > tsig.time_signed {
+        now - tsig.time_signed
+    } else {
+        tsig.time_signed - now
+    };
+    diff <= u64::from(tsig.fudge)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn invalid_tsig(reason: &str) -> failure::Error {
+    VgError::InvalidTsig(reason.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r53::{Message, MessageBuilder, MessageRender, Name, RRType};
+
+    fn key_conf(name: &str, algorithm: &str) -> TsigKeyConfig {
+        TsigKeyConfig {
+            name: name.to_string(),
+            algorithm: algorithm.to_string(),
+            secret: base64::encode(b"vanguard2 tsig secret"),
+        }
+    }
+
+    fn render(message: &Message) -> Vec<u8> {
+        let mut render = MessageRender::new();
+        message.rend(&mut render);
+        render.take_data()
+    }
+
+    #[test]
+    fn test_load_keys() {
+        assert!(KeyRing::new(&[key_conf("key", "hmac-md5")]).is_err());
+        let mut conf = key_conf("key", "hmac-sha256");
+        conf.secret = "%%".to_string();
+        assert!(KeyRing::new(&[conf]).is_err());
+
+        let keyring = KeyRing::new(&[
+            key_conf("Key.Example", "hmac-sha256"),
+            key_conf("key2.example.", "HMAC-SHA512."),
+        ])
+        .unwrap();
+        assert!(keyring.has_key("key.example."));
+        let key = keyring.get_key("KEY2.example").unwrap();
+        assert_eq!(key.get_name(), "key2.example.");
+        assert_eq!(key.algorithm, TsigAlgorithm::HmacSha512);
+        keyring.reload(&[]).unwrap();
+        assert!(!keyring.has_key("key.example"));
+    }
+
+    #[test]
+    fn test_verify_request() {
+        let keyring = KeyRing::new(&[key_conf("key.example", "hmac-sha256")]).unwrap();
+        let query = Message::with_query(Name::new("example.org").unwrap(), RRType::AXFR);
+        let wire = render(&query);
+        assert!(keyring.verify_request(&wire).unwrap().is_none());
+
+        let mut client = TsigSigner::new(keyring.get_key("key.example").unwrap());
+        let mut signed = wire.clone();
+        client.sign(&mut signed);
+        let (message, context) = keyring.verify_request(&signed).unwrap().unwrap();
+        assert_eq!(message, wire);
+        assert!(context.is_verified());
+        assert_eq!(context.get_key_name(), "key.example.");
+
+        //responses are chained by the mac of the previous one
+        let mut server = context.signer();
+        let mut response = query.clone();
+        MessageBuilder::new(&mut response).make_response().done();
+        let response = render(&response);
+        for _ in 0..3 {
+            let mut signed = response.clone();
+            server.sign(&mut signed);
+            assert_eq!(client.verify(&signed).unwrap(), response);
+        }
+        assert!(client.verify(&response).is_ok());
+        assert!(!client.is_verified());
+        let mut signed = response.clone();
+        server.sign(&mut signed);
+        assert!(client.verify(&signed).is_err());
+
+        let mut tampered = wire.clone();
+        TsigSigner::new(keyring.get_key("key.example").unwrap()).sign(&mut tampered);
+        tampered[2] ^= 0x01;
+        let (_, context) = keyring.verify_request(&tampered).unwrap().unwrap();
+        assert_eq!(context.get_error(), TsigError::BadSig);
+
+        let other = KeyRing::new(&[key_conf("key2.example", "hmac-sha256")]).unwrap();
+        let mut signed = wire.clone();
+        TsigSigner::new(other.get_key("key2.example").unwrap()).sign(&mut signed);
+        let (_, context) = keyring.verify_request(&signed).unwrap().unwrap();
+        assert_eq!(context.get_error(), TsigError::BadKey);
+        //response with bad key isn't signed
+        let mut response = wire.clone();
+        context.signer().sign(&mut response);
+        let (_, tsig) = split_tsig(&response).unwrap().unwrap();
+        assert!(tsig.mac.is_empty());
+        assert_eq!(tsig.error, TsigError::BadKey as u16);
+    }
+}
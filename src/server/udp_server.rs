@@ -1,6 +1,12 @@
 use std::{io, net::SocketAddr, sync::Arc, time::Duration};
 
-use super::handler::{Query, QueryHandler};
+use super::{
+    edns::{self, EdnsCheckResult},
+    handler::{Query, QueryHandler},
+    shutdown::ShutdownListener,
+    socket::bind_udp,
+    tsig::KeyRing,
+};
 use futures::{
     future::ok,
     stream::{Fuse, Stream},
@@ -8,48 +14,86 @@ use futures::{
     Async, Future, Poll,
 };
 use prometheus::{IntCounter, IntGauge};
-use r53::{Message, MessageRender};
+use r53::{header_flag::HeaderFlag, message::SectionType, Message, MessageBuilder, MessageRender};
 use tokio::{executor::spawn, net::UdpSocket};
 use tokio_timer::Interval;
 
 const QUERY_BUFFER_LEN: usize = 1024;
-const MAX_QUERY_MESSAGE_LEN: usize = 1024;
+const MAX_QUERY_MESSAGE_LEN: usize = 65535;
 
 lazy_static! {
     static ref QPS_UDP_INT_GAUGE: IntGauge =
         register_int_gauge!("pqs", "query per second").unwrap();
     static ref QC_UDP_INT_COUNT: IntCounter =
         register_int_counter!("qc", "query count until now").unwrap();
+    static ref TC_UDP_INT_COUNT: IntCounter =
+        register_int_counter!("tc", "truncated response count until now").unwrap();
+}
+
+pub struct UdpResponse {
+    query: Query,
+    max_len: usize,
 }
 
 pub struct UdpServer<S: QueryHandler> {
     socket: UdpSocket,
-    sender: Sender<Query>,
+    //sender is dropped after shutdown, response channel will be closed
+    //once all the pending queries are answered
+    sender: Option<Sender<UdpResponse>>,
     handler: Arc<S>,
-    response_ch: Fuse<Receiver<Query>>,
+    response_ch: Fuse<Receiver<UdpResponse>>,
+    max_udp_payload: u16,
+    recv_buf: Vec<u8>,
+    keyring: KeyRing,
+    shutdown: ShutdownListener,
 }
 
 impl<S: QueryHandler> UdpServer<S> {
-    pub fn new(addr: SocketAddr, handler: Arc<S>) -> Self {
-        let socket = UdpSocket::bind(&addr).unwrap();
+    pub fn new(
+        addr: SocketAddr,
+        handler: Arc<S>,
+        max_udp_payload: u16,
+        keyring: KeyRing,
+        shutdown: ShutdownListener,
+    ) -> io::Result<Self> {
+        let socket = bind_udp(&addr)?;
         let (sender, response_ch) = channel(QUERY_BUFFER_LEN);
-        UdpServer {
+        Ok(UdpServer {
             socket,
-            sender,
+            sender: Some(sender),
             handler,
             response_ch: response_ch.fuse(),
-        }
+            max_udp_payload: max_udp_payload.max(edns::MIN_UDP_PAYLOAD),
+            recv_buf: vec![0; MAX_QUERY_MESSAGE_LEN],
+            keyring,
+            shutdown,
+        })
     }
 
-    fn send_all_response(&mut self, render: &mut MessageRender) -> Poll<(), io::Error> {
+    //return true if response channel is closed
+    fn send_all_response(&mut self, render: &mut MessageRender) -> Poll<bool, io::Error> {
         loop {
             match self.response_ch.poll() {
-                Ok(Async::Ready(Some(query))) => {
-                    query.message.rend(render);
-                    try_ready!(self.socket.poll_send_to(render.data(), &query.client));
+                Ok(Async::Ready(Some(mut response))) => {
+                    //space of tsig rr is reserved before it's signed
+                    let tsig_len = response.query.tsig.as_ref().map_or(0, |tsig| tsig.rr_len());
+                    let max_len = response.max_len.saturating_sub(tsig_len);
+                    if fit_response(&mut response.query.message, render, max_len) {
+                        TC_UDP_INT_COUNT.inc();
+                    }
+                    if let Some(ref tsig) = response.query.tsig {
+                        let mut wire = render.take_data();
+                        tsig.signer().sign(&mut wire);
+                        try_ready!(self.socket.poll_send_to(&wire, &response.query.client));
+                    } else {
+                        try_ready!(self
+                            .socket
+                            .poll_send_to(render.data(), &response.query.client));
+                    }
                     render.clear();
                 }
-                Ok(Async::Ready(None)) | Ok(Async::NotReady) => return Ok(Async::Ready(())),
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(true)),
+                Ok(Async::NotReady) => return Ok(Async::Ready(false)),
                 Err(_) => panic!("get error form channel"),
             }
         }
@@ -61,24 +105,58 @@ impl<S: QueryHandler> Future for UdpServer<S> {
     type Error = io::Error;
 
     fn poll(&mut self) -> Poll<(), Self::Error> {
-        let mut buf = [0u8; MAX_QUERY_MESSAGE_LEN];
         let mut render = MessageRender::new();
         loop {
-            try_ready!(self.send_all_response(&mut render));
-            let (size, src) = try_ready!(self.socket.poll_recv_from(&mut buf));
-            let query = Message::from_wire(&buf[..size]);
+            let is_closed = try_ready!(self.send_all_response(&mut render));
+            if self.sender.is_none() {
+                if is_closed {
+                    return Ok(Async::Ready(()));
+                } else {
+                    return Ok(Async::NotReady);
+                }
+            }
+
+            if self.shutdown.is_shutdown() {
+                self.sender = None;
+                continue;
+            }
+
+            let (size, src) = try_ready!(self.socket.poll_recv_from(&mut self.recv_buf));
+            let query = Query::from_wire(&self.recv_buf[..size], src, &self.keyring);
             if query.is_err() {
                 continue;
             }
             QC_UDP_INT_COUNT.inc();
 
-            let query = Query::new(query.unwrap(), src);
-            let mut sender = UdpStreamSender::new(self.sender.clone());
+            let mut query = query.unwrap();
+            query.over_udp = true;
+            let mut sender = UdpStreamSender::new(self.sender.as_ref().unwrap().clone());
+            let client_edns = match edns::check_query_edns(&query.message) {
+                EdnsCheckResult::NoEdns => None,
+                EdnsCheckResult::Edns(client_edns) => Some(client_edns),
+                EdnsCheckResult::BadVersion => {
+                    edns::make_badvers_response(&mut query.message, self.max_udp_payload);
+                    query.done = true;
+                    let max_len = edns::max_response_len(None, self.max_udp_payload);
+                    if let Err(e) = sender.send_response(query, max_len) {
+                        println!("send response get err {}", e);
+                    }
+                    continue;
+                }
+            };
+
+            let max_udp_payload = self.max_udp_payload;
             spawn(
                 self.handler
                     .handle_query(query)
-                    .map(move |response| {
-                        if let Err(e) = sender.send_response(response) {
+                    .map(move |mut response| {
+                        edns::set_response_edns(
+                            &mut response.message,
+                            client_edns.as_ref(),
+                            max_udp_payload,
+                        );
+                        let max_len = edns::max_response_len(client_edns.as_ref(), max_udp_payload);
+                        if let Err(e) = sender.send_response(response, max_len) {
                             println!("send response get err {}", e);
                         }
                     })
@@ -91,20 +169,76 @@ impl<S: QueryHandler> Future for UdpServer<S> {
 }
 
 #[derive(Clone)]
-pub struct UdpStreamSender(Sender<Query>);
+pub struct UdpStreamSender(Sender<UdpResponse>);
 
 impl UdpStreamSender {
-    fn new(sender: Sender<Query>) -> Self {
+    fn new(sender: Sender<UdpResponse>) -> Self {
         UdpStreamSender(sender)
     }
 
-    fn send_response(&mut self, response: Query) -> io::Result<()> {
+    fn send_response(&mut self, query: Query, max_len: usize) -> io::Result<()> {
         self.0
-            .try_send(response)
+            .try_send(UdpResponse { query, max_len })
             .map_err(|_| io::Error::new(io::ErrorKind::Other, "full"))
     }
 }
 
+//rend the response into render and make sure it fits into max_len,
+//return true if the response is truncated and tc flag is set
+//
+//according to rfc2181, additional section is dropped first and
+//tc isn't necessary, if answer and authority still don't fit, whole
+//rrsets are removed from the tail and tc is set to let client retry
+//with tcp
+fn fit_response(response: &mut Message, render: &mut MessageRender, max_len: usize) -> bool {
+    if rend_within(response, render, max_len) {
+        return false;
+    }
+
+    if response.take_section(SectionType::Additional).is_some() {
+        MessageBuilder::new(response).done();
+        if rend_within(response, render, max_len) {
+            return false;
+        }
+    }
+
+    for &section in &[SectionType::Authority, SectionType::Answer] {
+        let mut rrsets = match response.take_section(section) {
+            Some(rrsets) => rrsets,
+            None => continue,
+        };
+        while rrsets.pop().is_some() {
+            let mut builder = MessageBuilder::new(response);
+            for rrset in &rrsets {
+                if section == SectionType::Answer {
+                    builder.add_answer(rrset.clone());
+                } else {
+                    builder.add_auth(rrset.clone());
+                }
+            }
+            builder.set_flag(HeaderFlag::Truncation).done();
+            if rend_within(response, render, max_len) {
+                return true;
+            }
+            response.take_section(section);
+        }
+    }
+
+    //only header, question and opt are left
+    MessageBuilder::new(response)
+        .set_flag(HeaderFlag::Truncation)
+        .done();
+    render.clear();
+    response.rend(render);
+    true
+}
+
+fn rend_within(response: &Message, render: &mut MessageRender, max_len: usize) -> bool {
+    render.clear();
+    response.rend(render);
+    render.data().len() <= max_len
+}
+
 pub fn start_qps_calculate() -> impl Future<Item = (), Error = ()> {
     let interval = Interval::new_interval(Duration::new(1, 0));
     let mut last_qc = 0;
@@ -117,3 +251,53 @@ pub fn start_qps_calculate() -> impl Future<Item = (), Error = ()> {
         })
         .map_err(|e| println!("timer get err {:?}", e))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r53::{Name, RRType, RRset};
+    use std::str::FromStr;
+
+    fn build_response(answer_count: usize) -> Message {
+        let mut msg = Message::with_query(Name::new("example.org.").unwrap(), RRType::TXT);
+        {
+            let mut builder = MessageBuilder::new(&mut msg);
+            builder.make_response();
+            for i in 0..answer_count {
+                builder.add_answer(
+                    RRset::from_str(
+                        format!("t{}.example.org. 3600 IN TXT {}", i, "x".repeat(212)).as_ref(),
+                    )
+                    .unwrap(),
+                );
+            }
+            builder
+                .add_auth(RRset::from_str("example.org. 3600 IN NS ns.example.org.").unwrap())
+                .add_additional(RRset::from_str("ns.example.org. 3600 IN A 192.0.2.1").unwrap())
+                .done();
+        }
+        msg
+    }
+
+    #[test]
+    fn test_fit_response() {
+        let mut render = MessageRender::new();
+        let mut response = build_response(1);
+        assert!(!fit_response(&mut response, &mut render, 512));
+        assert!(!response.header.is_flag_set(HeaderFlag::Truncation));
+        assert_eq!(response.header.ar_count, 1);
+
+        let mut response = build_response(2);
+        assert!(!fit_response(&mut response, &mut render, 512));
+        assert!(!response.header.is_flag_set(HeaderFlag::Truncation));
+        assert_eq!(response.header.an_count, 2);
+        assert_eq!(response.header.ar_count, 0);
+
+        let mut response = build_response(5);
+        assert!(fit_response(&mut response, &mut render, 512));
+        assert!(response.header.is_flag_set(HeaderFlag::Truncation));
+        assert_eq!(response.header.an_count, 2);
+        assert_eq!(response.header.ns_count, 0);
+        assert!(render.data().len() <= 512);
+    }
+}
@@ -0,0 +1,89 @@
+use r53::Name;
+
+const MAX_POINTER_COUNT: usize = 16;
+
+//names in dnssec and tsig are compared in canonical form, which is
+//uncompressed with ascii letters lowercased, rfc4034 section 6.2. Label
+//length is at most 63, so it isn't changed by lowercasing
+pub fn name_to_wire(name: &Name) -> Vec<u8> {
+    name.raw_data().to_ascii_lowercase()
+}
+
+//return the name in canonical form and the position after it
+pub fn read_name(wire: &[u8], mut pos: usize) -> Option<(Vec<u8>, usize)> {
+    let mut name = Vec::new();
+    let mut end = None;
+    let mut pointer_count = 0;
+    loop {
+        let len = *wire.get(pos)? as usize;
+        if len == 0 {
+            name.push(0);
+            return Some((name, end.unwrap_or(pos + 1)));
+        } else if len & 0xc0 == 0xc0 {
+            let low = *wire.get(pos + 1)? as usize;
+            pointer_count += 1;
+            if pointer_count > MAX_POINTER_COUNT {
+                return None;
+            }
+            if end.is_none() {
+                end = Some(pos + 2);
+            }
+            pos = (len & 0x3f) << 8 | low;
+        } else {
+            let label = wire.get(pos..pos + 1 + len)?;
+            name.extend_from_slice(&label.to_ascii_lowercase());
+            pos += 1 + len;
+        }
+    }
+}
+
+//absolute name in text, which ends with dot
+pub fn wire_to_string(wire: &[u8]) -> String {
+    let mut name = String::new();
+    let mut pos = 0;
+    while let Some(&len) = wire.get(pos) {
+        if len == 0 {
+            break;
+        }
+        let label = wire.get(pos + 1..pos + 1 + len as usize).unwrap_or(&[]);
+        name.push_str(&String::from_utf8_lossy(label));
+        name.push('.');
+        pos += 1 + len as usize;
+    }
+    if name.is_empty() {
+        name.push('.');
+    }
+    name
+}
+
+pub fn write_name(buf: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_name() {
+        //www.Example.org. followed by a pointer to example.org
+        let mut wire = b"\x03www\x07Example\x03org\x00".to_vec();
+        wire.extend_from_slice(&[3, b'W', b'\xc9', b'w', 0xc0, 4]);
+        let (name, end) = read_name(&wire, 0).unwrap();
+        assert_eq!(name, b"\x03www\x07example\x03org\x00".to_vec());
+        assert_eq!(end, 17);
+        let (name, end) = read_name(&wire, 17).unwrap();
+        //only ascii letters are lowercased
+        assert_eq!(name, b"\x03w\xc9w\x07example\x03org\x00".to_vec());
+        assert_eq!(end, wire.len());
+        assert_eq!(wire_to_string(&name[4..]), "example.org.");
+
+        //pointer loop
+        assert!(read_name(&[0xc0, 0], 0).is_none());
+        assert!(read_name(&[3, b'w'], 0).is_none());
+    }
+}